@@ -0,0 +1,264 @@
+//! Encoding and decoding throughput benchmarks.
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use unbin::{BytesReader, BytesWriter, Decoder, Encoder, IntEncoding};
+
+/// A struct touching most kinds of value, mirroring the fixture used by the
+/// unit tests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct MyStruct<'a> {
+    bool_field: bool,
+    i8_field: i8,
+    i16_field: i16,
+    i32_field: i32,
+    i64_field: i64,
+    i128_field: i128,
+    u8_field: u8,
+    u16_field: u16,
+    u32_field: u32,
+    u64_field: u64,
+    u128_field: u128,
+    f32_field: f32,
+    f64_field: f64,
+    char_field: char,
+    str_field: &'a str,
+    string_field: String,
+    option_none_field: Option<u8>,
+    option_some_field: Option<u8>,
+    unit_field: (),
+    seq_field: Vec<u8>,
+    tuple_field: ((), bool, u8),
+    map_field: HashMap<u8, u8>,
+    enum_field: MyEnum,
+}
+
+/// An enum with every kind of variant.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum MyEnum {
+    Unit,
+    Newtype(u8),
+    Tuple((), bool, u8),
+    Struct { a: (), b: bool, c: u8 },
+}
+
+/// Returns an instance of the struct fixture.
+fn my_struct() -> MyStruct<'static> {
+    MyStruct {
+        bool_field: true,
+        i8_field: i8::MIN,
+        i16_field: i16::MIN,
+        i32_field: i32::MIN,
+        i64_field: i64::MIN,
+        i128_field: i128::MIN,
+        u8_field: u8::MAX,
+        u16_field: u16::MAX,
+        u32_field: u32::MAX,
+        u64_field: u64::MAX,
+        u128_field: u128::MAX,
+        f32_field: 6.25,
+        f64_field: 3.125,
+        char_field: 'A',
+        str_field: "my borrowed string",
+        string_field: "my owned string".to_owned(),
+        option_none_field: None,
+        option_some_field: Some(4),
+        unit_field: (),
+        seq_field: vec![7, 8, 9, 10, 11],
+        tuple_field: ((), false, 12),
+        map_field: [(15, 16), (17, 18), (19, 20)].into_iter().collect(),
+        enum_field: MyEnum::Struct {
+            a: (),
+            b: false,
+            c: 22,
+        },
+    }
+}
+
+/// Benchmarks the struct fixture.
+fn bench_my_struct(c: &mut Criterion) {
+    let value = my_struct();
+    let serialized = unbin::serialize(&value).unwrap();
+    let mut group = c.benchmark_group("my_struct");
+    group.throughput(Throughput::Bytes(serialized.len() as u64));
+
+    group.bench_function("encode", |b| {
+        b.iter(|| unbin::serialize(black_box(&value)).unwrap())
+    });
+    group.bench_function("encode_reserved", |b| {
+        b.iter(|| unbin::serialize_reserved(black_box(&value)).unwrap())
+    });
+    group.bench_function("decode", |b| {
+        b.iter(|| unbin::deserialize::<MyStruct>(black_box(&serialized)).unwrap())
+    });
+
+    group.finish();
+}
+
+/// Benchmarks a large sequence of integers, under each integer encoding.
+fn bench_vec_u64(c: &mut Criterion) {
+    let value: Vec<u64> = (0..100_000).map(|i| i * 7919).collect();
+    let mut group = c.benchmark_group("vec_u64");
+
+    for int_encoding in [IntEncoding::Fixed, IntEncoding::Varint] {
+        let mut writer = BytesWriter::new();
+        let mut encoder = Encoder::new(&mut writer).with_int_encoding(int_encoding);
+        value.serialize(&mut encoder).unwrap();
+        let serialized = writer.into_inner();
+        group.throughput(Throughput::Bytes(serialized.len() as u64));
+        let name = format!("{int_encoding:?}");
+
+        group.bench_function(BenchmarkId::new("encode", &name), |b| {
+            b.iter(|| {
+                let mut writer = BytesWriter::new();
+                let mut encoder = Encoder::new(&mut writer).with_int_encoding(int_encoding);
+                black_box(&value).serialize(&mut encoder).unwrap();
+                writer.into_inner()
+            })
+        });
+        group.bench_function(BenchmarkId::new("decode", &name), |b| {
+            b.iter(|| {
+                let mut reader = BytesReader::new(black_box(&serialized));
+                let mut decoder = Decoder::new(&mut reader).with_int_encoding(int_encoding);
+                Vec::<u64>::deserialize(&mut decoder).unwrap()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+/// Benchmarks large sequences of single-byte values, which are written and
+/// read one byte at a time.
+fn bench_single_byte(c: &mut Criterion) {
+    let bools: Vec<bool> = (0..100_000).map(|i| i % 3 == 0).collect();
+    let bytes: Vec<u8> = (0..100_000).map(|i| i as u8).collect();
+    let mut group = c.benchmark_group("single_byte");
+
+    let serialized = unbin::serialize(&bools).unwrap();
+    group.throughput(Throughput::Bytes(serialized.len() as u64));
+    group.bench_function(BenchmarkId::new("encode", "bool"), |b| {
+        b.iter(|| unbin::serialize(black_box(&bools)).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("decode", "bool"), |b| {
+        b.iter(|| unbin::deserialize::<Vec<bool>>(black_box(&serialized)).unwrap())
+    });
+
+    let serialized = unbin::serialize(&bytes).unwrap();
+    group.throughput(Throughput::Bytes(serialized.len() as u64));
+    group.bench_function(BenchmarkId::new("encode", "u8"), |b| {
+        b.iter(|| unbin::serialize(black_box(&bytes)).unwrap())
+    });
+    group.bench_function(BenchmarkId::new("decode", "u8"), |b| {
+        b.iter(|| unbin::deserialize::<Vec<u8>>(black_box(&serialized)).unwrap())
+    });
+
+    group.finish();
+}
+
+/// Benchmarks a large map.
+fn bench_hash_map(c: &mut Criterion) {
+    let value: HashMap<u32, String> = (0..10_000).map(|i| (i, format!("value {i}"))).collect();
+    let serialized = unbin::serialize(&value).unwrap();
+    let mut group = c.benchmark_group("hash_map");
+    group.throughput(Throughput::Bytes(serialized.len() as u64));
+
+    group.bench_function("encode", |b| {
+        b.iter(|| unbin::serialize(black_box(&value)).unwrap())
+    });
+    group.bench_function("decode", |b| {
+        b.iter(|| unbin::deserialize::<HashMap<u32, String>>(black_box(&serialized)).unwrap())
+    });
+
+    group.finish();
+}
+
+/// Benchmarks a large string, decoded both borrowed and owned.
+fn bench_string(c: &mut Criterion) {
+    let value = "unbin ".repeat(1024 * 1024 / 6);
+    let serialized = unbin::serialize(&value).unwrap();
+    let mut group = c.benchmark_group("string");
+    group.throughput(Throughput::Bytes(serialized.len() as u64));
+
+    group.bench_function("encode", |b| {
+        b.iter(|| unbin::serialize(black_box(&value)).unwrap())
+    });
+    group.bench_function("decode_borrowed", |b| {
+        b.iter(|| {
+            unbin::deserialize::<&str>(black_box(&serialized))
+                .unwrap()
+                .len()
+        })
+    });
+    group.bench_function("decode_owned", |b| {
+        b.iter(|| unbin::deserialize::<String>(black_box(&serialized)).unwrap())
+    });
+
+    group.finish();
+}
+
+/// Compares reading a large byte buffer from a byte array, which zero-fills
+/// the buffer before copying into it, with reading it from a stream, which
+/// reads straight into spare capacity.
+fn bench_large_read(c: &mut Criterion) {
+    let value = "unbin ".repeat(10 * 1024 * 1024 / 6);
+    let serialized = unbin::serialize(&value).unwrap();
+    let mut group = c.benchmark_group("large_read");
+    group.throughput(Throughput::Bytes(serialized.len() as u64));
+
+    group.bench_function("bytes_reader", |b| {
+        b.iter(|| {
+            let mut reader = BytesReader::new(black_box(&serialized));
+            let mut decoder = Decoder::new(&mut reader);
+            String::deserialize(&mut decoder).unwrap()
+        })
+    });
+    group.bench_function("stream", |b| {
+        b.iter(|| {
+            let mut reader = black_box(serialized.as_slice());
+            let mut decoder = Decoder::new(&mut reader);
+            String::deserialize(&mut decoder).unwrap()
+        })
+    });
+
+    group.finish();
+}
+
+/// Compares allocating a fresh output buffer for every value with reusing a
+/// single buffer.
+fn bench_writer_reuse(c: &mut Criterion) {
+    let value = my_struct();
+    let mut group = c.benchmark_group("writer_reuse");
+
+    group.bench_function("fresh", |b| {
+        b.iter(|| {
+            let mut writer = BytesWriter::new();
+            unbin::serialize_into(black_box(&value), &mut writer).unwrap();
+            writer.into_inner()
+        })
+    });
+    group.bench_function("reused", |b| {
+        let mut buffer = Vec::new();
+
+        b.iter(|| {
+            buffer.clear();
+            unbin::serialize_into(black_box(&value), &mut buffer).unwrap();
+            buffer.len()
+        })
+    });
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_my_struct,
+    bench_vec_u64,
+    bench_single_byte,
+    bench_hash_map,
+    bench_string,
+    bench_large_read,
+    bench_writer_reuse
+);
+criterion_main!(benches);
@@ -0,0 +1,51 @@
+//! Deterministic serialization of sets.
+//!
+//! Sets are serialized as sequences, in the order they iterate over their
+//! elements. For a `HashSet`, that order can vary from one run to the next,
+//! and so can its encoding, even between equal sets. This module sorts the
+//! elements by their encoded bytes first, so that equal sets always produce
+//! equal bytes. The output is an ordinary sequence, so it decodes as any set
+//! or sequence type.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use std::collections::HashSet;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Permissions {
+//!     #[serde(with = "unbin::canonical_set")]
+//!     scopes: HashSet<String>,
+//! }
+//! ```
+
+use serde::ser::Error;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// Serializes a set as a sequence of its elements, sorted by their encoded
+/// bytes.
+pub fn serialize<'a, C, T, S>(set: &'a C, serializer: S) -> Result<S::Ok, S::Error>
+where
+    &'a C: IntoIterator<Item = &'a T>,
+    T: Serialize + 'a,
+    S: Serializer,
+{
+    // the sort keys are encoded apart from any table of shared values, which
+    // would otherwise record values the output never writes in full
+    let mut elements = crate::shared::without_serialize_table(|| {
+        set.into_iter()
+            .map(|element| crate::serialize(element).map(|bytes| (bytes, element)))
+            .collect::<crate::Result<Vec<_>>>()
+    })
+    .map_err(S::Error::custom)?;
+    elements.sort_unstable_by(|(a, _), (b, _)| a.cmp(b));
+    serializer.collect_seq(elements.iter().map(|(_, element)| element))
+}
+
+/// Deserializes a set from a sequence of its elements, in any order.
+pub fn deserialize<'de, C, D>(deserializer: D) -> Result<C, D::Error>
+where
+    C: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    C::deserialize(deserializer)
+}
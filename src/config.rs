@@ -0,0 +1,290 @@
+//! Encoding and decoding configuration.
+
+use crate::length_codec::{LengthCodec, PrefixedLength, VarintLength};
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+
+/// How the lengths of strings, byte arrays, sequences and maps are encoded.
+/// The length of a `char` is unaffected, and always takes a single byte.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum LengthEncoding {
+    /// A single byte giving the number of length bytes that follow, then the
+    /// length in big-endian order with leading zero bytes omitted.
+    #[default]
+    Prefixed,
+    /// An unsigned LEB128 varint: seven bits per byte, least significant group
+    /// first, with the high bit set on every byte but the last.
+    Varint,
+    /// Whatever the given codec writes. Custom encodings are compared by their
+    /// codec reference as a whole, both its address and its type, since
+    /// distinct zero-sized codecs can share an address.
+    Custom(&'static dyn LengthCodec),
+}
+
+impl LengthEncoding {
+    /// Returns the codec that writes and reads lengths in this encoding.
+    pub(crate) fn codec(self) -> &'static dyn LengthCodec {
+        match self {
+            Self::Prefixed => &PrefixedLength,
+            Self::Varint => &VarintLength,
+            Self::Custom(codec) => codec,
+        }
+    }
+
+    /// Returns a key telling encodings apart, with custom encodings told
+    /// apart by their codec reference.
+    fn key(&self) -> (u8, Option<*const dyn LengthCodec>) {
+        match self {
+            Self::Prefixed => (0, None),
+            Self::Varint => (1, None),
+            Self::Custom(codec) => (2, Some(*codec as *const dyn LengthCodec)),
+        }
+    }
+}
+
+impl PartialEq for LengthEncoding {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Custom(codec), Self::Custom(other_codec)) => std::ptr::eq(*codec, *other_codec),
+            _ => self.key().0 == other.key().0,
+        }
+    }
+}
+
+impl Eq for LengthEncoding {}
+
+impl PartialOrd for LengthEncoding {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for LengthEncoding {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl Hash for LengthEncoding {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.key().hash(state);
+    }
+}
+
+/// How integers wider than a byte are encoded. `u8` and `i8` are unaffected,
+/// and always take a single byte.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum IntEncoding {
+    /// The integer's bytes in big-endian order, always taking the full width
+    /// of the type.
+    #[default]
+    Fixed,
+    /// An unsigned LEB128 varint, as for [`LengthEncoding::Varint`], so that
+    /// small values take fewer bytes. Signed integers are first zigzag
+    /// encoded, so that values of small magnitude are small whatever their
+    /// sign. A `u128` takes up to 19 bytes.
+    Varint,
+}
+
+/// How `char` values are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum CharEncoding {
+    /// A single length byte, then the character's one to four UTF-8 bytes.
+    #[default]
+    Utf8,
+    /// The character's code point as a big-endian `u32`, always taking four
+    /// bytes. This trades compactness for a fixed layout.
+    Fixed,
+}
+
+/// How `f32` and `f64` values are encoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FloatEncoding {
+    /// The value's IEEE 754 bit pattern in big-endian order, always taking the
+    /// full width of the type.
+    #[default]
+    Fixed,
+    /// A width byte, then the value in the narrowest form that holds it
+    /// exactly: `0` for an integer fitting in an `i8`, taking one more byte,
+    /// `1` for an `f32`, taking four, and `2` for an `f64`, taking eight. An
+    /// `f64` that is really a small integer or an `f32` is written in fewer
+    /// bytes, at the cost of an extra byte for any other value. The bit
+    /// pattern is always preserved exactly, including the sign of zero and NaN
+    /// payloads, which are always written at full width.
+    Compact,
+}
+
+/// Options controlling how values are encoded and decoded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Config {
+    /// Whether to reject encodings that are valid but not in their minimal,
+    /// canonical form.
+    pub canonical: bool,
+    /// How the lengths of variable-size values are encoded.
+    pub length_encoding: LengthEncoding,
+    /// How integers wider than a byte are encoded.
+    pub int_encoding: IntEncoding,
+    /// How `char` values are encoded.
+    pub char_encoding: CharEncoding,
+    /// How `f32` and `f64` values are encoded.
+    pub float_encoding: FloatEncoding,
+    /// Whether a sentinel byte follows every struct and sequence, and the
+    /// number of fields follows the index of every tuple and struct variant,
+    /// to detect when the encoder and decoder fall out of step.
+    pub framed: bool,
+    /// Whether the decoder treats input ending exactly between two struct
+    /// fields as the end of the struct, leaving the remaining fields missing.
+    pub tolerant: bool,
+    /// Whether the encoder and decoder reject NaN and infinite floating point
+    /// values.
+    pub finite_floats: bool,
+    /// The maximum number of bytes the decoder may read in total, if any.
+    pub max_total_bytes: Option<usize>,
+    /// The maximum number of elements encoded in zero bytes that the decoder
+    /// accepts in a single sequence or map, or the default limit if unset.
+    pub max_empty_elements: Option<usize>,
+    /// Whether the decoder passes encoded sequence and map lengths on to
+    /// visitors as size hints as they are, for them to preallocate.
+    pub trust_length_hints: bool,
+    /// Whether every value is preceded by a type tag, making the encoding
+    /// self-describing.
+    pub tagged: bool,
+    /// Whether the encoder flushes the writer at the end of every sequence
+    /// and map.
+    pub flush_on_collection_end: bool,
+    /// Whether every length prefix is followed by a one-byte XOR checksum of
+    /// its bytes.
+    pub length_checksums: bool,
+}
+
+/// A reusable set of encoding and decoding options, built with [`builder`].
+/// Options are applied with [`serialize_with`] and [`deserialize_with`], or to
+/// an encoder or decoder with [`Encoder::with_options`] and
+/// [`Decoder::with_options`].
+///
+/// [`builder`]: crate::builder
+/// [`serialize_with`]: crate::serialize_with
+/// [`deserialize_with`]: crate::deserialize_with
+/// [`Encoder::with_options`]: crate::Encoder::with_options
+/// [`Decoder::with_options`]: crate::Decoder::with_options
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Options {
+    /// The configuration the options describe.
+    pub(crate) config: Config,
+}
+
+/// A fluent builder for [`Options`], gathering every setting an encoder or
+/// decoder accepts in one place. Each setting matches the encoder's or
+/// decoder's method of the same name, and settings that only affect decoding
+/// are ignored when encoding.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Builder {
+    /// The configuration built so far.
+    config: Config,
+}
+
+impl Builder {
+    /// Constructs a new builder with every setting at its default.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets whether decoding rejects valid but non-canonical encodings.
+    pub fn canonical(mut self, canonical: bool) -> Self {
+        self.config.canonical = canonical;
+        self
+    }
+
+    /// Sets how the lengths of strings, byte arrays, sequences and maps are
+    /// encoded.
+    pub fn length_encoding(mut self, length_encoding: LengthEncoding) -> Self {
+        self.config.length_encoding = length_encoding;
+        self
+    }
+
+    /// Sets how integers wider than a byte are encoded.
+    pub fn int_encoding(mut self, int_encoding: IntEncoding) -> Self {
+        self.config.int_encoding = int_encoding;
+        self
+    }
+
+    /// Sets how `char` values are encoded.
+    pub fn char_encoding(mut self, char_encoding: CharEncoding) -> Self {
+        self.config.char_encoding = char_encoding;
+        self
+    }
+
+    /// Sets how `f32` and `f64` values are encoded.
+    pub fn float_encoding(mut self, float_encoding: FloatEncoding) -> Self {
+        self.config.float_encoding = float_encoding;
+        self
+    }
+
+    /// Sets whether a sentinel byte follows every struct and sequence, and
+    /// tuple and struct variants are written with their number of fields.
+    pub fn framed(mut self, framed: bool) -> Self {
+        self.config.framed = framed;
+        self
+    }
+
+    /// Sets whether decoding treats input ending between two struct fields as
+    /// the end of the struct.
+    pub fn tolerant(mut self, tolerant: bool) -> Self {
+        self.config.tolerant = tolerant;
+        self
+    }
+
+    /// Sets whether encoding and decoding reject NaN and infinite floating
+    /// point values.
+    pub fn finite_floats(mut self, finite_floats: bool) -> Self {
+        self.config.finite_floats = finite_floats;
+        self
+    }
+
+    /// Sets the maximum number of bytes decoding may read in total.
+    pub fn max_total_bytes(mut self, limit: usize) -> Self {
+        self.config.max_total_bytes = Some(limit);
+        self
+    }
+
+    /// Sets the maximum number of elements encoded in zero bytes that decoding
+    /// accepts in a single sequence or map.
+    pub fn max_empty_elements(mut self, limit: usize) -> Self {
+        self.config.max_empty_elements = Some(limit);
+        self
+    }
+
+    /// Sets whether decoding trusts encoded lengths enough to preallocate
+    /// from them.
+    pub fn trust_length_hints(mut self, trust_length_hints: bool) -> Self {
+        self.config.trust_length_hints = trust_length_hints;
+        self
+    }
+
+    /// Sets whether every value is preceded by a type tag.
+    pub fn tagged(mut self, tagged: bool) -> Self {
+        self.config.tagged = tagged;
+        self
+    }
+
+    /// Sets whether every length prefix is followed by a checksum of its
+    /// bytes.
+    pub fn length_checksums(mut self, length_checksums: bool) -> Self {
+        self.config.length_checksums = length_checksums;
+        self
+    }
+
+    /// Sets whether encoding flushes the writer at the end of every sequence
+    /// and map.
+    pub fn flush_on_collection_end(mut self, flush_on_collection_end: bool) -> Self {
+        self.config.flush_on_collection_end = flush_on_collection_end;
+        self
+    }
+
+    /// Finishes building, returning the options.
+    pub fn build(self) -> Options {
+        Options {
+            config: self.config,
+        }
+    }
+}
@@ -2,19 +2,59 @@
 
 use std::marker::PhantomData;
 
+use crate::config::{CharEncoding, Config, FloatEncoding, IntEncoding, LengthEncoding, Options};
+use crate::fixed_bytes;
+use crate::length_codec::LengthReader;
 use crate::read::Read;
+use crate::tag::Tag;
 use crate::util::*;
 use crate::{Error, ValueType};
 use serde::de::{
     DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess, VariantAccess, Visitor,
 };
-use serde::Deserializer;
+use serde::{Deserialize, Deserializer};
 
 /// The binary decoder.
+// the `any_hint` function pointer is compared by address, which is fine for
+// telling decoders apart
+#[allow(unpredictable_function_pointer_comparisons)]
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Decoder<'de, 'r, R>(&'r mut R, PhantomData<&'de ()>)
+pub struct Decoder<'de, 'r, R>
 where
-    R: Read<'de>;
+    R: Read<'de>,
+{
+    /// The underlying reader.
+    reader: &'r mut R,
+    /// The decoding configuration.
+    config: Config,
+    /// The number of bytes read so far.
+    bytes_read: usize,
+    /// The caller's hint for the type of value to decode at each call to
+    /// `deserialize_any` outside of tagged mode.
+    any_hint: Option<fn(usize) -> Option<ValueType>>,
+    /// The number of calls to `deserialize_any` so far, outside of tagged
+    /// mode.
+    any_position: usize,
+    /// Whether the tag of the next value has already been read, by
+    /// `deserialize_any` in tagged mode.
+    tag_consumed: bool,
+    /// The byte sequence at which decoding stops, or empty for none.
+    delimiter: Vec<u8>,
+    /// The last bytes read, up to one fewer than the delimiter's length, in
+    /// case the delimiter straddles two reads.
+    recent_bytes: Vec<u8>,
+    /// A buffer reused across reads of length prefixes, so that they don't
+    /// allocate once it has grown large enough.
+    scratch: Vec<u8>,
+    /// The header read by `peek_header` from a reader that can't look ahead,
+    /// for `read_header` to return.
+    peeked_header: Option<([u8; 4], u16)>,
+    /// The offset, type and length of every primitive value read so far, if
+    /// they are being recorded.
+    trace: Option<Vec<(usize, ValueType, usize)>>,
+    /// Marker for the lifetime of borrowed data.
+    phantom: PhantomData<&'de ()>,
+}
 
 impl<'de, 'r, R> Decoder<'de, 'r, R>
 where
@@ -22,12 +62,753 @@ where
 {
     /// Constructs a new binary decoder.
     pub fn new(reader: &'r mut R) -> Self {
-        Self(reader, PhantomData)
+        Self {
+            reader,
+            config: Config::default(),
+            bytes_read: 0,
+            any_hint: None,
+            any_position: 0,
+            tag_consumed: false,
+            delimiter: Vec::new(),
+            recent_bytes: Vec::new(),
+            scratch: Vec::new(),
+            peeked_header: None,
+            trace: None,
+            phantom: PhantomData,
+        }
+    }
+
+    /// Sets whether to reject valid but non-canonical encodings, such as
+    /// length prefixes with redundant leading zero bytes. Defaults to `false`.
+    pub fn with_canonical(mut self, canonical: bool) -> Self {
+        self.config.canonical = canonical;
+        self
+    }
+
+    /// Sets how the lengths of strings, byte arrays, sequences and maps are
+    /// expected to be encoded. Defaults to [`LengthEncoding::Prefixed`].
+    pub fn with_length_encoding(mut self, length_encoding: LengthEncoding) -> Self {
+        self.config.length_encoding = length_encoding;
+        self
+    }
+
+    /// Sets how integers wider than a byte are expected to be encoded.
+    /// Defaults to [`IntEncoding::Fixed`].
+    pub fn with_int_encoding(mut self, int_encoding: IntEncoding) -> Self {
+        self.config.int_encoding = int_encoding;
+        self
+    }
+
+    /// Sets how `char` values are expected to be encoded. Defaults to
+    /// [`CharEncoding::Utf8`].
+    pub fn with_char_encoding(mut self, char_encoding: CharEncoding) -> Self {
+        self.config.char_encoding = char_encoding;
+        self
+    }
+
+    /// Sets how `f32` and `f64` values are expected to be encoded. Defaults
+    /// to [`FloatEncoding::Fixed`].
+    pub fn with_float_encoding(mut self, float_encoding: FloatEncoding) -> Self {
+        self.config.float_encoding = float_encoding;
+        self
+    }
+
+    /// Sets whether to expect a sentinel byte after every struct and
+    /// sequence, failing with [`Error::FrameDesync`] as soon as one is
+    /// missing, and the number of fields after the index of every tuple and
+    /// struct variant, failing with [`Error::VariantArityMismatch`] if it
+    /// isn't the decoded variant's. This is a debugging aid, and must match
+    /// the encoder's setting. Defaults to `false`.
+    pub fn with_framed(mut self, framed: bool) -> Self {
+        self.config.framed = framed;
+        self
+    }
+
+    /// Sets whether input ending exactly between two fields of a struct is
+    /// treated as the end of the struct, rather than as an error. The missing
+    /// fields are then left to the struct's `Deserialize` impl, which fills
+    /// in those marked `#[serde(default)]`. This allows fields to be appended
+    /// to a struct while still decoding data written before they existed.
+    ///
+    /// Only readers that know how much input remains, such as
+    /// [`BytesReader`](crate::BytesReader), can detect the end of input
+    /// between fields. Defaults to `false`.
+    pub fn with_tolerant(mut self, tolerant: bool) -> Self {
+        self.config.tolerant = tolerant;
+        self
+    }
+
+    /// Sets whether to reject NaN and infinite floating point values with
+    /// [`Error::NonFiniteFloat`], for data bound for systems that can't
+    /// represent them. Defaults to `false`.
+    pub fn with_finite_floats(mut self, finite_floats: bool) -> Self {
+        self.config.finite_floats = finite_floats;
+        self
+    }
+
+    /// Sets the maximum number of bytes to read in total, across every value
+    /// decoded, before failing with [`Error::InputLimitExceeded`]. This bounds
+    /// how much a malicious stream can make the decoder read. Unlimited by
+    /// default.
+    pub fn with_max_total_bytes(mut self, limit: usize) -> Self {
+        self.config.max_total_bytes = Some(limit);
+        self
+    }
+
+    /// Sets the maximum number of elements encoded in zero bytes, such as
+    /// units, to accept in a single sequence or map before failing with
+    /// [`Error::TooManyEmptyElements`]. Such elements don't advance through the
+    /// input, so this bounds how long a hostile length can keep the decoder
+    /// busy. Defaults to 1,048,576.
+    pub fn with_max_empty_elements(mut self, limit: usize) -> Self {
+        self.config.max_empty_elements = Some(limit);
+        self
+    }
+
+    /// Sets whether to trust encoded sequence and map lengths, passing them on
+    /// to visitors as size hints so that collections such as `Vec` can
+    /// allocate their full size up front. This is fastest when the input is
+    /// known to be well-formed, but lets a hostile length cause a huge
+    /// allocation.
+    ///
+    /// Otherwise, the hint is clamped to the number of bytes left in the
+    /// reader, for readers that know it, and omitted for those that don't, so
+    /// that collections grow as items are actually decoded. Defaults to
+    /// `false`.
+    pub fn with_trust_length_hints(mut self, trust_length_hints: bool) -> Self {
+        self.config.trust_length_hints = trust_length_hints;
+        self
+    }
+
+    /// Sets whether to expect a one-byte checksum after every length prefix,
+    /// as written by an encoder with length checksums enabled. The checksum
+    /// is verified before the length is used, so a corrupted length fails
+    /// with [`Error::LengthChecksumMismatch`] rather than causing a huge
+    /// allocation. Defaults to `false`.
+    pub fn with_length_checksums(mut self, length_checksums: bool) -> Self {
+        self.config.length_checksums = length_checksums;
+        self
+    }
+
+    /// Sets whether to expect a type tag before every value, as written by an
+    /// encoder in tagged mode. This makes `deserialize_any` available, so
+    /// that self-describing types such as `serde_json::Value` can be decoded.
+    /// A value preceded by the tag of another type fails with
+    /// [`Error::UnexpectedTag`]. Defaults to `false`.
+    pub fn with_tagged(mut self, tagged: bool) -> Self {
+        self.config.tagged = tagged;
+        self
+    }
+
+    /// Applies every setting from the given options at once, replacing any
+    /// set before.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.config = options.config;
+        self
+    }
+
+    /// Sets a function giving the type of value to decode when
+    /// `deserialize_any` is called outside of tagged mode, which otherwise
+    /// fails with [`Error::CannotDeserializeAny`]. The function is passed the
+    /// position of the call among all calls to `deserialize_any` made by this
+    /// decoder so far, starting from `0`, and returns `None` to fail as usual.
+    ///
+    /// This allows types such as `#[serde(untagged)]` enums to be decoded when
+    /// the caller knows the shape of the data, without full tagging. The hint
+    /// only sees the position, never the data, so each position must always
+    /// hold the same type of value. Only types that can be decoded without
+    /// further information from the caller can be hinted: tuples, structs and
+    /// enums, which need a length or field and variant names, fail with
+    /// [`Error::CannotDeserializeAny`]. In tagged mode the hint is unused, since every value
+    /// describes itself.
+    pub fn with_any_hint(mut self, hint: fn(usize) -> Option<ValueType>) -> Self {
+        self.any_hint = Some(hint);
+        self
     }
 
     /// Returns a mutable reference to the underlying reader.
     pub fn reader(&mut self) -> &mut R {
-        self.0
+        self.reader
+    }
+
+    /// Sets a byte sequence that marks the end of the value's frame, such as
+    /// a newline when values are embedded in a line-based log. If the
+    /// delimiter appears in the bytes read, decoding fails with
+    /// [`Error::DelimiterReached`] rather than reading on into the next frame.
+    ///
+    /// The encoding has no escaping, so this is only useful when the values
+    /// can't contain the delimiter. Every byte has to be inspected before it
+    /// is decoded, so strings and byte arrays are copied out of the input in
+    /// this mode, and types that can only borrow, such as `&str`, can't be
+    /// decoded. An empty delimiter disables the check, which is the default.
+    pub fn with_delimiter(mut self, delimiter: &[u8]) -> Self {
+        self.delimiter = delimiter.to_vec();
+        self
+    }
+
+    /// Returns the variant index of the enum value about to be decoded,
+    /// without consuming it, so that callers can branch on the variant before
+    /// committing to decode it. Returns `None` if the reader can't look ahead,
+    /// as only in-memory readers such as [`BytesReader`](crate::BytesReader)
+    /// can, if the input has ended, or, in tagged mode, if the next value is
+    /// not an enum.
+    pub fn peek_variant_index(&self) -> Option<u8> {
+        if self.config.tagged && !self.tag_consumed {
+            match self.reader.peek(2)? {
+                &[tag, index] if Tag::from_byte(tag) == Some(Tag::Enum) => Some(index),
+                _ => None,
+            }
+        } else {
+            self.reader.peek(1).map(|bytes| bytes[0])
+        }
+    }
+
+    /// Returns the magic number and version of the header written by
+    /// [`serialize_with_header`](crate::serialize_with_header) at the start of
+    /// the input, so that callers can route the input to the right decoder
+    /// before decoding the value. Fails with [`Error::UnexpectedEof`] if the
+    /// input is too short to hold a header.
+    ///
+    /// Readers that can look ahead, such as
+    /// [`BytesReader`](crate::BytesReader), are left where they were. Other
+    /// readers, such as streams, have the header consumed, and the decoder
+    /// keeps it for [`Decoder::read_header`] to return. Either way, following
+    /// this with [`Decoder::read_header`] leaves the decoder at the value.
+    pub fn peek_header(&mut self) -> crate::Result<([u8; 4], u16)> {
+        if let Some(header) = self.peeked_header {
+            return Ok(header);
+        }
+
+        if let Some(bytes) = self.reader.peek(HEADER_LEN) {
+            let header = bytes.try_into()?;
+            return Ok(decode_header(header));
+        }
+
+        if self
+            .reader
+            .remaining_len()
+            .is_some_and(|len| len < HEADER_LEN)
+        {
+            return Err(Error::UnexpectedEof);
+        }
+
+        let header = decode_header(self.read_n_array::<HEADER_LEN>()?);
+        self.peeked_header = Some(header);
+        Ok(header)
+    }
+
+    /// Reads the header written by
+    /// [`serialize_with_header`](crate::serialize_with_header) at the start of
+    /// the input, returning its magic number and version, or returns the one
+    /// already read by [`Decoder::peek_header`].
+    pub fn read_header(&mut self) -> crate::Result<([u8; 4], u16)> {
+        match self.peeked_header.take() {
+            Some(header) => Ok(header),
+            None => Ok(decode_header(self.read_n_array::<HEADER_LEN>()?)),
+        }
+    }
+
+    /// Deserializes a value from a frame written by
+    /// [`serialize_length_prefixed`](crate::serialize_length_prefixed),
+    /// failing with [`Error::FrameLengthMismatch`] unless the value took up
+    /// exactly the frame's declared length. This catches an encoder and
+    /// decoder that disagree on the format, even when the value happens to
+    /// decode.
+    ///
+    /// Unlike [`deserialize_length_prefixed`](crate::deserialize_length_prefixed),
+    /// this reads from any reader, such as a stream of frames. After a
+    /// mismatch, the decoder is no longer at the start of the next frame.
+    pub fn deserialize_length_prefixed<T>(&mut self) -> crate::Result<T>
+    where
+        T: Deserialize<'de>,
+    {
+        let len1 = self.read_u8()?;
+        self.read_n_scratch(decode_len_small(len1))?;
+        let declared = decode_len_large(&self.scratch).ok_or_else(|| {
+            let mut bytes = vec![len1];
+            bytes.extend_from_slice(&self.scratch);
+            Error::LengthOverflow(bytes)
+        })?;
+
+        let start = self.bytes_read;
+        let value = T::deserialize(&mut *self)?;
+        let consumed = self.bytes_read - start;
+
+        if consumed != declared {
+            return Err(Error::FrameLengthMismatch { declared, consumed });
+        }
+
+        Ok(value)
+    }
+
+    /// Returns the number of bytes read so far.
+    pub fn bytes_read(&self) -> usize {
+        self.bytes_read
+    }
+
+    /// Sets whether to record every primitive value read, such as an integer,
+    /// string or unit, along with every option discriminant, enum variant
+    /// index and sequence or map length prefix, for post-mortem analysis when
+    /// the decoder falls out of step with the data and starts reading
+    /// garbage. The record is returned by [`Decoder::trace`]. Defaults to
+    /// `false`.
+    pub fn with_trace(mut self, trace: bool) -> Self {
+        self.trace = trace.then(Vec::new);
+        self
+    }
+
+    /// Returns the offset, type and length in bytes of every primitive value
+    /// read so far, in the order they were read, with tracing enabled by
+    /// [`Decoder::with_trace`], or nothing otherwise. Options, enums,
+    /// sequences and maps are recorded as their discriminant, variant index
+    /// or length prefix alone, before their contents. Offsets count from where
+    /// the decoder started, and include any type tags, as
+    /// [`Decoder::bytes_read`] does. A value that failed to decode is recorded
+    /// as well, so the last entry shows where decoding gave up.
+    pub fn trace(&self) -> &[(usize, ValueType, usize)] {
+        self.trace.as_deref().unwrap_or_default()
+    }
+
+    /// Runs `read`, which reads a primitive value of type `ty`, or the header
+    /// of a compound one, recording its offset and length if tracing is
+    /// enabled.
+    fn traced<T>(
+        &mut self,
+        ty: ValueType,
+        read: impl FnOnce(&mut Self) -> crate::Result<T>,
+    ) -> crate::Result<T> {
+        let offset = self.bytes_read;
+        let result = read(self);
+
+        if let Some(trace) = &mut self.trace {
+            trace.push((offset, ty, self.bytes_read - offset));
+        }
+
+        result
+    }
+
+    /// Counts `n` more bytes as read, failing without reading anything if
+    /// that would exceed the total byte limit.
+    fn consume(&mut self, n: usize) -> crate::Result<()> {
+        let bytes_read = self.bytes_read.saturating_add(n);
+
+        if let Some(limit) = self.config.max_total_bytes {
+            if bytes_read > limit {
+                return Err(Error::InputLimitExceeded { limit });
+            }
+        }
+
+        self.bytes_read = bytes_read;
+        Ok(())
+    }
+
+    /// Reads `N` bytes from the underlying reader, counting them against the
+    /// total byte limit.
+    fn read_n_array<const N: usize>(&mut self) -> crate::Result<[u8; N]> {
+        self.consume(N)?;
+        let bytes = self.reader.read_n_array::<N>()?;
+        self.check_delimiter(&bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads the `N` bytes of a fixed-width value of type `ty`, attributing a
+    /// premature end of input to that value. Every fixed-width primitive is
+    /// read through here.
+    fn read_n_array_for<const N: usize>(&mut self, ty: ValueType) -> crate::Result<[u8; N]> {
+        self.read_n_array::<N>().map_err(truncated(ty))
+    }
+
+    /// Reads a single byte from the underlying reader, counting it against the
+    /// total byte limit.
+    fn read_u8(&mut self) -> crate::Result<u8> {
+        self.consume(1)?;
+        let byte = self.reader.read_u8()?;
+        self.check_delimiter(&[byte])?;
+        Ok(byte)
+    }
+
+    /// Reads a single-byte value of type `ty`, or the first byte of one,
+    /// attributing a premature end of input to that value.
+    fn read_u8_for(&mut self, ty: ValueType) -> crate::Result<u8> {
+        self.read_u8().map_err(truncated(ty))
+    }
+
+    /// Reads the one-byte discriminant of a value of type `ty`, such as a
+    /// `bool` or an option. Only `0` and `1` are valid, so that every such
+    /// value has a single encoding, and any other byte is rejected with
+    /// [`Error::InvalidBytes`].
+    fn read_discriminant(&mut self, ty: ValueType) -> crate::Result<u8> {
+        match self.read_u8_for(ty)? {
+            discriminant @ (0 | 1) => Ok(discriminant),
+            byte => Err(Error::InvalidBytes {
+                ty,
+                bytes: vec![byte],
+            }),
+        }
+    }
+
+    /// Reads `n` bytes from the underlying reader into a `Vec<u8>`, counting
+    /// them against the total byte limit.
+    fn read_n_vec(&mut self, n: usize) -> crate::Result<Vec<u8>> {
+        self.consume(n)?;
+        let bytes = self.reader.read_n_vec(n)?;
+        self.check_delimiter(&bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads `n` bytes from the underlying reader into the scratch buffer,
+    /// counting them against the total byte limit. The buffer is cleared
+    /// rather than reallocated, so this only allocates when it has to grow.
+    fn read_n_scratch(&mut self, n: usize) -> crate::Result<()> {
+        self.consume(n)?;
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        scratch.resize(n, 0);
+        let result = self
+            .reader
+            .read_exact(&mut scratch)
+            .and_then(|()| self.check_delimiter(&scratch));
+        self.scratch = scratch;
+        result
+    }
+
+    /// Fails with [`Error::DelimiterReached`] if the delimiter, if any,
+    /// appears in `bytes` or straddles them and the bytes read before them.
+    fn check_delimiter(&mut self, bytes: &[u8]) -> crate::Result<()> {
+        let len = self.delimiter.len();
+
+        if len == 0 {
+            return Ok(());
+        }
+
+        self.recent_bytes.extend_from_slice(bytes);
+
+        if self
+            .recent_bytes
+            .windows(len)
+            .any(|window| window == self.delimiter)
+        {
+            return Err(Error::DelimiterReached);
+        }
+
+        let excess = self.recent_bytes.len().saturating_sub(len - 1);
+        self.recent_bytes.drain(..excess);
+        Ok(())
+    }
+
+    /// Returns the size hint to give a visitor for a sequence or map with
+    /// `len` items remaining. Unless lengths are trusted, the hint is clamped
+    /// to the number of bytes left in the reader, or omitted if that isn't
+    /// known, so that a hostile length can't cause a huge up-front allocation.
+    /// Every item is assumed to take at least one byte, which only affects the
+    /// size hint reported to the visitor, never how many items are decoded.
+    fn cautious_len(&self, len: usize) -> Option<usize> {
+        if self.config.trust_length_hints {
+            return Some(len);
+        }
+
+        self.reader
+            .remaining_len()
+            .map(|remaining| len.min(remaining))
+    }
+
+    /// Counts an item of a sequence or entry of a map that took no bytes of
+    /// input in `empty_items`, failing once there are more than the configured
+    /// limit, or [`MAX_EMPTY_ELEMENTS`] if none is set.
+    /// Such items don't advance through the input, so they are the only way
+    /// a lying length could keep the decoder busy indefinitely.
+    fn count_empty_item(&self, empty_items: &mut usize) -> crate::Result<()> {
+        let limit = self.config.max_empty_elements.unwrap_or(MAX_EMPTY_ELEMENTS);
+        *empty_items += 1;
+
+        if *empty_items > limit {
+            return Err(Error::TooManyEmptyElements { limit });
+        }
+
+        Ok(())
+    }
+
+    /// Checks whether the input has ended, for a reader that knows how much
+    /// input remains.
+    fn at_end(&self) -> bool {
+        self.reader.remaining_len() == Some(0)
+    }
+
+    /// Checks whether the input has ended, when decoding tolerantly from a
+    /// reader that knows how much input remains.
+    fn at_tolerated_end(&self) -> bool {
+        self.config.tolerant && self.at_end()
+    }
+
+    /// Reads an `f32` in the configured float encoding.
+    fn read_f32(&mut self) -> crate::Result<f32> {
+        let ty = ValueType::F32;
+
+        if self.config.float_encoding == FloatEncoding::Fixed {
+            return Ok(f32::from_be_bytes(self.read_n_array_for::<4>(ty)?));
+        }
+
+        let width = self.read_u8_for(ty)?;
+        let value = match width {
+            COMPACT_FLOAT_I8 => f32::from(self.read_u8_for(ty)? as i8),
+            COMPACT_FLOAT_F32 => f32::from_be_bytes(self.read_n_array_for::<4>(ty)?),
+            _ => {
+                return Err(Error::InvalidBytes {
+                    ty,
+                    bytes: vec![width],
+                })
+            }
+        };
+
+        if self.config.canonical && compact_f32_width(value) != width {
+            return Err(Error::InvalidBytes {
+                ty,
+                bytes: vec![width],
+            });
+        }
+
+        Ok(value)
+    }
+
+    /// Reads an `f64` in the configured float encoding.
+    fn read_f64(&mut self) -> crate::Result<f64> {
+        let ty = ValueType::F64;
+
+        if self.config.float_encoding == FloatEncoding::Fixed {
+            return Ok(f64::from_be_bytes(self.read_n_array_for::<8>(ty)?));
+        }
+
+        let width = self.read_u8_for(ty)?;
+        let value = match width {
+            COMPACT_FLOAT_I8 => f64::from(self.read_u8_for(ty)? as i8),
+            COMPACT_FLOAT_F32 => f32::from_be_bytes(self.read_n_array_for::<4>(ty)?).into(),
+            COMPACT_FLOAT_F64 => f64::from_be_bytes(self.read_n_array_for::<8>(ty)?),
+            _ => {
+                return Err(Error::InvalidBytes {
+                    ty,
+                    bytes: vec![width],
+                })
+            }
+        };
+
+        if self.config.canonical && compact_f64_width(value) != width {
+            return Err(Error::InvalidBytes {
+                ty,
+                bytes: vec![width],
+            });
+        }
+
+        Ok(value)
+    }
+
+    /// Rejects a non-finite float of type `ty` if only finite values are
+    /// allowed.
+    fn check_finite(&self, ty: ValueType, value: f64) -> crate::Result<()> {
+        if self.config.finite_floats && !value.is_finite() {
+            return Err(Error::NonFiniteFloat { ty, value });
+        }
+
+        Ok(())
+    }
+
+    /// Reads and checks the frame sentinel in framed mode, or does nothing
+    /// otherwise.
+    fn read_frame_end(&mut self) -> crate::Result<()> {
+        if self.config.framed {
+            let found = self.read_u8()?;
+
+            if found != FRAME_SENTINEL {
+                return Err(Error::FrameDesync {
+                    expected_sentinel: FRAME_SENTINEL,
+                    found,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads and checks the number of fields written after the index of a
+    /// tuple or struct variant in framed mode, or does nothing otherwise.
+    fn read_variant_arity(&mut self, expected: usize) -> crate::Result<()> {
+        if self.config.framed && !self.config.tagged {
+            let found = self.read_len()?;
+
+            if found != expected {
+                return Err(Error::VariantArityMismatch { expected, found });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Reads a block of `len` bytes and passes it to the visitor, borrowed
+    /// from the input if the reader allows.
+    fn visit_bytes<V>(&mut self, len: usize, visitor: V) -> crate::Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        // as with strings, the bytes can't be borrowed in delimiter mode
+        if !self.delimiter.is_empty() {
+            let bytes = self.read_n_vec(len)?;
+            return visitor.visit_byte_buf(bytes);
+        }
+
+        self.consume(len)?;
+        self.reader.visit_bytes(len, visitor)
+    }
+
+    /// Reads a type tag.
+    fn read_tag(&mut self) -> crate::Result<Tag> {
+        let byte = self.read_u8()?;
+        Tag::from_byte(byte).ok_or(Error::InvalidTag(byte))
+    }
+
+    /// Reads the tag of the next value in tagged mode and checks that it is
+    /// `expected`, unless `deserialize_any` has already read it. Does nothing
+    /// outside of tagged mode.
+    fn expect_tag(&mut self, expected: Tag) -> crate::Result<()> {
+        if !self.config.tagged || std::mem::take(&mut self.tag_consumed) {
+            return Ok(());
+        }
+
+        let found = self.read_tag().map_err(truncated(expected.value_type()))?;
+
+        if found != expected {
+            return Err(Error::UnexpectedTag {
+                expected: expected.value_type(),
+                found: found.value_type(),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Reads the number of fields in a tuple, struct, or tuple or struct
+    /// variant body. Outside of tagged mode this is known from the type, and
+    /// `len` is returned without reading anything.
+    fn read_fields_len(&mut self, len: usize) -> crate::Result<usize> {
+        if self.config.tagged {
+            self.expect_tag(Tag::Seq)?;
+            self.read_len()
+        } else {
+            Ok(len)
+        }
+    }
+
+    /// Reads the frame sentinel after a tuple, tuple struct or tuple variant
+    /// body, which is framed like a sequence in tagged mode.
+    fn read_fields_end(&mut self) -> crate::Result<()> {
+        if self.config.tagged {
+            self.read_frame_end()?;
+        }
+
+        Ok(())
+    }
+
+    /// Reads a varint-encoded integer of type `ty`, which is `bits` bits wide.
+    /// Varints too long for the type or holding values that don't fit in it
+    /// are rejected, as are overlong encodings in canonical mode.
+    fn read_int_varint(&mut self, ty: ValueType, bits: u32) -> crate::Result<u128> {
+        let max_len = bits.div_ceil(7) as usize;
+        let mut encoded = [0; MAX_INT_VARINT_BYTES];
+        let mut len = 0;
+
+        loop {
+            let byte = self.read_u8_for(ty)?;
+            encoded[len] = byte;
+            len += 1;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+
+            if len == max_len {
+                return Err(Error::InvalidBytes {
+                    ty,
+                    bytes: encoded[..len].to_vec(),
+                });
+            }
+        }
+
+        let encoded = &encoded[..len];
+
+        if self.config.canonical && !is_canonical_varint(encoded) {
+            return Err(Error::InvalidBytes {
+                ty,
+                bytes: encoded.to_vec(),
+            });
+        }
+
+        decode_int_varint(encoded, bits).ok_or_else(|| Error::InvalidBytes {
+            ty,
+            bytes: encoded.to_vec(),
+        })
+    }
+
+    /// Reads the length of a string, byte array, sequence or map with the
+    /// configured encoding's codec, verifying its checksum and, in canonical
+    /// mode, that it is canonical.
+    fn read_len(&mut self) -> crate::Result<usize> {
+        let codec = self.config.length_encoding.codec();
+        let mut len_encoded = std::mem::take(&mut self.scratch);
+        len_encoded.clear();
+        let mut reader = LenBytesReader {
+            decoder: self,
+            bytes: &mut len_encoded,
+        };
+        let result = codec.decode_len(&mut reader).and_then(|len| {
+            self.verify_len_checksum(xor_checksum(&len_encoded))?;
+
+            if self.config.canonical && !codec.is_canonical(&len_encoded) {
+                return Err(Error::NonCanonicalLength(len_encoded.clone()));
+            }
+
+            Ok(len)
+        });
+        self.scratch = len_encoded;
+        result
+    }
+
+    /// Reads the checksum following a length prefix's bytes, with length
+    /// checksums enabled, and compares it to the one computed from them.
+    fn verify_len_checksum(&mut self, expected: u8) -> crate::Result<()> {
+        if !self.config.length_checksums {
+            return Ok(());
+        }
+
+        let found = self.read_u8()?;
+
+        if found == expected {
+            Ok(())
+        } else {
+            Err(Error::LengthChecksumMismatch { expected, found })
+        }
+    }
+}
+
+/// Reads the bytes of a length for a custom [`LengthCodec`] through a
+/// decoder, recording them so that their checksum can be verified.
+struct LenBytesReader<'a, 'de, 'r, R>
+where
+    R: Read<'de>,
+{
+    /// The decoder to read through.
+    decoder: &'a mut Decoder<'de, 'r, R>,
+    /// The bytes of the length read so far.
+    bytes: &'a mut Vec<u8>,
+}
+
+impl<'a, 'de, 'r, R> LengthReader for LenBytesReader<'a, 'de, 'r, R>
+where
+    R: Read<'de>,
+{
+    fn read_exact(&mut self, buf: &mut [u8]) -> crate::Result<()> {
+        self.decoder.consume(buf.len())?;
+        self.decoder.reader.read_exact(buf)?;
+        self.decoder.check_delimiter(buf)?;
+        self.bytes.extend_from_slice(buf);
+        Ok(())
     }
 }
 
@@ -37,185 +818,399 @@ where
 {
     type Error = Error;
 
-    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::CannotDeserializeAny)
+        if !self.config.tagged {
+            let position = self.any_position;
+            self.any_position += 1;
+
+            return match self.any_hint.and_then(|hint| hint(position)) {
+                Some(ValueType::Bool) => self.deserialize_bool(visitor),
+                Some(ValueType::I8) => self.deserialize_i8(visitor),
+                Some(ValueType::I16) => self.deserialize_i16(visitor),
+                Some(ValueType::I32) => self.deserialize_i32(visitor),
+                Some(ValueType::I64) => self.deserialize_i64(visitor),
+                Some(ValueType::I128) => self.deserialize_i128(visitor),
+                Some(ValueType::U8) => self.deserialize_u8(visitor),
+                Some(ValueType::U16) => self.deserialize_u16(visitor),
+                Some(ValueType::U32) => self.deserialize_u32(visitor),
+                Some(ValueType::U64) => self.deserialize_u64(visitor),
+                Some(ValueType::U128) => self.deserialize_u128(visitor),
+                Some(ValueType::F32) => self.deserialize_f32(visitor),
+                Some(ValueType::F64) => self.deserialize_f64(visitor),
+                Some(ValueType::Char) => self.deserialize_char(visitor),
+                Some(ValueType::Str) => self.deserialize_str(visitor),
+                Some(ValueType::String) => self.deserialize_string(visitor),
+                Some(ValueType::Bytes) => self.deserialize_bytes(visitor),
+                Some(ValueType::ByteBuf) => self.deserialize_byte_buf(visitor),
+                Some(ValueType::Option) => self.deserialize_option(visitor),
+                Some(ValueType::Unit | ValueType::UnitStruct) => self.deserialize_unit(visitor),
+                Some(ValueType::NewtypeStruct) => self.deserialize_newtype_struct("", visitor),
+                Some(ValueType::Seq) => self.deserialize_seq(visitor),
+                Some(ValueType::Map) => self.deserialize_map(visitor),
+                Some(
+                    ValueType::Tuple | ValueType::TupleStruct | ValueType::Struct | ValueType::Enum,
+                )
+                | None => Err(Error::CannotDeserializeAny),
+            };
+        }
+
+        let tag = self.read_tag()?;
+        self.tag_consumed = true;
+
+        match tag {
+            Tag::Unit => self.deserialize_unit(visitor),
+            Tag::Bool => self.deserialize_bool(visitor),
+            Tag::I8 => self.deserialize_i8(visitor),
+            Tag::I16 => self.deserialize_i16(visitor),
+            Tag::I32 => self.deserialize_i32(visitor),
+            Tag::I64 => self.deserialize_i64(visitor),
+            Tag::I128 => self.deserialize_i128(visitor),
+            Tag::U8 => self.deserialize_u8(visitor),
+            Tag::U16 => self.deserialize_u16(visitor),
+            Tag::U32 => self.deserialize_u32(visitor),
+            Tag::U64 => self.deserialize_u64(visitor),
+            Tag::U128 => self.deserialize_u128(visitor),
+            Tag::F32 => self.deserialize_f32(visitor),
+            Tag::F64 => self.deserialize_f64(visitor),
+            Tag::Char => self.deserialize_char(visitor),
+            Tag::Str => self.deserialize_str(visitor),
+            Tag::Bytes => self.deserialize_bytes(visitor),
+            Tag::Option => self.deserialize_option(visitor),
+            Tag::Seq => self.deserialize_seq(visitor),
+            Tag::Map => self.deserialize_map(visitor),
+            Tag::Enum => self.deserialize_enum("", &[], visitor),
+        }
     }
 
     fn deserialize_bool<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<1>()?;
-        let value = match bytes[0] {
-            0 => Ok(false),
-            1 => Ok(true),
-            _ => Err(Error::InvalidBytes {
-                ty: ValueType::Bool,
-                bytes: bytes.to_vec(),
-            }),
-        }?;
-        visitor.visit_bool(value)
+        self.traced(ValueType::Bool, |decoder| {
+            decoder.expect_tag(Tag::Bool)?;
+            // Nonzero bytes other than `1` are not leniently read as `true`.
+            let discriminant = decoder.read_discriminant(ValueType::Bool)?;
+            visitor.visit_bool(discriminant == 1)
+        })
     }
 
     fn deserialize_i8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<1>()?;
-        visitor.visit_i8(i8::from_be_bytes(bytes))
+        self.traced(ValueType::I8, |decoder| {
+            decoder.expect_tag(Tag::I8)?;
+            let byte = decoder.read_u8_for(ValueType::I8)?;
+            visitor.visit_i8(byte as i8)
+        })
     }
 
     fn deserialize_i16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<2>()?;
-        visitor.visit_i16(i16::from_be_bytes(bytes))
+        self.traced(ValueType::I16, |decoder| {
+            decoder.expect_tag(Tag::I16)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    i16::from_be_bytes(decoder.read_n_array_for::<2>(ValueType::I16)?)
+                }
+                IntEncoding::Varint => {
+                    zigzag_decode(decoder.read_int_varint(ValueType::I16, i16::BITS)?) as i16
+                }
+            };
+            visitor.visit_i16(value)
+        })
     }
 
     fn deserialize_i32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<4>()?;
-        visitor.visit_i32(i32::from_be_bytes(bytes))
+        self.traced(ValueType::I32, |decoder| {
+            decoder.expect_tag(Tag::I32)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    i32::from_be_bytes(decoder.read_n_array_for::<4>(ValueType::I32)?)
+                }
+                IntEncoding::Varint => {
+                    zigzag_decode(decoder.read_int_varint(ValueType::I32, i32::BITS)?) as i32
+                }
+            };
+            visitor.visit_i32(value)
+        })
     }
 
     fn deserialize_i64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<8>()?;
-        visitor.visit_i64(i64::from_be_bytes(bytes))
+        self.traced(ValueType::I64, |decoder| {
+            decoder.expect_tag(Tag::I64)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    i64::from_be_bytes(decoder.read_n_array_for::<8>(ValueType::I64)?)
+                }
+                IntEncoding::Varint => {
+                    zigzag_decode(decoder.read_int_varint(ValueType::I64, i64::BITS)?) as i64
+                }
+            };
+            visitor.visit_i64(value)
+        })
     }
 
     fn deserialize_i128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<16>()?;
-        visitor.visit_i128(i128::from_be_bytes(bytes))
+        self.traced(ValueType::I128, |decoder| {
+            decoder.expect_tag(Tag::I128)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    i128::from_be_bytes(decoder.read_n_array_for::<16>(ValueType::I128)?)
+                }
+                IntEncoding::Varint => {
+                    zigzag_decode(decoder.read_int_varint(ValueType::I128, i128::BITS)?)
+                }
+            };
+            visitor.visit_i128(value)
+        })
     }
 
     fn deserialize_u8<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<1>()?;
-        visitor.visit_u8(bytes[0])
+        self.traced(ValueType::U8, |decoder| {
+            decoder.expect_tag(Tag::U8)?;
+            visitor.visit_u8(decoder.read_u8_for(ValueType::U8)?)
+        })
     }
 
     fn deserialize_u16<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<2>()?;
-        visitor.visit_u16(u16::from_be_bytes(bytes))
+        self.traced(ValueType::U16, |decoder| {
+            decoder.expect_tag(Tag::U16)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    u16::from_be_bytes(decoder.read_n_array_for::<2>(ValueType::U16)?)
+                }
+                IntEncoding::Varint => decoder.read_int_varint(ValueType::U16, u16::BITS)? as u16,
+            };
+            visitor.visit_u16(value)
+        })
     }
 
     fn deserialize_u32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<4>()?;
-        visitor.visit_u32(u32::from_be_bytes(bytes))
+        self.traced(ValueType::U32, |decoder| {
+            decoder.expect_tag(Tag::U32)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    u32::from_be_bytes(decoder.read_n_array_for::<4>(ValueType::U32)?)
+                }
+                IntEncoding::Varint => decoder.read_int_varint(ValueType::U32, u32::BITS)? as u32,
+            };
+            visitor.visit_u32(value)
+        })
     }
 
     fn deserialize_u64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<8>()?;
-        visitor.visit_u64(u64::from_be_bytes(bytes))
+        self.traced(ValueType::U64, |decoder| {
+            decoder.expect_tag(Tag::U64)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    u64::from_be_bytes(decoder.read_n_array_for::<8>(ValueType::U64)?)
+                }
+                IntEncoding::Varint => decoder.read_int_varint(ValueType::U64, u64::BITS)? as u64,
+            };
+            visitor.visit_u64(value)
+        })
     }
 
     fn deserialize_u128<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<16>()?;
-        visitor.visit_u128(u128::from_be_bytes(bytes))
+        self.traced(ValueType::U128, |decoder| {
+            decoder.expect_tag(Tag::U128)?;
+            let value = match decoder.config.int_encoding {
+                IntEncoding::Fixed => {
+                    u128::from_be_bytes(decoder.read_n_array_for::<16>(ValueType::U128)?)
+                }
+                IntEncoding::Varint => decoder.read_int_varint(ValueType::U128, u128::BITS)?,
+            };
+            visitor.visit_u128(value)
+        })
     }
 
     fn deserialize_f32<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<4>()?;
-        visitor.visit_f32(f32::from_be_bytes(bytes))
+        self.traced(ValueType::F32, |decoder| {
+            decoder.expect_tag(Tag::F32)?;
+            let value = decoder.read_f32()?;
+            decoder.check_finite(ValueType::F32, value.into())?;
+            visitor.visit_f32(value)
+        })
     }
 
     fn deserialize_f64<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_n_array::<8>()?;
-        visitor.visit_f64(f64::from_be_bytes(bytes))
+        self.traced(ValueType::F64, |decoder| {
+            decoder.expect_tag(Tag::F64)?;
+            let value = decoder.read_f64()?;
+            decoder.check_finite(ValueType::F64, value)?;
+            visitor.visit_f64(value)
+        })
     }
 
     fn deserialize_char<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let len = self.0.read_n_array::<1>()?;
-        let decoded_len = decode_len_small(len[0]);
-        let mut bytes = [0; 4];
-        self.0.read_exact(&mut bytes[4 - decoded_len..])?;
-        let chr = std::str::from_utf8(&bytes[4 - decoded_len..])?
-            .chars()
-            .take(1)
-            .next()
-            .unwrap();
-        visitor.visit_char(chr)
+        self.traced(ValueType::Char, |decoder| {
+            decoder.expect_tag(Tag::Char)?;
+
+            if decoder.config.char_encoding == CharEncoding::Fixed {
+                let bytes = decoder.read_n_array_for::<4>(ValueType::Char)?;
+
+                return match char::from_u32(u32::from_be_bytes(bytes)) {
+                    Some(chr) => visitor.visit_char(chr),
+                    None => Err(Error::InvalidBytes {
+                        ty: ValueType::Char,
+                        bytes: bytes.to_vec(),
+                    }),
+                };
+            }
+
+            let len = decoder.read_u8_for(ValueType::Char)?;
+            let decoded_len = decode_len_small(len);
+
+            if !(1..=4).contains(&decoded_len) {
+                return Err(Error::InvalidBytes {
+                    ty: ValueType::Char,
+                    bytes: vec![len],
+                });
+            }
+
+            let mut bytes = [0; 4];
+            let bytes = &mut bytes[..decoded_len];
+            decoder.consume(decoded_len)?;
+            decoder
+                .reader
+                .read_exact(bytes)
+                .map_err(truncated(ValueType::Char))?;
+            decoder.check_delimiter(bytes)?;
+            let mut chars = std::str::from_utf8(bytes)
+                .map_err(|source| Error::InvalidUtf8 {
+                    ty: ValueType::Char,
+                    source,
+                })?
+                .chars();
+
+            match (chars.next(), chars.next()) {
+                (Some(chr), None) => visitor.visit_char(chr),
+                _ => Err(Error::InvalidBytes {
+                    ty: ValueType::Char,
+                    bytes: bytes.to_vec(),
+                }),
+            }
+        })
     }
 
     fn deserialize_str<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.0.visit_str(visitor)
+        self.traced(ValueType::Str, |decoder| {
+            decoder.expect_tag(Tag::Str)?;
+            let len = decoder.read_len()?;
+
+            // the string has to be inspected for the delimiter before the visitor
+            // sees it, which rules out borrowing it
+            if !decoder.delimiter.is_empty() {
+                let bytes = decoder.read_n_vec(len)?;
+                let string = String::from_utf8(bytes).map_err(|err| Error::InvalidUtf8 {
+                    ty: ValueType::Str,
+                    source: err.utf8_error(),
+                })?;
+                return visitor.visit_string(string);
+            }
+
+            decoder.consume(len)?;
+            decoder
+                .reader
+                .visit_str(len, visitor)
+                .map_err(invalid_utf8(ValueType::Str))
+        })
     }
 
     fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_bytes_with_large_len()?;
-        let string = std::str::from_utf8(&bytes)?;
-        visitor.visit_string(string.to_owned())
+        self.traced(ValueType::String, |decoder| {
+            decoder.expect_tag(Tag::Str)?;
+            let len = decoder.read_len()?;
+            let bytes = decoder.read_n_vec(len)?;
+            // validate in place and reuse the buffer rather than copying it
+            let string = String::from_utf8(bytes).map_err(|err| Error::InvalidUtf8 {
+                ty: ValueType::String,
+                source: err.utf8_error(),
+            })?;
+            visitor.visit_string(string)
+        })
     }
 
     fn deserialize_bytes<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        self.0.visit_bytes(visitor)
+        self.traced(ValueType::Bytes, |decoder| {
+            decoder.expect_tag(Tag::Bytes)?;
+            let len = decoder.read_len()?;
+            decoder.visit_bytes(len, visitor)
+        })
     }
 
     fn deserialize_byte_buf<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.0.read_bytes_with_large_len()?;
-        visitor.visit_byte_buf(bytes)
+        self.traced(ValueType::ByteBuf, |decoder| {
+            decoder.expect_tag(Tag::Bytes)?;
+            let len = decoder.read_len()?;
+            let bytes = decoder.read_n_vec(len)?;
+            visitor.visit_byte_buf(bytes)
+        })
     }
 
     fn deserialize_option<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let discriminant = self.0.read_n_array::<1>()?;
+        let discriminant = self.traced(ValueType::Option, |decoder| {
+            decoder.expect_tag(Tag::Option)?;
+            decoder.read_discriminant(ValueType::Option)
+        })?;
 
-        match discriminant[0] {
+        match discriminant {
             0 => visitor.visit_none(),
-            1 => visitor.visit_some(self),
-            _ => Err(Error::InvalidBytes {
-                ty: ValueType::Option,
-                bytes: discriminant.to_vec(),
-            }),
+            _ => visitor.visit_some(self),
         }
     }
 
@@ -223,7 +1218,10 @@ where
     where
         V: Visitor<'de>,
     {
-        visitor.visit_unit()
+        self.traced(ValueType::Unit, |decoder| {
+            decoder.expect_tag(Tag::Unit)?;
+            visitor.visit_unit()
+        })
     }
 
     fn deserialize_unit_struct<V>(
@@ -234,6 +1232,7 @@ where
     where
         V: Visitor<'de>,
     {
+        self.expect_tag(Tag::Unit)?;
         visitor.visit_unit()
     }
 
@@ -252,41 +1251,55 @@ where
     where
         V: Visitor<'de>,
     {
-        let len1 = self.0.read_n_array::<1>()?;
-        let decoded_len1 = decode_len_small(len1[0]);
-        let len2 = self.0.read_n_vec(decoded_len1)?;
-        let decoded_len2 = decode_len_large(&len2);
-        visitor.visit_seq(SeqDecoder::new(self, decoded_len2))
+        let len = self.traced(ValueType::Seq, |decoder| {
+            decoder.expect_tag(Tag::Seq)?;
+            decoder.read_len()
+        })?;
+        let value = visitor.visit_seq(SeqDecoder::new(self, len))?;
+        self.read_frame_end()?;
+        Ok(value)
     }
 
     fn deserialize_tuple<V>(self, len: usize, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_seq(SeqDecoder::new(self, len))
+        let len = self.read_fields_len(len)?;
+        let value = visitor.visit_seq(SeqDecoder::new_tuple(self, len))?;
+        self.read_fields_end()?;
+        Ok(value)
     }
 
     fn deserialize_tuple_struct<V>(
         self,
-        _name: &'static str,
+        name: &'static str,
         len: usize,
         visitor: V,
     ) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        visitor.visit_seq(SeqDecoder::new(self, len))
+        // a fixed-size byte array, whose bytes are written as a block outside
+        // of tagged mode
+        if name == fixed_bytes::NAME && !self.config.tagged {
+            return self.visit_bytes(len, visitor);
+        }
+
+        let len = self.read_fields_len(len)?;
+        let value = visitor.visit_seq(SeqDecoder::new_tuple(self, len))?;
+        self.read_fields_end()?;
+        Ok(value)
     }
 
     fn deserialize_map<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        let len1 = self.0.read_n_array::<1>()?;
-        let decoded_len1 = decode_len_small(len1[0]);
-        let len2 = self.0.read_n_vec(decoded_len1)?;
-        let decoded_len2 = decode_len_large(&len2);
-        visitor.visit_map(MapDecoder::new(self, decoded_len2))
+        let len = self.traced(ValueType::Map, |decoder| {
+            decoder.expect_tag(Tag::Map)?;
+            decoder.read_len()
+        })?;
+        visitor.visit_map(MapDecoder::new(self, len))
     }
 
     fn deserialize_struct<V>(
@@ -298,7 +1311,14 @@ where
     where
         V: Visitor<'de>,
     {
-        visitor.visit_seq(SeqDecoder::new(self, fields.len()))
+        let len = self.read_fields_len(fields.len())?;
+        let value = visitor.visit_seq(SeqDecoder::new_struct(self, len, fields))?;
+
+        if !self.at_tolerated_end() {
+            self.read_frame_end()?;
+        }
+
+        Ok(value)
     }
 
     fn deserialize_enum<V>(
@@ -310,21 +1330,29 @@ where
     where
         V: Visitor<'de>,
     {
+        self.expect_tag(Tag::Enum)?;
         visitor.visit_enum(EnumDecoder::new(self))
     }
 
-    fn deserialize_identifier<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
+        // identifiers are only written by formats that store field and variant
+        // names, such as by internally tagged enums, and are then tagged like
+        // any other string
+        if self.config.tagged {
+            return self.deserialize_any(visitor);
+        }
+
         Err(Error::CannotDeserializeIdentifier)
     }
 
-    fn deserialize_ignored_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    fn deserialize_ignored_any<V>(self, visitor: V) -> Result<V::Value, Self::Error>
     where
         V: Visitor<'de>,
     {
-        Err(Error::CannotDeserializeAny)
+        self.deserialize_any(visitor)
     }
 
     fn is_human_readable(&self) -> bool {
@@ -332,6 +1360,24 @@ where
     }
 }
 
+/// Returns a function attributing a premature end of input to a value of type
+/// `ty`, and leaving any other error untouched.
+fn truncated(ty: ValueType) -> impl FnOnce(Error) -> Error {
+    move |err| match err {
+        Error::UnexpectedEof => Error::TruncatedValue { ty },
+        err => err,
+    }
+}
+
+/// Returns a function attributing invalid UTF-8 to a value of type `ty`, and
+/// leaving any other error untouched.
+fn invalid_utf8(ty: ValueType) -> impl FnOnce(Error) -> Error {
+    move |err| match err {
+        Error::Utf8Error(source) => Error::InvalidUtf8 { ty, source },
+        err => err,
+    }
+}
+
 /// Decodes a sequence.
 pub struct SeqDecoder<'de, 'a, 'r, R>
 where
@@ -341,6 +1387,16 @@ where
     decoder: &'a mut Decoder<'de, 'r, R>,
     /// The number of items in the sequence.
     len: usize,
+    /// Whether the items are struct fields, which may be cut short when
+    /// decoding tolerantly.
+    is_struct: bool,
+    /// The number of items of a tuple or struct, whose items are counted
+    /// when the input ends between two of them.
+    arity: Option<usize>,
+    /// The names of the struct's fields, or empty for anything else.
+    fields: &'static [&'static str],
+    /// The number of items decoded so far that took no bytes of input.
+    empty_items: usize,
 }
 
 impl<'de, 'a, 'r, R> SeqDecoder<'de, 'a, 'r, R>
@@ -349,7 +1405,69 @@ where
 {
     /// Creates a new sequence decoder.
     pub fn new(decoder: &'a mut Decoder<'de, 'r, R>, len: usize) -> Self {
-        Self { decoder, len }
+        Self {
+            decoder,
+            len,
+            is_struct: false,
+            arity: None,
+            fields: &[],
+            empty_items: 0,
+        }
+    }
+
+    /// Creates a new decoder for the elements of a tuple or tuple struct.
+    pub fn new_tuple(decoder: &'a mut Decoder<'de, 'r, R>, len: usize) -> Self {
+        Self {
+            decoder,
+            len,
+            is_struct: false,
+            arity: Some(len),
+            fields: &[],
+            empty_items: 0,
+        }
+    }
+
+    /// Creates a new decoder for the fields of a struct, with the given
+    /// names.
+    pub fn new_struct(
+        decoder: &'a mut Decoder<'de, 'r, R>,
+        len: usize,
+        fields: &'static [&'static str],
+    ) -> Self {
+        Self {
+            decoder,
+            len,
+            is_struct: true,
+            arity: Some(len),
+            fields,
+            empty_items: 0,
+        }
+    }
+
+    /// Counts an item of a sequence that took no bytes of input. Tuples and
+    /// structs have their number of items fixed by their type, so they aren't
+    /// counted.
+    fn count_empty_item(&mut self) -> crate::Result<()> {
+        if self.arity.is_some() {
+            return Ok(());
+        }
+
+        self.decoder.count_empty_item(&mut self.empty_items)
+    }
+
+    /// Attaches the position of the item at `index` to an error decoding it,
+    /// as the name of the field for a struct and the index for a tuple.
+    fn item_error(&self, index: usize, err: Error) -> Error {
+        match self.fields.get(index) {
+            Some(&field) if self.is_struct => Error::InStructField {
+                field,
+                source: Box::new(err),
+            },
+            _ => Error::InTupleElement {
+                index,
+                source: Box::new(err),
+            },
+        }
     }
 }
 
@@ -363,17 +1481,42 @@ where
     where
         T: DeserializeSeed<'de>,
     {
+        if self.is_struct && self.decoder.at_tolerated_end() {
+            self.len = 0;
+        }
+
         if self.len > 0 {
+            let at_end = self.decoder.at_end();
+            let bytes_read = self.decoder.bytes_read;
             self.len -= 1;
-            let value = seed.deserialize(&mut *self.decoder)?;
-            Ok(Some(value))
+
+            match (seed.deserialize(&mut *self.decoder), self.arity) {
+                (Ok(value), _) => {
+                    if self.decoder.bytes_read == bytes_read {
+                        self.count_empty_item()?;
+                    }
+
+                    Ok(Some(value))
+                }
+                // the input ended between two items, rather than within one
+                (Err(Error::UnexpectedEof | Error::TruncatedValue { .. }), Some(expected))
+                    if at_end =>
+                {
+                    Err(Error::IncompleteStruct {
+                        expected,
+                        read: expected - self.len - 1,
+                    })
+                }
+                (Err(err), Some(expected)) => Err(self.item_error(expected - self.len - 1, err)),
+                (Err(err), None) => Err(err),
+            }
         } else {
             Ok(None)
         }
     }
 
     fn size_hint(&self) -> Option<usize> {
-        Some(self.len)
+        self.decoder.cautious_len(self.len)
     }
 }
 
@@ -386,6 +1529,10 @@ where
     decoder: &'a mut Decoder<'de, 'r, R>,
     /// The number of items in the map.
     len: usize,
+    /// The number of bytes read before the current entry's key.
+    entry_start: usize,
+    /// The number of entries decoded so far that took no bytes of input.
+    empty_entries: usize,
 }
 
 impl<'de, 'a, 'r, R> MapDecoder<'de, 'a, 'r, R>
@@ -394,7 +1541,12 @@ where
 {
     /// Creates a new map decoder.
     pub fn new(decoder: &'a mut Decoder<'de, 'r, R>, len: usize) -> Self {
-        Self { decoder, len }
+        Self {
+            decoder,
+            len,
+            entry_start: 0,
+            empty_entries: 0,
+        }
     }
 }
 
@@ -410,6 +1562,7 @@ where
     {
         if self.len > 0 {
             self.len -= 1;
+            self.entry_start = self.decoder.bytes_read;
             let key = seed.deserialize(&mut *self.decoder)?;
             Ok(Some(key))
         } else {
@@ -422,11 +1575,16 @@ where
         V: DeserializeSeed<'de>,
     {
         let value = seed.deserialize(&mut *self.decoder)?;
+
+        if self.decoder.bytes_read == self.entry_start {
+            self.decoder.count_empty_item(&mut self.empty_entries)?;
+        }
+
         Ok(value)
     }
 
     fn size_hint(&self) -> Option<usize> {
-        Some(self.len)
+        self.decoder.cautious_len(self.len)
     }
 }
 
@@ -456,7 +1614,9 @@ where
     where
         V: DeserializeSeed<'de>,
     {
-        let variant_index = self.0 .0.read_n_array::<1>()?[0];
+        let variant_index = self.0.traced(ValueType::Enum, |decoder| {
+            decoder.read_u8_for(ValueType::Enum)
+        })?;
         let value: crate::Result<_> = seed.deserialize(variant_index.into_deserializer());
         Ok((value?, VariantDecoder::new(self.0)))
     }
@@ -484,7 +1644,7 @@ where
     type Error = Error;
 
     fn unit_variant(self) -> Result<(), Self::Error> {
-        Ok(())
+        self.0.expect_tag(Tag::Unit)
     }
 
     fn newtype_variant_seed<T>(self, seed: T) -> Result<T::Value, Self::Error>
@@ -498,6 +1658,7 @@ where
     where
         V: Visitor<'de>,
     {
+        self.0.read_variant_arity(len)?;
         self.0.deserialize_tuple(len, visitor)
     }
 
@@ -509,6 +1670,7 @@ where
     where
         V: Visitor<'de>,
     {
+        self.0.read_variant_arity(fields.len())?;
         self.0.deserialize_struct("", fields, visitor)
     }
 }
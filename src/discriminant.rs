@@ -0,0 +1,84 @@
+//! Serialization of fieldless enums by their explicit discriminants.
+//!
+//! Enum variants are normally identified on the wire by their position in
+//! the enum's declaration, as serde's variant index, regardless of any
+//! explicit discriminant given to them. Protocols with externally defined
+//! opcodes need the discriminant itself instead. This module serializes a
+//! fieldless enum implementing [`Discriminant`] as a single byte holding its
+//! discriminant, which takes the same space as a variant index, and rejects
+//! unknown discriminants when deserializing.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use unbin::discriminant::Discriminant;
+//!
+//! #[derive(Debug, Clone, Copy, PartialEq)]
+//! #[repr(u8)]
+//! enum Opcode {
+//!     Ping = 0x01,
+//!     Data = 0x10,
+//!     Close = 0xff,
+//! }
+//!
+//! impl Discriminant for Opcode {
+//!     fn discriminant(&self) -> u8 {
+//!         *self as u8
+//!     }
+//!
+//!     fn from_discriminant(discriminant: u8) -> Option<Self> {
+//!         [Self::Ping, Self::Data, Self::Close]
+//!             .into_iter()
+//!             .find(|opcode| opcode.discriminant() == discriminant)
+//!     }
+//! }
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Packet {
+//!     #[serde(with = "unbin::discriminant")]
+//!     opcode: Opcode,
+//!     payload: Vec<u8>,
+//! }
+//!
+//! let packet = Packet {
+//!     opcode: Opcode::Data,
+//!     payload: vec![7],
+//! };
+//! assert_eq!(unbin::serialize(&packet).unwrap(), [0x10, 1, 1, 7]);
+//! ```
+
+use serde::de::{Error, Unexpected};
+use serde::{Deserialize, Deserializer, Serializer};
+
+/// A fieldless enum, or other type, identified by a one-byte discriminant.
+pub trait Discriminant: Sized {
+    /// Returns the discriminant of the value.
+    fn discriminant(&self) -> u8;
+
+    /// Returns the value with the given discriminant, or `None` if there is
+    /// none.
+    fn from_discriminant(discriminant: u8) -> Option<Self>;
+}
+
+/// Serializes a value as its discriminant.
+pub fn serialize<T, S>(value: &T, serializer: S) -> Result<S::Ok, S::Error>
+where
+    T: Discriminant,
+    S: Serializer,
+{
+    serializer.serialize_u8(value.discriminant())
+}
+
+/// Deserializes a value from its discriminant, failing if no value has it.
+pub fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: Discriminant,
+    D: Deserializer<'de>,
+{
+    let discriminant = u8::deserialize(deserializer)?;
+    T::from_discriminant(discriminant).ok_or_else(|| {
+        D::Error::invalid_value(
+            Unexpected::Unsigned(discriminant.into()),
+            &"a known discriminant",
+        )
+    })
+}
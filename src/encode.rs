@@ -1,139 +1,691 @@
 //! Encoding implementation.
 
+use crate::config::{CharEncoding, Config, FloatEncoding, IntEncoding, LengthEncoding, Options};
+use crate::tag::Tag;
 use crate::util::*;
 use crate::write::Write;
-use crate::Error;
+use crate::{Error, ValueType};
 use serde::ser::{
     SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
     SerializeTupleStruct, SerializeTupleVariant,
 };
 use serde::{Serialize, Serializer};
+use std::hash::{Hash, Hasher};
+use std::marker::PhantomData;
+use std::{cmp, fmt, io};
+
+/// Where an encoder keeps its writer: borrowed, as `&mut W`, by an encoder
+/// constructed with [`Encoder::new`], or in an [`OwnedWriter`] by one
+/// constructed with [`Encoder::owned`]. This trait is sealed.
+pub trait WriterSlot<W>: sealed::Sealed {
+    /// Returns a mutable reference to the writer.
+    fn writer(&mut self) -> &mut W;
+}
+
+/// A writer owned by an encoder constructed with [`Encoder::owned`], and
+/// handed back by [`Encoder::into_writer`].
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OwnedWriter<W>(W);
+
+impl<W> WriterSlot<W> for &mut W {
+    fn writer(&mut self) -> &mut W {
+        self
+    }
+}
+
+impl<W> WriterSlot<W> for OwnedWriter<W> {
+    fn writer(&mut self) -> &mut W {
+        &mut self.0
+    }
+}
+
+/// Keeps [`WriterSlot`] from being implemented outside of this crate.
+mod sealed {
+    /// The supertrait sealing [`WriterSlot`](super::WriterSlot).
+    pub trait Sealed {}
+
+    impl<W> Sealed for &mut W {}
+
+    impl<W> Sealed for super::OwnedWriter<W> {}
+}
+
+/// A caller's check for whether encoding should be cancelled.
+#[derive(Clone, Copy)]
+struct CancelCheck<'w>(&'w (dyn Fn() -> bool + Sync));
+
+impl<'w> CancelCheck<'w> {
+    /// Returns the address of the check, which tells checks apart.
+    fn address(self) -> *const () {
+        self.0 as *const (dyn Fn() -> bool + Sync) as *const ()
+    }
+}
+
+impl<'w> fmt::Debug for CancelCheck<'w> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CancelCheck").field(&self.address()).finish()
+    }
+}
+
+impl<'w> PartialEq for CancelCheck<'w> {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl<'w> Eq for CancelCheck<'w> {}
+
+impl<'w> PartialOrd for CancelCheck<'w> {
+    fn partial_cmp(&self, other: &Self) -> Option<cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<'w> Ord for CancelCheck<'w> {
+    fn cmp(&self, other: &Self) -> cmp::Ordering {
+        self.address().cmp(&other.address())
+    }
+}
+
+impl<'w> Hash for CancelCheck<'w> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address().hash(state);
+    }
+}
 
-/// The binary encoder.
+/// The binary encoder. The writer is borrowed by default, or owned with
+/// [`Encoder::owned`], as tracked by `S`.
 #[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
-pub struct Encoder<'w, W>(&'w mut W)
+pub struct Encoder<'w, W, S = &'w mut W>
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>,
+{
+    /// The underlying writer.
+    writer: S,
+    /// The encoding configuration.
+    config: Config,
+    /// The offsets and types of the length fields written so far, if they
+    /// are being recorded.
+    length_fields: Option<Vec<(u64, ValueType)>>,
+    /// The caller's check for whether to cancel, polled before every
+    /// element, map entry and field.
+    cancel_check: Option<CancelCheck<'w>>,
+    /// The type of the writer kept in `writer`.
+    marker: PhantomData<W>,
+}
 
 impl<'w, W> Encoder<'w, W>
 where
     W: Write,
 {
-    /// Constructs a new binary encoder.
+    /// Constructs a new binary encoder that borrows the given writer.
     pub fn new(writer: &'w mut W) -> Self {
-        Self(writer)
+        Self {
+            writer,
+            config: Config::default(),
+            length_fields: None,
+            cancel_check: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<'w, W, S> Encoder<'w, W, S>
+where
+    W: Write,
+    S: WriterSlot<W>,
+    S: WriterSlot<W>,
+{
+    /// Sets how the lengths of strings, byte arrays, sequences and maps are
+    /// encoded. Defaults to [`LengthEncoding::Prefixed`].
+    pub fn with_length_encoding(mut self, length_encoding: LengthEncoding) -> Self {
+        self.config.length_encoding = length_encoding;
+        self
+    }
+
+    /// Sets how integers wider than a byte are encoded. Defaults to
+    /// [`IntEncoding::Fixed`].
+    pub fn with_int_encoding(mut self, int_encoding: IntEncoding) -> Self {
+        self.config.int_encoding = int_encoding;
+        self
+    }
+
+    /// Sets how `char` values are encoded. Defaults to
+    /// [`CharEncoding::Utf8`].
+    pub fn with_char_encoding(mut self, char_encoding: CharEncoding) -> Self {
+        self.config.char_encoding = char_encoding;
+        self
+    }
+
+    /// Sets how `f32` and `f64` values are encoded. Defaults to
+    /// [`FloatEncoding::Fixed`].
+    pub fn with_float_encoding(mut self, float_encoding: FloatEncoding) -> Self {
+        self.config.float_encoding = float_encoding;
+        self
+    }
+
+    /// Sets whether to write a sentinel byte after every struct and sequence,
+    /// and the number of fields after the index of every tuple and struct
+    /// variant, so that a decoder in framed mode can detect when it falls out
+    /// of step with the data. This is a debugging aid, and must match the
+    /// decoder's setting. Defaults to `false`.
+    pub fn with_framed(mut self, framed: bool) -> Self {
+        self.config.framed = framed;
+        self
+    }
+
+    /// Sets whether to write a type tag before every value, making the
+    /// encoding self-describing so that it can be decoded without knowing
+    /// its type up front, such as into a `serde_json::Value`. Tuples and
+    /// structs are also prefixed with their length in this mode. This must
+    /// match the decoder's setting. Defaults to `false`.
+    pub fn with_tagged(mut self, tagged: bool) -> Self {
+        self.config.tagged = tagged;
+        self
+    }
+
+    /// Sets whether to reject NaN and infinite floating point values with
+    /// [`Error::NonFiniteFloat`], so that they never enter the stream, for
+    /// producers feeding systems that can't represent them. Defaults to
+    /// `false`.
+    pub fn with_finite_floats(mut self, finite_floats: bool) -> Self {
+        self.config.finite_floats = finite_floats;
+        self
+    }
+
+    /// Sets whether to follow every length prefix with a one-byte checksum of
+    /// its bytes, all of them XORed together, so that a decoder can catch a
+    /// corrupted length before acting on it. This must match the decoder's
+    /// setting. Defaults to `false`.
+    pub fn with_length_checksums(mut self, length_checksums: bool) -> Self {
+        self.config.length_checksums = length_checksums;
+        self
+    }
+
+    /// Sets whether to flush the writer at the end of every sequence and map,
+    /// nested ones included, so that each collection is pushed out promptly
+    /// when streaming to a socket or similar. Flushing after every collection
+    /// can be costly, so this is best left off for buffered output. Defaults
+    /// to `false`.
+    pub fn with_flush_on_collection_end(mut self, flush_on_collection_end: bool) -> Self {
+        self.config.flush_on_collection_end = flush_on_collection_end;
+        self
+    }
+
+    /// Applies every setting from the given options at once, replacing any
+    /// set before.
+    pub fn with_options(mut self, options: Options) -> Self {
+        self.config = options.config;
+        self
+    }
+
+    /// Sets a check polled before every sequence element, map entry and
+    /// struct field, nested ones included, which cancels encoding with
+    /// [`Error::Cancelled`] once it returns `true`. Whatever was written
+    /// before the cancellation remains in the writer. The check must be
+    /// `Sync`, as is a check of an atomic flag or a deadline, so that the
+    /// encoder can still be shared across threads.
+    pub fn with_cancel_check(mut self, should_cancel: &'w (dyn Fn() -> bool + Sync)) -> Self {
+        self.cancel_check = Some(CancelCheck(should_cancel));
+        self
     }
 
     /// Returns a mutable reference to the underlying writer.
     pub fn writer(&mut self) -> &mut W {
-        self.0
+        self.writer.writer()
+    }
+
+    /// Writes the frame sentinel in framed mode, or nothing otherwise.
+    fn write_frame_end(&mut self) -> crate::Result<()> {
+        if self.config.framed {
+            self.writer().write_u8(FRAME_SENTINEL)?;
+        }
+
+        Ok(())
+    }
+
+    /// Flushes the writer at the end of a sequence or map, if configured to,
+    /// or does nothing otherwise.
+    fn flush_collection_end(&mut self) -> crate::Result<()> {
+        if self.config.flush_on_collection_end {
+            self.writer().flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Fails with [`Error::Cancelled`] if the caller's cancellation check
+    /// says to cancel, or does nothing otherwise.
+    fn check_cancelled(&self) -> crate::Result<()> {
+        match self.cancel_check {
+            Some(CancelCheck(should_cancel)) if should_cancel() => Err(Error::Cancelled),
+            _ => Ok(()),
+        }
+    }
+
+    /// Rejects a non-finite float of type `ty` if only finite values are
+    /// allowed.
+    fn check_finite(&self, ty: ValueType, value: f64) -> crate::Result<()> {
+        if self.config.finite_floats && !value.is_finite() {
+            return Err(Error::NonFiniteFloat { ty, value });
+        }
+
+        Ok(())
+    }
+
+    /// Writes a type tag in tagged mode, or nothing otherwise.
+    fn write_tag(&mut self, tag: Tag) -> crate::Result<()> {
+        if self.config.tagged {
+            self.writer().write_u8(tag as u8)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the index of an enum variant, preceded by its tag in tagged
+    /// mode. Only the first 256 variants of an enum can be encoded.
+    fn write_variant_index(&mut self, name: &'static str, variant_index: u32) -> crate::Result<()> {
+        if variant_index < 256 {
+            self.write_tag(Tag::Enum)?;
+            self.writer().write_u8(variant_index as u8)
+        } else {
+            Err(Error::TooManyVariants(name))
+        }
+    }
+
+    /// Writes the number of fields of a tuple or struct variant after its
+    /// index in framed mode, so that a corrupted index pointing to a variant
+    /// with a different number of fields is caught. Tagged mode already
+    /// writes this as the length of the body, so nothing is written then.
+    fn write_variant_arity(&mut self, len: usize) -> crate::Result<()> {
+        if self.config.framed && !self.config.tagged {
+            self.write_len(len, ValueType::Enum)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the start of a tuple, struct, or tuple or struct variant body.
+    /// These have no prefix unless tagged, when they are written as a tagged
+    /// sequence of `len` elements.
+    fn write_fields_start(&mut self, len: usize, ty: ValueType) -> crate::Result<()> {
+        if self.config.tagged {
+            self.write_tag(Tag::Seq)?;
+            self.write_len(len, ty)?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes the end of a tuple, tuple struct or tuple variant body, which
+    /// is framed like a sequence in tagged mode.
+    fn write_fields_end(&mut self) -> crate::Result<()> {
+        if self.config.tagged {
+            self.write_frame_end()?;
+        }
+
+        Ok(())
+    }
+
+    /// Writes an integer as a varint, from a stack buffer.
+    fn write_int_varint(&mut self, value: u128) -> crate::Result<()> {
+        let mut buf = [0; MAX_INT_VARINT_BYTES];
+        let len = encode_int_varint(value, &mut buf);
+        self.writer().write_all(&buf[..len])
+    }
+
+    /// Writes the length of a string, byte array, sequence or map.
+    fn write_len(&mut self, len: usize, ty: ValueType) -> crate::Result<()> {
+        if self.length_fields.is_some() {
+            if let Some(offset) = self.writer().bytes_written() {
+                if let Some(length_fields) = &mut self.length_fields {
+                    length_fields.push((offset as u64, ty));
+                }
+            }
+        }
+
+        // the codec writes straight through to the writer, so that nothing
+        // is allocated, while the checksum is computed along the way
+        let codec = self.config.length_encoding.codec();
+        let mut writer = ChecksumWriter {
+            writer: self.writer(),
+            checksum: 0,
+        };
+        codec.encode_len(len, &mut writer)?;
+        let checksum = writer.checksum;
+
+        if self.config.length_checksums {
+            self.writer().write_u8(checksum)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<W> Encoder<'static, W, OwnedWriter<W>>
+where
+    W: Write,
+{
+    /// Constructs a new binary encoder that takes ownership of the given
+    /// writer. The writer can be recovered with [`Encoder::into_writer`].
+    pub fn owned(writer: W) -> Self {
+        Self {
+            writer: OwnedWriter(writer),
+            config: Config::default(),
+            length_fields: None,
+            cancel_check: None,
+            marker: PhantomData,
+        }
     }
+
+    /// Consumes the encoder, returning the writer it owns.
+    pub fn into_writer(self) -> W {
+        self.writer.0
+    }
+}
+
+/// A [`Write`]r that computes the checksum of the bytes of an encoded length
+/// as they pass through, matching [`xor_checksum`].
+struct ChecksumWriter<'a, W> {
+    /// The underlying writer.
+    writer: &'a mut W,
+    /// The checksum of the bytes written so far.
+    checksum: u8,
+}
+
+impl<'a, W> Write for ChecksumWriter<'a, W>
+where
+    W: Write,
+{
+    fn write_all(&mut self, buf: &[u8]) -> crate::Result<()> {
+        self.writer.write_all(buf)?;
+        self.checksum ^= xor_checksum(buf);
+        Ok(())
+    }
+
+    fn write_u8(&mut self, byte: u8) -> crate::Result<()> {
+        self.writer.write_u8(byte)?;
+        self.checksum ^= byte;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A [`Write`]r that counts the bytes passed through to an [`io::Write`]r,
+/// so that an encoder can tell where each value starts.
+struct CountingWriter<'w, W> {
+    /// The underlying writer.
+    writer: &'w mut W,
+    /// The number of bytes written so far.
+    count: usize,
+}
+
+impl<'w, W> Write for CountingWriter<'w, W>
+where
+    W: io::Write,
+{
+    fn write_all(&mut self, buf: &[u8]) -> crate::Result<()> {
+        io::Write::write_all(self.writer, buf)?;
+        self.count += buf.len();
+        Ok(())
+    }
+
+    fn flush(&mut self) -> crate::Result<()> {
+        Ok(io::Write::flush(self.writer)?)
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        Some(self.count)
+    }
+}
+
+/// An encoder for seekable writers that records the stream position and type
+/// of every length field it writes, so that lengths can be patched after the
+/// variable content following them has been written.
+///
+/// A patched length must encode to the same number of bytes as the original,
+/// since the built-in [`LengthEncoding`]s don't write lengths at a fixed
+/// width.
+#[derive(Debug)]
+pub struct IndexedEncoder<'w, W>
+where
+    W: io::Write + io::Seek,
+{
+    /// The underlying writer.
+    writer: &'w mut W,
+    /// The encoding configuration.
+    config: Config,
 }
 
-impl<'a, 'w, W> Serializer for &'a mut Encoder<'w, W>
+impl<'w, W> IndexedEncoder<'w, W>
+where
+    W: io::Write + io::Seek,
+{
+    /// Constructs a new indexed encoder that borrows the given writer.
+    pub fn new(writer: &'w mut W) -> Self {
+        Self {
+            writer,
+            config: Config::default(),
+        }
+    }
+
+    /// Sets how the lengths of strings, byte arrays, sequences and maps are
+    /// encoded. Defaults to [`LengthEncoding::Prefixed`].
+    pub fn with_length_encoding(mut self, length_encoding: LengthEncoding) -> Self {
+        self.config.length_encoding = length_encoding;
+        self
+    }
+
+    /// Sets how integers wider than a byte are encoded. Defaults to
+    /// [`IntEncoding::Fixed`].
+    pub fn with_int_encoding(mut self, int_encoding: IntEncoding) -> Self {
+        self.config.int_encoding = int_encoding;
+        self
+    }
+
+    /// Serializes a value at the writer's current position, returning the
+    /// stream position of each length field written, in order, along with the
+    /// type of value it belongs to.
+    pub fn serialize<T>(&mut self, value: &T) -> crate::Result<Vec<(u64, ValueType)>>
+    where
+        T: ?Sized + Serialize,
+    {
+        let start = self.writer.stream_position()?;
+        let mut writer = CountingWriter {
+            writer: &mut *self.writer,
+            count: 0,
+        };
+        let mut encoder = Encoder {
+            writer: &mut writer,
+            config: self.config,
+            length_fields: Some(Vec::new()),
+            cancel_check: None,
+            marker: PhantomData,
+        };
+        value.serialize(&mut encoder)?;
+
+        Ok(encoder
+            .length_fields
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(offset, ty)| (start + offset, ty))
+            .collect())
+    }
+}
+
+impl<'a, 'w, W, S> Serializer for &'a mut Encoder<'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
-    type SerializeSeq = SeqEncoder<'a, 'w, W>;
-    type SerializeTuple = TupleEncoder<'a, 'w, W>;
-    type SerializeTupleStruct = TupleStructEncoder<'a, 'w, W>;
-    type SerializeTupleVariant = TupleVariantEncoder<'a, 'w, W>;
-    type SerializeMap = MapEncoder<'a, 'w, W>;
-    type SerializeStruct = StructEncoder<'a, 'w, W>;
-    type SerializeStructVariant = StructVariantEncoder<'a, 'w, W>;
+    type SerializeSeq = SeqEncoder<'a, 'w, W, S>;
+    type SerializeTuple = TupleEncoder<'a, 'w, W, S>;
+    type SerializeTupleStruct = TupleStructEncoder<'a, 'w, W, S>;
+    type SerializeTupleVariant = TupleVariantEncoder<'a, 'w, W, S>;
+    type SerializeMap = MapEncoder<'a, 'w, W, S>;
+    type SerializeStruct = StructEncoder<'a, 'w, W, S>;
+    type SerializeStructVariant = StructVariantEncoder<'a, 'w, W, S>;
 
     fn serialize_bool(self, v: bool) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&[v as u8])?;
+        self.write_tag(Tag::Bool)?;
+        self.writer().write_u8(v as u8)?;
         Ok(())
     }
 
     fn serialize_i8(self, v: i8) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
+        self.write_tag(Tag::I8)?;
+        self.writer().write_all(&v.to_be_bytes())?;
         Ok(())
     }
 
     fn serialize_i16(self, v: i16) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::I16)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(zigzag_encode(v.into())),
+        }
     }
 
     fn serialize_i32(self, v: i32) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::I32)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(zigzag_encode(v.into())),
+        }
     }
 
     fn serialize_i64(self, v: i64) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::I64)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(zigzag_encode(v.into())),
+        }
     }
 
     fn serialize_i128(self, v: i128) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::I128)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(zigzag_encode(v)),
+        }
     }
 
     fn serialize_u8(self, v: u8) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&[v])?;
+        self.write_tag(Tag::U8)?;
+        self.writer().write_u8(v)?;
         Ok(())
     }
 
     fn serialize_u16(self, v: u16) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::U16)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(v.into()),
+        }
     }
 
     fn serialize_u32(self, v: u32) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::U32)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(v.into()),
+        }
     }
 
     fn serialize_u64(self, v: u64) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::U64)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(v.into()),
+        }
     }
 
     fn serialize_u128(self, v: u128) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
-        Ok(())
+        self.write_tag(Tag::U128)?;
+        match self.config.int_encoding {
+            IntEncoding::Fixed => self.writer().write_all(&v.to_be_bytes()),
+            IntEncoding::Varint => self.write_int_varint(v),
+        }
     }
 
     fn serialize_f32(self, v: f32) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
+        self.check_finite(ValueType::F32, v.into())?;
+        self.write_tag(Tag::F32)?;
+
+        if self.config.float_encoding == FloatEncoding::Compact {
+            return match compact_f32_width(v) {
+                COMPACT_FLOAT_I8 => self.writer().write_all(&[COMPACT_FLOAT_I8, v as i8 as u8]),
+                width => {
+                    self.writer().write_u8(width)?;
+                    self.writer().write_all(&v.to_be_bytes())
+                }
+            };
+        }
+
+        self.writer().write_all(&v.to_be_bytes())?;
         Ok(())
     }
 
     fn serialize_f64(self, v: f64) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&v.to_be_bytes())?;
+        self.check_finite(ValueType::F64, v)?;
+        self.write_tag(Tag::F64)?;
+
+        if self.config.float_encoding == FloatEncoding::Compact {
+            return match compact_f64_width(v) {
+                COMPACT_FLOAT_I8 => self.writer().write_all(&[COMPACT_FLOAT_I8, v as i8 as u8]),
+                COMPACT_FLOAT_F32 => {
+                    self.writer().write_u8(COMPACT_FLOAT_F32)?;
+                    self.writer().write_all(&(v as f32).to_be_bytes())
+                }
+                width => {
+                    self.writer().write_u8(width)?;
+                    self.writer().write_all(&v.to_be_bytes())
+                }
+            };
+        }
+
+        self.writer().write_all(&v.to_be_bytes())?;
         Ok(())
     }
 
     fn serialize_char(self, v: char) -> Result<Self::Ok, Self::Error> {
+        self.write_tag(Tag::Char)?;
+
+        if self.config.char_encoding == CharEncoding::Fixed {
+            self.writer().write_all(&u32::from(v).to_be_bytes())?;
+            return Ok(());
+        }
+
         let len = v.len_utf8();
         let encoded_len = encode_len_small(len);
         let mut bytes = [encoded_len; 5];
         v.encode_utf8(&mut bytes[1..]);
-        self.0.write_all(&bytes[..len + 1])?;
+        self.writer().write_all(&bytes[..len + 1])?;
         Ok(())
     }
 
     fn serialize_str(self, v: &str) -> Result<Self::Ok, Self::Error> {
-        let mut bytes = encode_len_large(v.len());
-        bytes.extend_from_slice(v.as_bytes());
-        self.0.write_all(&bytes)?;
+        self.write_tag(Tag::Str)?;
+        self.write_len(v.len(), ValueType::Str)?;
+        self.writer().write_all(v.as_bytes())?;
         Ok(())
     }
 
     fn serialize_bytes(self, v: &[u8]) -> Result<Self::Ok, Self::Error> {
-        let mut bytes = encode_len_large(v.len());
-        bytes.extend_from_slice(v);
-        self.0.write_all(&bytes)?;
+        self.write_tag(Tag::Bytes)?;
+        self.write_len(v.len(), ValueType::Bytes)?;
+        self.writer().write_all(v)?;
         Ok(())
     }
 
     fn serialize_none(self) -> Result<Self::Ok, Self::Error> {
-        self.0.write_all(&[0])?;
+        self.write_tag(Tag::Option)?;
+        self.writer().write_u8(0)?;
         Ok(())
     }
 
@@ -141,17 +693,18 @@ where
     where
         T: ?Sized + Serialize,
     {
-        self.0.write_all(&[1])?;
+        self.write_tag(Tag::Option)?;
+        self.writer().write_u8(1)?;
         value.serialize(self)?;
         Ok(())
     }
 
     fn serialize_unit(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.write_tag(Tag::Unit)
     }
 
     fn serialize_unit_struct(self, _name: &'static str) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.write_tag(Tag::Unit)
     }
 
     fn serialize_unit_variant(
@@ -160,12 +713,8 @@ where
         variant_index: u32,
         _variant: &'static str,
     ) -> Result<Self::Ok, Self::Error> {
-        if variant_index < 256 {
-            self.0.write_all(&(variant_index as u8).to_be_bytes())?;
-            Ok(())
-        } else {
-            Err(Error::TooManyVariants(name))
-        }
+        self.write_variant_index(name, variant_index)?;
+        self.write_tag(Tag::Unit)
     }
 
     fn serialize_newtype_struct<T>(
@@ -189,13 +738,8 @@ where
     where
         T: ?Sized + Serialize,
     {
-        if variant_index < 256 {
-            self.0.write_all(&(variant_index as u8).to_be_bytes())?;
-            value.serialize(self)?;
-            Ok(())
-        } else {
-            Err(Error::TooManyVariants(name))
-        }
+        self.write_variant_index(name, variant_index)?;
+        value.serialize(self)
     }
 
     fn serialize_seq(self, len: Option<usize>) -> Result<Self::SerializeSeq, Self::Error> {
@@ -205,15 +749,17 @@ where
         }
     }
 
-    fn serialize_tuple(self, _len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+    fn serialize_tuple(self, len: usize) -> Result<Self::SerializeTuple, Self::Error> {
+        self.write_fields_start(len, ValueType::Tuple)?;
         Ok(TupleEncoder::new(self))
     }
 
     fn serialize_tuple_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleStruct, Self::Error> {
+        self.write_fields_start(len, ValueType::TupleStruct)?;
         Ok(TupleStructEncoder::new(self))
     }
 
@@ -222,9 +768,12 @@ where
         name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeTupleVariant, Self::Error> {
-        TupleVariantEncoder::new(self, name, variant_index)
+        let encoder = TupleVariantEncoder::new(self, name, variant_index)?;
+        encoder.0.write_variant_arity(len)?;
+        encoder.0.write_fields_start(len, ValueType::Enum)?;
+        Ok(encoder)
     }
 
     fn serialize_map(self, len: Option<usize>) -> Result<Self::SerializeMap, Self::Error> {
@@ -237,9 +786,10 @@ where
     fn serialize_struct(
         self,
         _name: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStruct, Self::Error> {
-        Ok(StructEncoder::new(self))
+        self.write_fields_start(len, ValueType::Struct)?;
+        Ok(StructEncoder::new(self, len))
     }
 
     fn serialize_struct_variant(
@@ -247,9 +797,12 @@ where
         name: &'static str,
         variant_index: u32,
         _variant: &'static str,
-        _len: usize,
+        len: usize,
     ) -> Result<Self::SerializeStructVariant, Self::Error> {
-        StructVariantEncoder::new(self, name, variant_index)
+        let encoder = StructVariantEncoder::new(self, name, variant_index, len)?;
+        encoder.encoder.write_variant_arity(len)?;
+        encoder.encoder.write_fields_start(len, ValueType::Enum)?;
+        Ok(encoder)
     }
 
     fn is_human_readable(&self) -> bool {
@@ -258,25 +811,28 @@ where
 }
 
 /// Encodes a sequence to binary.
-pub struct SeqEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct SeqEncoder<'a, 'w, W, S>(&'a mut Encoder<'w, W, S>)
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>;
 
-impl<'a, 'w, W> SeqEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SeqEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     /// Creates a new sequence encoder.
-    pub fn new(encoder: &'a mut Encoder<'w, W>, len: usize) -> crate::Result<Self> {
-        let encoded_len = encode_len_large(len);
-        encoder.0.write_all(&encoded_len)?;
+    pub fn new(encoder: &'a mut Encoder<'w, W, S>, len: usize) -> crate::Result<Self> {
+        encoder.write_tag(Tag::Seq)?;
+        encoder.write_len(len, ValueType::Seq)?;
         Ok(Self(encoder))
     }
 }
 
-impl<'a, 'w, W> SerializeSeq for SeqEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeSeq for SeqEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -285,32 +841,37 @@ where
     where
         T: ?Sized + Serialize,
     {
+        self.0.check_cancelled()?;
         value.serialize(&mut *self.0)
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.0.write_frame_end()?;
+        self.0.flush_collection_end()
     }
 }
 
 /// Encodes a tuple to binary.
-pub struct TupleEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct TupleEncoder<'a, 'w, W, S>(&'a mut Encoder<'w, W, S>)
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>;
 
-impl<'a, 'w, W> TupleEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> TupleEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     /// Creates a new tuple encoder.
-    pub fn new(encoder: &'a mut Encoder<'w, W>) -> Self {
+    pub fn new(encoder: &'a mut Encoder<'w, W, S>) -> Self {
         Self(encoder)
     }
 }
 
-impl<'a, 'w, W> SerializeTuple for TupleEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeTuple for TupleEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -323,28 +884,31 @@ where
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.0.write_fields_end()
     }
 }
 
 /// Encodes a tuple struct to binary.
-pub struct TupleStructEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct TupleStructEncoder<'a, 'w, W, S>(&'a mut Encoder<'w, W, S>)
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>;
 
-impl<'a, 'w, W> TupleStructEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> TupleStructEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     /// Creates a new tuple struct encoder.
-    pub fn new(encoder: &'a mut Encoder<'w, W>) -> Self {
+    pub fn new(encoder: &'a mut Encoder<'w, W, S>) -> Self {
         Self(encoder)
     }
 }
 
-impl<'a, 'w, W> SerializeTupleStruct for TupleStructEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeTupleStruct for TupleStructEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -357,37 +921,36 @@ where
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.0.write_fields_end()
     }
 }
 
 /// Encodes a tuple variant to binary.
-pub struct TupleVariantEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct TupleVariantEncoder<'a, 'w, W, S>(&'a mut Encoder<'w, W, S>)
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>;
 
-impl<'a, 'w, W> TupleVariantEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> TupleVariantEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     /// Creates a new tuple variant encoder.
     pub fn new(
-        encoder: &'a mut Encoder<'w, W>,
+        encoder: &'a mut Encoder<'w, W, S>,
         name: &'static str,
         variant_index: u32,
     ) -> crate::Result<Self> {
-        if variant_index < 256 {
-            encoder.0.write_all(&(variant_index as u8).to_be_bytes())?;
-            Ok(Self(encoder))
-        } else {
-            Err(Error::TooManyVariants(name))
-        }
+        encoder.write_variant_index(name, variant_index)?;
+        Ok(Self(encoder))
     }
 }
 
-impl<'a, 'w, W> SerializeTupleVariant for TupleVariantEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeTupleVariant for TupleVariantEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -400,30 +963,33 @@ where
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.0.write_fields_end()
     }
 }
 
 /// Encodes a map to binary.
-pub struct MapEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct MapEncoder<'a, 'w, W, S>(&'a mut Encoder<'w, W, S>)
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>;
 
-impl<'a, 'w, W> MapEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> MapEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     /// Creates a new map encoder.
-    pub fn new(encoder: &'a mut Encoder<'w, W>, len: usize) -> crate::Result<Self> {
-        let encoded_len = encode_len_large(len);
-        encoder.0.write_all(&encoded_len)?;
+    pub fn new(encoder: &'a mut Encoder<'w, W, S>, len: usize) -> crate::Result<Self> {
+        encoder.write_tag(Tag::Map)?;
+        encoder.write_len(len, ValueType::Map)?;
         Ok(Self(encoder))
     }
 }
 
-impl<'a, 'w, W> SerializeMap for MapEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeMap for MapEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -432,6 +998,7 @@ where
     where
         T: ?Sized + Serialize,
     {
+        self.0.check_cancelled()?;
         key.serialize(&mut *self.0)
     }
 
@@ -443,28 +1010,43 @@ where
     }
 
     fn end(self) -> Result<Self::Ok, Self::Error> {
-        Ok(())
+        self.0.flush_collection_end()
     }
 }
 
 /// Encodes a struct to binary.
-pub struct StructEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct StructEncoder<'a, 'w, W, S>
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>,
+{
+    /// The underlying encoder.
+    encoder: &'a mut Encoder<'w, W, S>,
+    /// The number of fields in the struct, including any skipped.
+    expected: usize,
+    /// The number of fields serialized so far.
+    serialized: usize,
+}
 
-impl<'a, 'w, W> StructEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> StructEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
-    /// Creates a new struct encoder.
-    pub fn new(encoder: &'a mut Encoder<'w, W>) -> Self {
-        Self(encoder)
+    /// Creates a new encoder for a struct of `len` fields.
+    pub fn new(encoder: &'a mut Encoder<'w, W, S>, len: usize) -> Self {
+        Self {
+            encoder,
+            expected: len,
+            serialized: 0,
+        }
     }
 }
 
-impl<'a, 'w, W> SerializeStruct for StructEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeStruct for StructEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -473,41 +1055,61 @@ where
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut *self.0)
+        self.encoder.check_cancelled()?;
+        self.serialized += 1;
+        value.serialize(&mut *self.encoder)
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn skip_field(&mut self, _key: &'static str) -> Result<(), Self::Error> {
+        self.expected += 1;
         Ok(())
     }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        check_field_count(self.expected, self.serialized)?;
+        self.encoder.write_frame_end()
+    }
 }
 
 /// Encodes a struct variant to binary.
-pub struct StructVariantEncoder<'a, 'w, W>(&'a mut Encoder<'w, W>)
+pub struct StructVariantEncoder<'a, 'w, W, S>
 where
-    W: Write;
+    W: Write,
+    S: WriterSlot<W>,
+{
+    /// The underlying encoder.
+    encoder: &'a mut Encoder<'w, W, S>,
+    /// The number of fields in the variant, including any skipped.
+    expected: usize,
+    /// The number of fields serialized so far.
+    serialized: usize,
+}
 
-impl<'a, 'w, W> StructVariantEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> StructVariantEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
-    /// Creates a new struct variant encoder.
+    /// Creates a new encoder for a struct variant of `len` fields.
     pub fn new(
-        encoder: &'a mut Encoder<'w, W>,
+        encoder: &'a mut Encoder<'w, W, S>,
         name: &'static str,
         variant_index: u32,
+        len: usize,
     ) -> crate::Result<Self> {
-        if variant_index < 256 {
-            encoder.0.write_all(&(variant_index as u8).to_be_bytes())?;
-            Ok(Self(encoder))
-        } else {
-            Err(Error::TooManyVariants(name))
-        }
+        encoder.write_variant_index(name, variant_index)?;
+        Ok(Self {
+            encoder,
+            expected: len,
+            serialized: 0,
+        })
     }
 }
 
-impl<'a, 'w, W> SerializeStructVariant for StructVariantEncoder<'a, 'w, W>
+impl<'a, 'w, W, S> SerializeStructVariant for StructVariantEncoder<'a, 'w, W, S>
 where
     W: Write,
+    S: WriterSlot<W>,
 {
     type Ok = ();
     type Error = Error;
@@ -516,10 +1118,32 @@ where
     where
         T: ?Sized + Serialize,
     {
-        value.serialize(&mut *self.0)
+        self.encoder.check_cancelled()?;
+        self.serialized += 1;
+        value.serialize(&mut *self.encoder)
     }
 
-    fn end(self) -> Result<Self::Ok, Self::Error> {
+    fn skip_field(&mut self, _key: &'static str) -> Result<(), Self::Error> {
+        self.expected += 1;
         Ok(())
     }
+
+    fn end(self) -> Result<Self::Ok, Self::Error> {
+        check_field_count(self.expected, self.serialized)?;
+        self.encoder.write_frame_end()
+    }
+}
+
+/// Checks that every field of a struct was serialized. Fields are written
+/// positionally, so a field left out, such as by `skip_serializing_if`, would
+/// shift every later field out of place when decoding.
+fn check_field_count(expected: usize, serialized: usize) -> crate::Result<()> {
+    if serialized != expected {
+        return Err(Error::FieldCountMismatch {
+            expected,
+            serialized,
+        });
+    }
+
+    Ok(())
 }
@@ -1,5 +1,10 @@
 //! Library error types.
 
+use crate::util::expected_is;
+use serde::de::{Expected, Unexpected};
+use std::array::TryFromSliceError;
+use std::borrow::Cow;
+use std::fmt;
 use std::io;
 use thiserror::Error;
 
@@ -37,6 +42,108 @@ pub enum ValueType {
     Enum,
 }
 
+impl ValueType {
+    /// Returns the type of value decoded by the [`Deserializer`] method with
+    /// the given name, e.g. [`ValueType::U32`] for `"deserialize_u32"`, or
+    /// `None` if there is no such method or it doesn't decode a single type of
+    /// value, as with `deserialize_any` and `deserialize_identifier`.
+    ///
+    /// [`Deserializer`]: serde::Deserializer
+    pub fn from_deserialize_method(method: &str) -> Option<Self> {
+        match method {
+            "deserialize_bool" => Some(Self::Bool),
+            "deserialize_i8" => Some(Self::I8),
+            "deserialize_i16" => Some(Self::I16),
+            "deserialize_i32" => Some(Self::I32),
+            "deserialize_i64" => Some(Self::I64),
+            "deserialize_i128" => Some(Self::I128),
+            "deserialize_u8" => Some(Self::U8),
+            "deserialize_u16" => Some(Self::U16),
+            "deserialize_u32" => Some(Self::U32),
+            "deserialize_u64" => Some(Self::U64),
+            "deserialize_u128" => Some(Self::U128),
+            "deserialize_f32" => Some(Self::F32),
+            "deserialize_f64" => Some(Self::F64),
+            "deserialize_char" => Some(Self::Char),
+            "deserialize_str" => Some(Self::Str),
+            "deserialize_string" => Some(Self::String),
+            "deserialize_bytes" => Some(Self::Bytes),
+            "deserialize_byte_buf" => Some(Self::ByteBuf),
+            "deserialize_option" => Some(Self::Option),
+            "deserialize_unit" => Some(Self::Unit),
+            "deserialize_unit_struct" => Some(Self::UnitStruct),
+            "deserialize_newtype_struct" => Some(Self::NewtypeStruct),
+            "deserialize_seq" => Some(Self::Seq),
+            "deserialize_tuple" => Some(Self::Tuple),
+            "deserialize_tuple_struct" => Some(Self::TupleStruct),
+            "deserialize_map" => Some(Self::Map),
+            "deserialize_struct" => Some(Self::Struct),
+            "deserialize_enum" => Some(Self::Enum),
+            _ => None,
+        }
+    }
+
+    /// Returns a human-friendly name for the type of value, such as
+    /// `"unsigned 32-bit integer"`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Bool => "boolean",
+            Self::I8 => "signed 8-bit integer",
+            Self::I16 => "signed 16-bit integer",
+            Self::I32 => "signed 32-bit integer",
+            Self::I64 => "signed 64-bit integer",
+            Self::I128 => "signed 128-bit integer",
+            Self::U8 => "unsigned 8-bit integer",
+            Self::U16 => "unsigned 16-bit integer",
+            Self::U32 => "unsigned 32-bit integer",
+            Self::U64 => "unsigned 64-bit integer",
+            Self::U128 => "unsigned 128-bit integer",
+            Self::F32 => "32-bit float",
+            Self::F64 => "64-bit float",
+            Self::Char => "character",
+            Self::Str => "string slice",
+            Self::String => "string",
+            Self::Bytes => "byte slice",
+            Self::ByteBuf => "byte array",
+            Self::Option => "option",
+            Self::Unit => "unit",
+            Self::UnitStruct => "unit struct",
+            Self::NewtypeStruct => "newtype struct",
+            Self::Seq => "sequence",
+            Self::Tuple => "tuple",
+            Self::TupleStruct => "tuple struct",
+            Self::Map => "map",
+            Self::Struct => "struct",
+            Self::Enum => "enum",
+        }
+    }
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Displays a byte sequence in hex, as `[0x02, 0xff]`.
+struct HexBytes<'a>(&'a [u8]);
+
+impl fmt::Display for HexBytes<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("[")?;
+
+        for (i, byte) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+
+            write!(f, "{byte:#04x}")?;
+        }
+
+        f.write_str("]")
+    }
+}
+
 /// Library-level error.
 #[derive(Debug, Error)]
 pub enum Error {
@@ -49,32 +156,283 @@ pub enum Error {
     /// An enum has more than 256 variants.
     #[error("enum `{0}` has more than 256 variants")]
     TooManyVariants(&'static str),
-    /// The deserializer is trying to use `deserialize_any`.
-    #[error("`deserialize_any` is not allowed")]
+    /// The deserializer is trying to use `deserialize_any` outside of tagged
+    /// mode, as self-describing types such as internally tagged and untagged
+    /// enums do.
+    #[error(
+        "`deserialize_any` is not allowed outside of tagged mode; self-describing types such as internally tagged enums need `with_tagged`"
+    )]
     CannotDeserializeAny,
-    /// The deserializer is trying to use `deserialize_identifier`.
+    /// The deserializer is trying to use `deserialize_identifier` outside of
+    /// tagged mode.
     #[error("`deserialize_identifier` is not allowed")]
     CannotDeserializeIdentifier,
     /// A byte reader reached the end of the stream prematurely.
     #[error("a byte reader reached the end of the stream prematurely")]
     UnexpectedEof,
+    /// The input ended partway through a fixed-width value.
+    #[error("unexpected end of input while deserializing {ty}")]
+    TruncatedValue {
+        /// The type of value that was cut short.
+        ty: ValueType,
+    },
+    /// The input ended before any of a top-level value was read, rather than
+    /// partway through it, so that a stream of values can tell a clean end
+    /// from a truncated value. This is only detected by readers that know how
+    /// much input remains, such as [`BytesReader`](crate::BytesReader);
+    /// others report [`Error::UnexpectedEof`] or [`Error::TruncatedValue`].
+    #[error("the input is empty")]
+    EmptyInput,
+    /// The input ended between two fields of a struct or elements of a
+    /// tuple. This is only detected by readers that know how much input
+    /// remains, such as [`BytesReader`](crate::BytesReader).
+    #[error("expected {expected} fields, read {read} before the end of input")]
+    IncompleteStruct {
+        /// The number of fields or elements in the struct or tuple.
+        expected: usize,
+        /// The number of fields or elements read before the input ended.
+        read: usize,
+    },
+    /// An element of a tuple, tuple struct or tuple variant failed to
+    /// decode.
+    #[error("{source} at tuple index {index}")]
+    InTupleElement {
+        /// The index of the element.
+        index: usize,
+        /// The error decoding the element.
+        source: Box<Error>,
+    },
+    /// A field of a struct or struct variant failed to decode.
+    #[error("{source} in field `{field}`")]
+    InStructField {
+        /// The name of the field.
+        field: &'static str,
+        /// The error decoding the field.
+        source: Box<Error>,
+    },
     /// An invalid byte sequence was encountered.
-    #[error("invalid byte sequence while deserializing value of type `{ty:?}`: `{bytes:?}`")]
+    #[error(
+        "invalid byte sequence while deserializing {ty}: `{}`",
+        HexBytes(bytes)
+    )]
     InvalidBytes {
         /// The type of value where the deserializer failed.
         ty: ValueType,
         /// The sequence of invalid bytes.
         bytes: Vec<u8>,
     },
+    /// A length prefix was not in its minimal, canonical form.
+    #[error("non-canonical length encoding: `{0:?}`")]
+    NonCanonicalLength(Vec<u8>),
+    /// An encoded length does not fit in a `usize`.
+    #[error("length encoding `{0:?}` overflows `usize`")]
+    LengthOverflow(Vec<u8>),
+    /// The checksum following a length prefix did not match the checksum of
+    /// its bytes.
+    #[error("length checksum mismatch: expected `{expected:#04x}`, found `{found:#04x}`")]
+    LengthChecksumMismatch {
+        /// The checksum computed from the length prefix's bytes.
+        expected: u8,
+        /// The checksum that was read.
+        found: u8,
+    },
+    /// A NaN or infinite floating point value was encoded or decoded while
+    /// only finite values are allowed.
+    #[error("non-finite {ty}: `{value}`")]
+    NonFiniteFloat {
+        /// The type of float.
+        ty: ValueType,
+        /// The encoded or decoded value.
+        value: f64,
+    },
+    /// A zero was decoded where a nonzero integer, such as a
+    /// [`NonZeroU32`](std::num::NonZeroU32), was expected.
+    #[error("expected a nonzero {ty}, found zero")]
+    UnexpectedZero {
+        /// The type of integer.
+        ty: ValueType,
+    },
+    /// The decoder would have read more than its configured total byte limit.
+    #[error("input exceeds the limit of {limit} bytes")]
+    InputLimitExceeded {
+        /// The maximum number of bytes the decoder may read.
+        limit: usize,
+    },
+    /// A sequence or map held more elements or entries encoded in zero
+    /// bytes, such as units, than allowed.
+    #[error("sequence or map has more than {limit} elements encoded in zero bytes")]
+    TooManyEmptyElements {
+        /// The maximum number of such elements, as set with
+        /// [`Decoder::with_max_empty_elements`](crate::Decoder::with_max_empty_elements),
+        /// or 2<sup>20</sup> by default.
+        limit: usize,
+    },
+    /// A struct was serialized with a different number of fields than it
+    /// has. Fields are encoded positionally, so they can't be skipped, such
+    /// as with `#[serde(skip_serializing_if = "...")]`.
+    #[error("struct has {expected} fields, but {serialized} were serialized")]
+    FieldCountMismatch {
+        /// The number of fields in the struct, including any skipped.
+        expected: usize,
+        /// The number of fields that were serialized.
+        serialized: usize,
+    },
+    /// An iterator yielded a different number of items than the length it
+    /// reported, which was already written as the length of its sequence.
+    #[error("iterator reported {reported} items, but yielded {yielded}")]
+    IteratorLengthMismatch {
+        /// The length the iterator reported up front.
+        reported: usize,
+        /// The number of items it yielded, counting only the first beyond
+        /// its reported length, since it is not iterated any further.
+        yielded: usize,
+    },
+    /// The decoder's delimiter appeared in the input, marking the end of the
+    /// frame before the value was complete.
+    #[error("reached the delimiter before the end of the value")]
+    DelimiterReached,
+    /// Input expected to hold values exactly had bytes left over after them,
+    /// such as a length-prefixed message declaring a longer body than its
+    /// value took up.
+    #[error("{count} unread bytes remain after the last value")]
+    TrailingBytes {
+        /// The number of bytes left over after the last value, or 1 if the
+        /// reader couldn't tell how many remain without being drained.
+        count: usize,
+    },
+    /// A value read from a length-prefixed frame took up a different number
+    /// of bytes than the frame declared.
+    #[error("frame declared {declared} bytes, but its value took up {consumed}")]
+    FrameLengthMismatch {
+        /// The length the frame declared.
+        declared: usize,
+        /// The number of bytes the value took up.
+        consumed: usize,
+    },
+    /// In framed mode, the byte following a struct or sequence was not the
+    /// frame sentinel, meaning the decoder has fallen out of step with the
+    /// encoded data.
+    #[error("frame desync: expected sentinel byte {expected_sentinel:#04x}, found {found:#04x}")]
+    FrameDesync {
+        /// The sentinel byte that should have ended the frame.
+        expected_sentinel: u8,
+        /// The byte that was found instead.
+        found: u8,
+    },
+    /// In framed mode, the number of fields written after the index of a
+    /// tuple or struct variant did not match the decoded variant, meaning
+    /// the index was corrupted or the enum's definition differs.
+    #[error("expected a variant with {expected} fields, found one with {found}")]
+    VariantArityMismatch {
+        /// The number of fields in the decoded variant.
+        expected: usize,
+        /// The number of fields written by the encoder.
+        found: usize,
+    },
+    /// The input began with a header for a different format than the one
+    /// expected.
+    #[error(
+        "expected magic number {}, found {}",
+        HexBytes(expected.as_slice()),
+        HexBytes(found.as_slice())
+    )]
+    MagicMismatch {
+        /// The magic number that was expected.
+        expected: [u8; 4],
+        /// The magic number found in the header.
+        found: [u8; 4],
+    },
+    /// In tagged mode, a value was preceded by the tag of a different type
+    /// of value than the one being deserialized.
+    #[error("expected a {expected} tag, found a {found} tag")]
+    UnexpectedTag {
+        /// The type of value being deserialized.
+        expected: ValueType,
+        /// The type of value the tag introduces.
+        found: ValueType,
+    },
+    /// In tagged mode, a value was preceded by a byte that is not a valid
+    /// type tag.
+    #[error("invalid type tag: {0:#04x}")]
+    InvalidTag(u8),
+    /// A borrowed value was requested from a reader that cannot lend out
+    /// borrowed data, such as a file.
+    #[error(
+        "cannot borrow a {ty} from this reader; deserialize from a `BytesReader` or use an owned type instead"
+    )]
+    CannotBorrowFromReader {
+        /// The type of value that could not be borrowed.
+        ty: ValueType,
+    },
+    /// Encoding was cancelled by the caller's cancellation check.
+    #[error("serialization was cancelled")]
+    Cancelled,
     /// An I/O error.
     #[error("I/O error: {0}")]
     IoError(#[from] io::Error),
+    /// A string or character was not valid UTF-8.
+    #[error("invalid UTF-8 while deserializing {ty}: {source}")]
+    InvalidUtf8 {
+        /// The type of value that was not valid UTF-8.
+        ty: ValueType,
+        /// The underlying UTF-8 error.
+        source: std::str::Utf8Error,
+    },
     /// A UTF-8 encode/decode error.
     #[error("UTF-8 encode/decode error: {0}")]
     Utf8Error(#[from] std::str::Utf8Error),
-    /// A custom error message from `serde`.
+    /// A custom error message from `serde`. Static messages can be stored
+    /// without allocating.
     #[error("serialization error: {0}")]
-    Custom(String),
+    Custom(Cow<'static, str>),
+}
+
+/// Converting a slice of the wrong length into an array means too few bytes
+/// were available.
+impl From<TryFromSliceError> for Error {
+    fn from(_: TryFromSliceError) -> Self {
+        Self::UnexpectedEof
+    }
+}
+
+/// Lets the crate's errors be returned from functions producing
+/// [`io::Result`]. An [`Error::IoError`] converts back into the error it
+/// holds, and errors for input that ended too soon into errors of kind
+/// [`io::ErrorKind::UnexpectedEof`], matching how they are read. An
+/// [`Error::Cancelled`] converts into an error of kind
+/// [`io::ErrorKind::Other`]. Every other error means the data itself was at
+/// fault, and converts into an error of kind [`io::ErrorKind::InvalidData`]
+/// wrapping it.
+impl From<Error> for io::Error {
+    fn from(err: Error) -> Self {
+        let kind = match err.innermost() {
+            Error::IoError(source) => source.kind(),
+            Error::UnexpectedEof
+            | Error::TruncatedValue { .. }
+            | Error::EmptyInput
+            | Error::IncompleteStruct { .. } => io::ErrorKind::UnexpectedEof,
+            Error::Cancelled => io::ErrorKind::Other,
+            _ => io::ErrorKind::InvalidData,
+        };
+
+        match err {
+            Error::IoError(source) => source,
+            err => Self::new(kind, err),
+        }
+    }
+}
+
+impl Error {
+    /// Returns the error beneath any tuple element and struct field context,
+    /// i.e. the error that actually occurred, for matching on its kind.
+    pub fn innermost(&self) -> &Self {
+        match self {
+            Self::InTupleElement { source, .. } | Self::InStructField { source, .. } => {
+                source.innermost()
+            }
+            err => err,
+        }
+    }
 }
 
 impl serde::ser::Error for Error {
@@ -82,7 +440,7 @@ impl serde::ser::Error for Error {
     where
         T: std::fmt::Display,
     {
-        Self::Custom(msg.to_string())
+        Self::Custom(Cow::Owned(msg.to_string()))
     }
 }
 
@@ -91,8 +449,42 @@ impl serde::de::Error for Error {
     where
         T: std::fmt::Display,
     {
-        Self::Custom(msg.to_string())
+        Self::Custom(Cow::Owned(msg.to_string()))
     }
+
+    fn invalid_value(unexp: Unexpected, exp: &dyn Expected) -> Self {
+        if let Unexpected::Unsigned(0) | Unexpected::Signed(0) = unexp {
+            if let Some(ty) = nonzero_type(exp) {
+                return Self::UnexpectedZero { ty };
+            }
+        }
+
+        Self::custom(format_args!("invalid value: {unexp}, expected {exp}"))
+    }
+}
+
+/// The descriptions serde's `NonZero*` visitors give of themselves, and the
+/// integer types they expect.
+const NONZERO_TYPES: [(&str, ValueType); 10] = [
+    ("a nonzero i8", ValueType::I8),
+    ("a nonzero i16", ValueType::I16),
+    ("a nonzero i32", ValueType::I32),
+    ("a nonzero i64", ValueType::I64),
+    ("a nonzero i128", ValueType::I128),
+    ("a nonzero u8", ValueType::U8),
+    ("a nonzero u16", ValueType::U16),
+    ("a nonzero u32", ValueType::U32),
+    ("a nonzero u64", ValueType::U64),
+    ("a nonzero u128", ValueType::U128),
+];
+
+/// Returns the integer type that `exp` expects if it is one of serde's
+/// `NonZero*` visitors.
+fn nonzero_type(exp: &dyn Expected) -> Option<ValueType> {
+    NONZERO_TYPES
+        .iter()
+        .find(|(text, _)| expected_is(exp, text))
+        .map(|&(_, ty)| ty)
 }
 
 /// Library-level `Result` alias.
@@ -0,0 +1,87 @@
+//! Block deserialization of fixed-size byte arrays.
+//!
+//! A `[u8; N]` is encoded as its `N` bytes with no length prefix, since the
+//! length is known from the type, but serde decodes it one element at a time.
+//! For large arrays such as keys and hashes, this module reads all `N` bytes
+//! in a single block instead, borrowing them from the input where the reader
+//! allows. The encoded bytes are identical to those of the default array
+//! encoding, so the two are interchangeable on the wire, and other formats
+//! see an ordinary tuple of `N` bytes.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Signature {
+//!     #[serde(with = "unbin::fixed_bytes")]
+//!     public_key: [u8; 32],
+//!     #[serde(with = "unbin::fixed_bytes")]
+//!     signature: [u8; 64],
+//! }
+//! ```
+
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeTupleStruct;
+use serde::{Deserializer, Serializer};
+use std::fmt;
+
+/// The name of the tuple struct a fixed-size byte array is passed through,
+/// which the decoder recognizes in order to read it as a block.
+pub(crate) const NAME: &str = "$unbin::fixed_bytes";
+
+/// Serializes a fixed-size byte array as its bytes, with no length prefix.
+pub fn serialize<const N: usize, S>(value: &[u8; N], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut tuple = serializer.serialize_tuple_struct(NAME, N)?;
+
+    for byte in value {
+        tuple.serialize_field(byte)?;
+    }
+
+    tuple.end()
+}
+
+/// Deserializes a fixed-size byte array from its bytes in a single block.
+pub fn deserialize<'de, const N: usize, D>(deserializer: D) -> Result<[u8; N], D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple_struct(NAME, N, FixedBytesVisitor)
+}
+
+/// Visits a block of exactly `N` bytes, or a sequence of them for formats
+/// that don't read the array as a block.
+struct FixedBytesVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for FixedBytesVisitor<N> {
+    type Value = [u8; N];
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "an array of {N} bytes")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        v.try_into()
+            .map_err(|_| de::Error::invalid_length(v.len(), &self))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = [0; N];
+
+        for (index, byte) in bytes.iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+        }
+
+        Ok(bytes)
+    }
+}
@@ -0,0 +1,248 @@
+//! Packed groups of boolean flags.
+
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// A group of `N` boolean flags, encoded as a bitfield.
+///
+/// Every `bool` normally takes a byte of its own, so a struct with several
+/// flags spends most of its bits on padding. This packs the flags together
+/// instead, eight to a byte, least significant bit first, so that eight flags
+/// take a single byte. Unused bits in the last byte must be zero, so that
+/// every group of flags has exactly one encoding. Groups of 1 to 64 flags are
+/// supported.
+///
+/// Since fields are encoded by position, the [`flags!`](crate::flags!) macro
+/// can declare a struct of named flags that is encoded this way:
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+///
+/// unbin::flags! {
+///     #[derive(Debug, Clone, Copy, Default, PartialEq)]
+///     pub struct Permissions {
+///         pub read,
+///         pub write,
+///         pub execute,
+///     }
+/// }
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct File {
+///     name: String,
+///     permissions: Permissions,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Flags<const N: usize>(u64);
+
+impl<const N: usize> Flags<N> {
+    /// Fails to compile for an unsupported number of flags.
+    const SUPPORTED: () = assert!(N >= 1 && N <= 64, "flags must number from 1 to 64");
+
+    /// The number of bytes the flags are encoded in.
+    const BYTES: usize = N.div_ceil(8);
+
+    /// The bits that hold a flag.
+    const MASK: u64 = u64::MAX >> (64 - N);
+
+    /// Creates a group of flags with every flag clear.
+    pub const fn new() -> Self {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::SUPPORTED;
+        Self(0)
+    }
+
+    /// Creates a group of flags from its bits, with flag `i` held in bit `i`.
+    /// Returns `None` if any bit beyond the first `N` is set.
+    pub const fn from_bits(bits: u64) -> Option<Self> {
+        #[allow(clippy::let_unit_value)]
+        let () = Self::SUPPORTED;
+
+        if bits & !Self::MASK == 0 {
+            Some(Self(bits))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the bits of the flags, with flag `i` held in bit `i`.
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    /// Returns the flag at `index`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `N`.
+    pub fn get(self, index: usize) -> bool {
+        assert!(index < N, "flag index {index} out of range for {N} flags");
+        self.0 & (1 << index) != 0
+    }
+
+    /// Sets the flag at `index` to `value`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index` is not less than `N`.
+    pub fn set(&mut self, index: usize, value: bool) {
+        assert!(index < N, "flag index {index} out of range for {N} flags");
+
+        if value {
+            self.0 |= 1 << index;
+        } else {
+            self.0 &= !(1 << index);
+        }
+    }
+}
+
+impl<const N: usize> From<[bool; N]> for Flags<N> {
+    fn from(flags: [bool; N]) -> Self {
+        let mut bits = Self::new();
+
+        for (index, flag) in flags.into_iter().enumerate() {
+            bits.set(index, flag);
+        }
+
+        bits
+    }
+}
+
+impl<const N: usize> From<Flags<N>> for [bool; N] {
+    fn from(flags: Flags<N>) -> Self {
+        std::array::from_fn(|index| flags.get(index))
+    }
+}
+
+impl<const N: usize> Serialize for Flags<N> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(Self::BYTES)?;
+
+        for byte in &self.0.to_le_bytes()[..Self::BYTES] {
+            tuple.serialize_element(byte)?;
+        }
+
+        tuple.end()
+    }
+}
+
+impl<'de, const N: usize> Deserialize<'de> for Flags<N> {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(Self::BYTES, FlagsVisitor)
+    }
+}
+
+/// Visits a group of `N` flags.
+struct FlagsVisitor<const N: usize>;
+
+impl<'de, const N: usize> Visitor<'de> for FlagsVisitor<N> {
+    type Value = Flags<N>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        write!(formatter, "a bitfield of {N} flags")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = [0u8; 8];
+
+        for (index, byte) in bytes[..Flags::<N>::BYTES].iter_mut().enumerate() {
+            *byte = seq
+                .next_element()?
+                .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+        }
+
+        Flags::from_bits(u64::from_le_bytes(bytes)).ok_or_else(|| {
+            de::Error::invalid_value(
+                Unexpected::Bytes(&bytes[..Flags::<N>::BYTES]),
+                &"a bitfield with its unused bits clear",
+            )
+        })
+    }
+}
+
+/// Declares a struct of named boolean flags, encoded as a bitfield.
+///
+/// Each field is written without a type and declared as a `bool`. The struct
+/// is encoded as a [`Flags`](crate::Flags) with one flag per field, in the
+/// order the fields are declared, and converts to and from it with `From`.
+/// Attributes on the struct, such as derives, are kept.
+///
+/// ```
+/// unbin::flags! {
+///     #[derive(Debug, PartialEq)]
+///     struct Options {
+///         verbose,
+///         quiet,
+///     }
+/// }
+///
+/// let options = Options { verbose: false, quiet: true };
+/// let serialized = unbin::serialize(&options).unwrap();
+/// assert_eq!(serialized, [0b10]);
+/// assert_eq!(unbin::deserialize::<Options>(&serialized).unwrap(), options);
+/// ```
+#[macro_export]
+macro_rules! flags {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident),+ $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: bool,)+
+        }
+
+        impl ::core::convert::From<$name>
+            for $crate::Flags<{ [$(::core::stringify!($field)),+].len() }>
+        {
+            fn from(value: $name) -> Self {
+                Self::from([$(value.$field),+])
+            }
+        }
+
+        impl ::core::convert::From<$crate::Flags<{ [$(::core::stringify!($field)),+].len() }>>
+            for $name
+        {
+            fn from(flags: $crate::Flags<{ [$(::core::stringify!($field)),+].len() }>) -> Self {
+                let [$($field),+] = flags.into();
+                Self { $($field),+ }
+            }
+        }
+
+        impl $crate::__private::serde::Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> ::core::result::Result<S::Ok, S::Error>
+            where
+                S: $crate::__private::serde::Serializer,
+            {
+                let flags: $crate::Flags<{ [$(::core::stringify!($field)),+].len() }> =
+                    ::core::convert::From::from([$(self.$field),+]);
+                $crate::__private::serde::Serialize::serialize(&flags, serializer)
+            }
+        }
+
+        impl<'de> $crate::__private::serde::Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> ::core::result::Result<Self, D::Error>
+            where
+                D: $crate::__private::serde::Deserializer<'de>,
+            {
+                let flags: $crate::Flags<{ [$(::core::stringify!($field)),+].len() }> =
+                    $crate::__private::serde::Deserialize::deserialize(deserializer)?;
+                ::core::result::Result::Ok(::core::convert::From::from(flags))
+            }
+        }
+    };
+}
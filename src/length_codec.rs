@@ -0,0 +1,156 @@
+//! Pluggable length encodings.
+
+use crate::util::{
+    decode_len_large, decode_len_small, decode_len_varint, encode_int_varint,
+    encode_len_large_into, is_canonical_len_large, is_canonical_varint, MAX_INT_VARINT_BYTES,
+    MAX_LEN_LARGE_BYTES, MAX_LEN_VARINT_BYTES,
+};
+use crate::write::Write;
+use crate::{Error, Result};
+use std::fmt;
+
+/// A scheme for encoding the lengths of strings, byte arrays, sequences and
+/// maps, for use as [`LengthEncoding::Custom`](crate::LengthEncoding::Custom).
+///
+/// An encoder writes each length with [`encode_len`](Self::encode_len), and
+/// a decoder reads it back with [`decode_len`](Self::decode_len), so the two
+/// must agree on the layout. Length checksums, if enabled, cover every byte
+/// a codec writes. In canonical mode, a decoder rejects any length whose bytes
+/// [`is_canonical`](Self::is_canonical) returns `false` for, once its checksum
+/// has been verified.
+///
+/// The built-in [`LengthEncoding`](crate::LengthEncoding)s are themselves
+/// implemented by [`PrefixedLength`] and [`VarintLength`].
+///
+/// ```
+/// use unbin::{LengthCodec, LengthEncoding, LengthReader, Write};
+///
+/// /// Every length as two big-endian bytes.
+/// #[derive(Debug)]
+/// struct TwoByteLength;
+///
+/// impl LengthCodec for TwoByteLength {
+///     fn encode_len(&self, len: usize, out: &mut dyn Write) -> unbin::Result<()> {
+///         let len = u16::try_from(len)
+///             .map_err(|_| unbin::Error::Custom("length too large".into()))?;
+///         out.write_all(&len.to_be_bytes())
+///     }
+///
+///     fn decode_len(&self, reader: &mut dyn LengthReader) -> unbin::Result<usize> {
+///         let mut bytes = [0; 2];
+///         reader.read_exact(&mut bytes)?;
+///         Ok(u16::from_be_bytes(bytes).into())
+///     }
+/// }
+///
+/// let options = unbin::builder()
+///     .length_encoding(LengthEncoding::Custom(&TwoByteLength))
+///     .build();
+/// let serialized = unbin::serialize_with(&"hi", &options).unwrap();
+/// assert_eq!(serialized, [0, 2, b'h', b'i']);
+/// ```
+pub trait LengthCodec: fmt::Debug + Sync {
+    /// Writes the encoding of `len` to `out`.
+    fn encode_len(&self, len: usize, out: &mut dyn Write) -> Result<()>;
+
+    /// Reads an encoded length from `reader` and decodes it.
+    fn decode_len(&self, reader: &mut dyn LengthReader) -> Result<usize>;
+
+    /// Returns whether `encoded`, every byte [`decode_len`](Self::decode_len)
+    /// read for a length, is that length's canonical encoding. Every encoding
+    /// is canonical by default.
+    fn is_canonical(&self, encoded: &[u8]) -> bool {
+        let _ = encoded;
+        true
+    }
+}
+
+/// A source of the bytes of an encoded length, passed to
+/// [`LengthCodec::decode_len`]. Bytes read through it count towards the
+/// decoder's limits like any others.
+pub trait LengthReader {
+    /// Reads exactly enough bytes to fill `buf`.
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
+
+    /// Reads a single byte.
+    fn read_u8(&mut self) -> Result<u8> {
+        let mut byte = [0];
+        self.read_exact(&mut byte)?;
+        Ok(byte[0])
+    }
+}
+
+/// The codec behind [`LengthEncoding::Prefixed`](crate::LengthEncoding::Prefixed),
+/// for use in codecs of one's own. Redundant leading zero bytes are accepted
+/// when decoding, except in canonical mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct PrefixedLength;
+
+impl LengthCodec for PrefixedLength {
+    fn encode_len(&self, len: usize, out: &mut dyn Write) -> Result<()> {
+        let mut buf = [0; MAX_LEN_LARGE_BYTES];
+        let n = encode_len_large_into(len, &mut buf);
+        out.write_all(&buf[..n])
+    }
+
+    fn decode_len(&self, reader: &mut dyn LengthReader) -> Result<usize> {
+        // the byte count is a single byte, so the whole length fits on the
+        // stack, redundant leading zero bytes included
+        let mut bytes = [0; 1 + u8::MAX as usize];
+        let len1 = reader.read_u8()?;
+        bytes[0] = len1;
+        let bytes = &mut bytes[..1 + decode_len_small(len1)];
+        reader.read_exact(&mut bytes[1..])?;
+
+        match decode_len_large(&bytes[1..]) {
+            Some(len) => Ok(len),
+            None => Err(Error::LengthOverflow(bytes.to_vec())),
+        }
+    }
+
+    fn is_canonical(&self, encoded: &[u8]) -> bool {
+        is_canonical_len_large(encoded.get(1..).unwrap_or_default())
+    }
+}
+
+/// The codec behind [`LengthEncoding::Varint`](crate::LengthEncoding::Varint),
+/// for use in codecs of one's own. Redundant trailing zero groups are
+/// accepted when decoding, except in canonical mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct VarintLength;
+
+impl LengthCodec for VarintLength {
+    fn encode_len(&self, len: usize, out: &mut dyn Write) -> Result<()> {
+        let mut buf = [0; MAX_INT_VARINT_BYTES];
+        let n = encode_int_varint(len as u128, &mut buf);
+        out.write_all(&buf[..n])
+    }
+
+    fn decode_len(&self, reader: &mut dyn LengthReader) -> Result<usize> {
+        let mut bytes = [0; MAX_LEN_VARINT_BYTES];
+        let mut n = 0;
+
+        loop {
+            let byte = reader.read_u8()?;
+            bytes[n] = byte;
+            n += 1;
+
+            if byte & 0x80 == 0 {
+                break;
+            }
+
+            if n == MAX_LEN_VARINT_BYTES {
+                return Err(Error::LengthOverflow(bytes.to_vec()));
+            }
+        }
+
+        match decode_len_varint(&bytes[..n]) {
+            Some(len) => Ok(len),
+            None => Err(Error::LengthOverflow(bytes[..n].to_vec())),
+        }
+    }
+
+    fn is_canonical(&self, encoded: &[u8]) -> bool {
+        is_canonical_varint(encoded)
+    }
+}
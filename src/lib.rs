@@ -1,24 +1,176 @@
 //! Binary serialization and deserialization compatible with [`serde`].
+//!
+//! # Floating point values
+//!
+//! `f32` and `f64` values are encoded as their IEEE 754 bit patterns in
+//! big-endian byte order by default, on every platform.
+//! The bits are written exactly as they are: the sign of zero, subnormal
+//! values, infinities and NaN payloads are all preserved, and no
+//! normalization takes place. Equal bit patterns therefore always produce
+//! equal bytes. A [`Decoder`] can optionally reject NaN and infinite values
+//! with [`Decoder::with_finite_floats`].
+//!
+//! With [`FloatEncoding::Compact`], a value is instead written in the
+//! narrowest form that holds it exactly, after a byte giving its width, so
+//! that an `f64` holding a small integer or an `f32` takes fewer bytes. The
+//! bit pattern is still preserved exactly.
+//!
+//! # Tuples, structs and arrays
+//!
+//! Tuples, tuple structs, structs and fixed-size arrays are encoded as their
+//! fields one after another, with no length prefix, since the number of
+//! fields is known from the type. Sequences and maps, whose lengths are only
+//! known at runtime, are prefixed with their length instead.
+//!
+//! Since fields are identified only by their position, every field must be
+//! written. A field skipped with `#[serde(skip_serializing_if = "...")]`
+//! fails encoding with [`Error::FieldCountMismatch`] rather than leaving the
+//! decoder to read later fields out of place. Fields skipped entirely with
+//! `#[serde(skip)]` are fine, since they are skipped when decoding too, but
+//! `#[serde(skip_serializing)]` alone can't be detected and must be avoided.
+//!
+//! This crate places no limit on the number of fields. serde itself only
+//! implements its traits for tuples of up to 16 elements and arrays of up to
+//! 32, but larger fixed-arity values can be expressed as derived tuple
+//! structs, which are encoded the same way.
+//!
+//! # Ranges
+//!
+//! `Range` and `RangeInclusive` are encoded like structs of their start and
+//! end bounds, in that order, with nothing to tell the two kinds apart.
+//! `5u32..10` and `5u32..=10` are therefore both encoded as `[0, 0, 0, 5, 0,
+//! 0, 0, 10]`, or as `[5, 10]` with [`IntEncoding::Varint`]. An inclusive
+//! range that has been iterated to exhaustion is encoded as its bounds alone,
+//! and so decodes as not yet exhausted. This layout is part of the format and
+//! will not change.
+//!
+//! # Maps
+//!
+//! Maps are encoded as their length followed by each key and value, in the
+//! order the map iterates over them. A `BTreeMap` therefore always encodes
+//! its keys in ascending order, and equal maps produce equal bytes, while a
+//! `HashMap`'s order, and so its encoding, can vary from one run to the next.
+//! Decoding accepts entries in any order, even with
+//! [`Decoder::with_canonical`], and leaves ordering to the map type, so a
+//! `BTreeMap` decoded from out-of-order entries is sorted as usual.
+//!
+//! Sets are encoded as sequences, with the same caveat: a `HashSet`'s
+//! encoding can vary between equal sets. The [`canonical_set`] module sorts a
+//! set's elements by their encoded bytes so that it can't.
+//!
+//! # Booleans
+//!
+//! A `bool` is encoded as a single byte, `0` for `false` and `1` for `true`.
+//! Decoding is strict: any other byte is rejected with
+//! [`Error::InvalidBytes`], rather than being read as `true`. Every boolean
+//! therefore has exactly one encoding.
+//!
+//! # Results
+//!
+//! `Result<T, E>` is encoded as an enum with two variants: a one-byte variant
+//! index of `0` for `Ok` or `1` for `Err`, followed by the encoded payload.
+//! `Ok(5u8)` is therefore encoded as `[0, 5]`, and `Err("x")` as `[1, 1, 1,
+//! b'x']`. This layout is part of the format and will not change.
+//!
+//! # Headers
+//!
+//! [`serialize_with_header`] writes a six-byte header before the value: a
+//! four-byte magic number chosen by the caller to identify the format, then
+//! a version as a big-endian `u16`, whatever the integer encoding. The header
+//! can be inspected with [`Decoder::peek_header`] to route the input before
+//! decoding the value.
+//!
+//! # Allocation
+//!
+//! Decoding a value whose encoding has a fixed size never allocates when
+//! reading from a [`BytesReader`], or with [`deserialize`]. This covers
+//! primitives including `char`, unit, options, fixed-size arrays, and tuples,
+//! structs and enums whose fields are all of such types, which makes these
+//! types usable where the heap is unavailable or must be avoided. Values with
+//! a length prefix, such as strings, sequences and maps, may allocate, both
+//! for their contents and for the decoder's buffer for reading lengths.
+//!
+//! # Limits
+//!
+//! Elements encoded in zero bytes, such as units, don't advance through the
+//! input, so a hostile length could otherwise have the decoder produce them
+//! practically forever. A single sequence or map may therefore hold at most
+//! 1,048,576 of them by default, beyond which decoding fails with
+//! [`Error::TooManyEmptyElements`], even if the data is valid. The limit is
+//! raised or lowered with [`Decoder::with_max_empty_elements`]. Elements that
+//! take at least one byte are never counted.
+//!
+//! # Self-describing mode
+//!
+//! By default the encoding carries no type information, so decoding relies
+//! on knowing the type being decoded, and `deserialize_any` is unsupported.
+//! With [`Encoder::with_tagged`] and [`Decoder::with_tagged`], every value
+//! is instead preceded by a one-byte type tag, and tuples and structs by
+//! their length, so that dynamic types such as `serde_json::Value` can be
+//! decoded. Newtype structs remain transparent, and field names are not
+//! written, so structs decode dynamically as sequences.
+//!
+//! Internally tagged enums, declared with `#[serde(tag = "...")]`, and
+//! untagged enums rely on `deserialize_any`, so they can only be decoded in
+//! tagged mode, and fail with [`Error::CannotDeserializeAny`] otherwise. An
+//! internally tagged enum is written as a sequence of the variant name
+//! followed by the variant's fields.
 
 #![forbid(unsafe_code)]
 #![deny(missing_docs)]
 #![warn(clippy::missing_docs_in_private_items)]
 
+pub mod canonical_set;
+mod config;
 mod decode;
+pub mod discriminant;
 mod encode;
 mod error;
+pub mod fixed_bytes;
+mod flags;
+mod length_codec;
+pub mod net;
+mod option_bitmap;
+pub mod path;
 mod read;
+pub mod shared;
+pub mod signed_bytes;
+mod sparse_vec;
+mod tag;
+pub mod time;
 mod util;
+#[cfg(feature = "uuid")]
+pub mod uuid;
 mod write;
 
-use crate::decode::Decoder;
-use crate::encode::Encoder;
+pub use crate::config::{
+    Builder, CharEncoding, FloatEncoding, IntEncoding, LengthEncoding, Options,
+};
+pub use crate::decode::Decoder;
+pub use crate::encode::{Encoder, IndexedEncoder, OwnedWriter, WriterSlot};
 pub use crate::error::{Error, Result, ValueType};
-use crate::read::{BytesReader, Read};
-use crate::write::{BytesWriter, Write};
+pub use crate::flags::Flags;
+pub use crate::length_codec::{LengthCodec, LengthReader, PrefixedLength, VarintLength};
+pub use crate::option_bitmap::OptionBitmap;
+pub use crate::read::{BufferedReader, BytesReader, ChainedBytesReader, Read};
+pub use crate::sparse_vec::SparseVec;
+use crate::util::{
+    decode_len_large, decode_len_small, encode_header, encode_len_large, MAX_READ_PREALLOC,
+};
+pub use crate::write::{
+    BytesWriter, HashingWriter, PooledWriter, TeeWriter, TransactionalWriter, Write, WriterPool,
+};
+use crate::write::{CallbackWriter, SizeCounter};
 use serde::de::DeserializeOwned;
+use serde::ser::{SerializeSeq, Serializer};
 use serde::{Deserialize, Serialize};
 
+/// Items used by this crate's macros, and not part of its public API.
+#[doc(hidden)]
+pub mod __private {
+    pub use serde;
+}
+
 /// Serializes a value to binary.
 pub fn serialize<T>(value: &T) -> Result<Vec<u8>>
 where
@@ -30,7 +182,102 @@ where
     Ok(writer.into_inner())
 }
 
+/// Returns a new [`Builder`] for [`Options`], to be used with
+/// [`serialize_with`] and [`deserialize_with`].
+pub fn builder() -> Builder {
+    Builder::new()
+}
+
+/// Serializes a value to binary using the given options.
+pub fn serialize_with<T>(value: &T, options: &Options) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    let mut writer = BytesWriter::new();
+    let mut encoder = Encoder::new(&mut writer).with_options(*options);
+    value.serialize(&mut encoder)?;
+    Ok(writer.into_inner())
+}
+
+/// Serializes a value to binary, first computing the exact size of the output
+/// so that it can be allocated up front. This traverses the value twice, but
+/// avoids reallocating as the output grows, which can pay off for large values.
+pub fn serialize_reserved<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    let size = serialized_size(value)?;
+    let mut writer = BytesWriter::with_capacity(size);
+    serialize_into(value, &mut writer)?;
+    Ok(writer.into_inner())
+}
+
+/// Serializes a value to binary, writing each [`Arc`](std::sync::Arc) that
+/// uses the [`shared`] module only once, with later clones of it written as
+/// back-references. Decode the output with [`deserialize_shared`].
+pub fn serialize_shared<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    shared::with_serialize_table(|| serialize(value))
+}
+
+/// Serializes a value to binary, passing each chunk of bytes to `sink` as it
+/// is produced rather than collecting the output. Chunks may be as small as a
+/// single byte, and an error returned by `sink` aborts serialization.
+pub fn serialize_streaming<T, F>(value: &T, sink: F) -> Result<()>
+where
+    T: Serialize,
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    let mut writer = CallbackWriter::new(sink);
+    serialize_into(value, &mut writer)
+}
+
+/// Serializes a value to binary, preceded by the length of its encoding, so
+/// that a reader can take exactly one message from a transport with no
+/// framing of its own. Decode the output with [`deserialize_length_prefixed`].
+pub fn serialize_length_prefixed<T>(value: &T) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    let body = serialize(value)?;
+    let mut bytes = encode_len_large(body.len());
+    bytes.extend_from_slice(&body);
+    Ok(bytes)
+}
+
+/// Serializes a value to binary, preceded by a header of a four-byte magic
+/// number identifying the format and a version, so that readers can tell
+/// what the input holds before decoding it. Decode the output with
+/// [`deserialize_with_header`], or inspect the header with
+/// [`Decoder::peek_header`].
+pub fn serialize_with_header<T>(value: &T, magic: [u8; 4], version: u16) -> Result<Vec<u8>>
+where
+    T: Serialize,
+{
+    let mut writer = BytesWriter::new();
+    writer.write_all(&encode_header(magic, version))?;
+    serialize_into(value, &mut writer)?;
+    Ok(writer.into_inner())
+}
+
+/// Computes the number of bytes a value serializes to, without producing the
+/// bytes themselves.
+pub fn serialized_size<T>(value: &T) -> Result<usize>
+where
+    T: Serialize,
+{
+    let mut counter = SizeCounter::new();
+    serialize_into(value, &mut counter)?;
+    Ok(counter.size())
+}
+
 /// Serializes a value to binary and writes it to the given writer.
+///
+/// Bytes are written as they are produced, so if serialization fails partway
+/// through, whatever was written before the failure remains in the writer. Use
+/// [`serialize_into_atomic`] when partial writes are unacceptable.
 pub fn serialize_into<T, W>(value: &T, writer: &mut W) -> Result<()>
 where
     T: Serialize,
@@ -41,6 +288,117 @@ where
     Ok(())
 }
 
+/// Serializes a value to binary and writes it to the given writer, which is
+/// named by this crate's [`Write`] trait.
+///
+/// This behaves exactly like [`serialize_into`]. Since [`Write`] is
+/// implemented for every [`std::io::Write`], both accept in-memory writers
+/// such as a [`BytesWriter`] as well as files and sockets, but this name
+/// makes it plain at the call site which trait is in play, which helps when
+/// `std::io::Write` is also in scope and method calls on the writer become
+/// ambiguous.
+pub fn serialize_into_writer<T, W>(value: &T, writer: &mut W) -> Result<()>
+where
+    T: Serialize,
+    W: Write,
+{
+    serialize_into(value, writer)
+}
+
+/// Serializes a value to binary and writes it to the given writer, polling
+/// `should_cancel` before every sequence element, map entry and struct field
+/// and failing with [`Error::Cancelled`] once it returns `true`.
+///
+/// This lets serialization of a very large value to a slow writer be
+/// abandoned cleanly, such as when a deadline passes or a shutdown is
+/// requested, rather than running to completion. The check is only made
+/// between values, so a single large string or byte array is still written
+/// in full. As with [`serialize_into`], whatever was written before the
+/// cancellation remains in the writer. The check must be `Sync`, as is a
+/// check of an atomic flag or a deadline.
+///
+/// ```
+/// use std::sync::atomic::{AtomicBool, Ordering};
+///
+/// let cancelled = AtomicBool::new(true);
+/// let mut writer = Vec::new();
+/// let result = unbin::serialize_into_cancellable(&vec![1u8, 2, 3], &mut writer, || {
+///     cancelled.load(Ordering::Relaxed)
+/// });
+/// assert!(matches!(result, Err(unbin::Error::Cancelled)));
+/// ```
+pub fn serialize_into_cancellable<T, W>(
+    value: &T,
+    writer: &mut W,
+    should_cancel: impl Fn() -> bool + Sync,
+) -> Result<()>
+where
+    T: Serialize,
+    W: Write,
+{
+    let mut encoder = Encoder::new(writer).with_cancel_check(&should_cancel);
+    value.serialize(&mut encoder)?;
+    Ok(())
+}
+
+/// Serializes the items of an iterator as a sequence and writes it to the
+/// given writer, without first collecting them. The output decodes as any
+/// sequence type, such as a `Vec`.
+///
+/// The sequence's length is written first, so the iterator must know its
+/// exact length up front. An iterator that then yields a different number of
+/// items fails with [`Error::IteratorLengthMismatch`], having written
+/// malformed output. An iterator yielding too many fails at the first extra
+/// item, before writing it.
+pub fn serialize_iter<I, W>(iter: I, writer: &mut W) -> Result<()>
+where
+    I: IntoIterator,
+    I::IntoIter: ExactSizeIterator,
+    I::Item: Serialize,
+    W: Write,
+{
+    let iter = iter.into_iter();
+    let len = iter.len();
+    let mut encoder = Encoder::new(writer);
+    let mut seq = encoder.serialize_seq(Some(len))?;
+    let mut count = 0;
+
+    for item in iter {
+        if count == len {
+            return Err(Error::IteratorLengthMismatch {
+                reported: len,
+                yielded: count + 1,
+            });
+        }
+
+        seq.serialize_element(&item)?;
+        count += 1;
+    }
+
+    if count != len {
+        return Err(Error::IteratorLengthMismatch {
+            reported: len,
+            yielded: count,
+        });
+    }
+
+    seq.end()
+}
+
+/// Serializes a value to binary and writes it to the given writer, with
+/// all-or-nothing semantics. The value is buffered in memory and only written
+/// to the writer if serialization succeeds, so a failure leaves the writer
+/// untouched.
+pub fn serialize_into_atomic<T, W>(value: &T, writer: &mut W) -> Result<()>
+where
+    T: Serialize,
+    W: Write,
+{
+    let mut transaction = TransactionalWriter::new(writer);
+    serialize_into(value, &mut transaction)?;
+    transaction.commit()
+}
+
 /// Deserializes binary data into a new instance of `T`.
 pub fn deserialize<'de, 'a, T>(bytes: &'a [u8]) -> Result<T>
 where
@@ -49,7 +407,170 @@ where
 {
     let mut reader = BytesReader::new(bytes);
     let mut decoder = Decoder::new(&mut reader);
-    T::deserialize(&mut decoder)
+    deserialize_value(&mut decoder)
+}
+
+/// Deserializes binary data into a new instance of `T` using the given
+/// options.
+pub fn deserialize_with<'de, 'a, T>(bytes: &'a [u8], options: &Options) -> Result<T>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    let mut reader = BytesReader::new(bytes);
+    let mut decoder = Decoder::new(&mut reader).with_options(*options);
+    deserialize_value(&mut decoder)
+}
+
+/// Deserializes binary data written by [`serialize_shared`] into a new
+/// instance of `T`, restoring the sharing between
+/// [`Arc`](std::sync::Arc)s that use the [`shared`] module.
+pub fn deserialize_shared<'de, 'a, T>(bytes: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    shared::with_deserialize_table(|| deserialize(bytes))
+}
+
+/// Deserializes binary data written by [`serialize_with_header`] into a new
+/// instance of `T`, also returning the version from the header. A header
+/// with a magic number other than `magic` fails with
+/// [`Error::MagicMismatch`]. Checking the version is left to the caller.
+pub fn deserialize_with_header<'de, 'a, T>(bytes: &'a [u8], magic: [u8; 4]) -> Result<(T, u16)>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    let mut reader = BytesReader::new(bytes);
+    let mut decoder = Decoder::new(&mut reader);
+    let (found, version) = decoder.read_header()?;
+
+    if found != magic {
+        return Err(Error::MagicMismatch {
+            expected: magic,
+            found,
+        });
+    }
+
+    let value = T::deserialize(&mut decoder)?;
+    Ok((value, version))
+}
+
+/// Deserializes binary data into a new instance of `T`, also returning the
+/// number of bytes the value occupied. Any bytes following the value are left
+/// untouched, so concatenated values can be decoded by advancing past each one
+/// in turn.
+pub fn deserialize_with_len<'de, 'a, T>(bytes: &'a [u8]) -> Result<(T, usize)>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    let mut reader = BytesReader::new(bytes);
+    let mut decoder = Decoder::new(&mut reader);
+    let value = deserialize_value(&mut decoder)?;
+    Ok((value, reader.position()))
+}
+
+/// Deserializes binary data written by [`serialize_length_prefixed`] into a
+/// new instance of `T`. The value must take up exactly the declared length:
+/// a body too short for the value fails as truncated, and one with bytes left
+/// over fails with [`Error::TrailingBytes`]. Any input following the message
+/// is left untouched.
+pub fn deserialize_length_prefixed<'de, 'a, T>(bytes: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    if bytes.is_empty() {
+        return Err(Error::EmptyInput);
+    }
+
+    let (body, _) = split_length_prefixed(bytes)?;
+    deserialize_body(body)
+}
+
+/// Deserializes a sequence of messages written one after another by
+/// [`serialize_length_prefixed`], carrying on past any message that fails to
+/// decode. Each message's result is returned in order, so a corrupt record in
+/// a stream of independent ones costs only that record.
+///
+/// A message can only be skipped using its declared length, so a length
+/// prefix that is malformed or runs past the end of the input ends the
+/// sequence, with its error as the last result.
+pub fn deserialize_frames_lossy<'de, 'a, T>(bytes: &'a [u8]) -> Vec<Result<T>>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    let mut results = Vec::new();
+    let mut rest = bytes;
+
+    while !rest.is_empty() {
+        match split_length_prefixed(rest) {
+            Ok((body, next)) => {
+                results.push(deserialize_body(body));
+                rest = next;
+            }
+            Err(err) => {
+                results.push(Err(err));
+                break;
+            }
+        }
+    }
+
+    results
+}
+
+/// Splits a message written by [`serialize_length_prefixed`] off the front of
+/// `bytes`, returning its body and the input following it.
+fn split_length_prefixed(bytes: &[u8]) -> Result<(&[u8], &[u8])> {
+    let mut reader = BytesReader::new(bytes);
+    let len_len = decode_len_small(reader.read_bytes(1)?[0]);
+    let len = decode_len_large(reader.read_bytes(len_len)?)
+        .ok_or_else(|| Error::LengthOverflow(bytes[..1 + len_len].to_vec()))?;
+    let body = reader.read_bytes(len)?;
+    Ok((body, &bytes[reader.position()..]))
+}
+
+/// Deserializes the body of a length-prefixed message, which the value must
+/// take up exactly.
+fn deserialize_body<'de, 'a, T>(body: &'a [u8]) -> Result<T>
+where
+    T: Deserialize<'de>,
+    'a: 'de,
+{
+    let (value, value_len) = deserialize_with_len(body)?;
+
+    if value_len < body.len() {
+        return Err(Error::TrailingBytes {
+            count: body.len() - value_len,
+        });
+    }
+
+    Ok(value)
+}
+
+/// Deserializes the entire contents of the given stream into a new instance of
+/// `T`, which can borrow from them, unlike with [`deserialize_from`].
+///
+/// The stream is first read to its end into `buffer`, replacing anything it
+/// held, and the value is then decoded from the buffer. This holds the whole
+/// stream in memory at once, so a `limit` on the number of bytes to read can
+/// be given, past which this fails with [`Error::InputLimitExceeded`] instead.
+/// The buffer can be reused across calls to avoid reallocating it.
+pub fn deserialize_from_borrowed<'de, T, R>(
+    reader: R,
+    buffer: &'de mut Vec<u8>,
+    limit: Option<usize>,
+) -> Result<T>
+where
+    T: Deserialize<'de>,
+    R: std::io::Read,
+{
+    buffer.clear();
+    read::read_to_end(reader, buffer, limit)?;
+    deserialize(buffer)
 }
 
 /// Deserializes binary data from the given reader into a new instance of `T`.
@@ -59,15 +580,124 @@ where
     R: Read<'de>,
 {
     let mut decoder = Decoder::new(reader);
-    T::deserialize(&mut decoder)
+    deserialize_value(&mut decoder)
+}
+
+/// Deserializes binary data from the given reader, which is named by this
+/// crate's [`Read`] trait, into a new instance of `T`.
+///
+/// This behaves exactly like [`deserialize_from`]. Since [`Read`] is
+/// implemented for every [`std::io::Read`], both accept in-memory readers
+/// such as a [`BytesReader`] as well as files and sockets. Use this name to
+/// make the trait plain at the call site, for instance alongside
+/// [`deserialize_from_borrowed`], which takes a [`std::io::Read`] instead,
+/// or when `std::io::Read` is also in scope and method calls on the reader
+/// become ambiguous.
+pub fn deserialize_from_reader<'de, T, R>(reader: &mut R) -> Result<T>
+where
+    T: DeserializeOwned,
+    R: Read<'de>,
+{
+    deserialize_from(reader)
+}
+
+/// Deserializes a top-level value with the given decoder, failing with
+/// [`Error::EmptyInput`] rather than as truncated if the reader knew there was
+/// no input left to decode it from.
+fn deserialize_value<'de, T, R>(decoder: &mut Decoder<'de, '_, R>) -> Result<T>
+where
+    T: Deserialize<'de>,
+    R: Read<'de>,
+{
+    let empty = decoder.reader().remaining_len() == Some(0);
+
+    T::deserialize(&mut *decoder).map_err(|err| match err.innermost() {
+        Error::UnexpectedEof | Error::TruncatedValue { .. } | Error::IncompleteStruct { .. }
+            if empty =>
+        {
+            Error::EmptyInput
+        }
+        _ => err,
+    })
+}
+
+/// Deserializes exactly `n` consecutive values from the given reader, failing
+/// if the input holds fewer or more.
+///
+/// Input that ends between two values before all `n` have been decoded fails
+/// with [`Error::UnexpectedEof`] if the reader knows how much input remains,
+/// as a [`BytesReader`] does, and as truncated otherwise. Input left over after
+/// the last value fails with [`Error::TrailingBytes`]. A reader that doesn't
+/// know how much input remains has a single byte read from it to check for
+/// this, rather than being drained, so its `count` is then 1.
+///
+/// `n` often comes from an untrusted source such as a file header, so no more
+/// than a bounded number of values are reserved for up front.
+pub fn deserialize_n<'de, T, R>(reader: &mut R, n: usize) -> Result<Vec<T>>
+where
+    T: DeserializeOwned,
+    R: Read<'de>,
+{
+    let mut decoder = Decoder::new(reader);
+    let mut values = Vec::with_capacity(n.min(MAX_READ_PREALLOC));
+
+    for _ in 0..n {
+        if decoder.reader().remaining_len() == Some(0) {
+            return Err(Error::UnexpectedEof);
+        }
+
+        values.push(T::deserialize(&mut decoder)?);
+    }
+
+    let reader = decoder.reader();
+    let count = match reader.remaining_len() {
+        Some(count) => count,
+        None => match reader.read_u8() {
+            Ok(_) => 1,
+            Err(Error::UnexpectedEof) => 0,
+            Err(err) => return Err(err),
+        },
+    };
+
+    if count > 0 {
+        return Err(Error::TrailingBytes { count });
+    }
+
+    Ok(values)
+}
+
+/// Serializes a value and immediately deserializes it again, returning the
+/// decoded copy. This is meant for testing custom `Serialize` and
+/// `Deserialize` impls, which should decode to a value equal to the original
+/// and consume every byte they wrote, and is only available with the
+/// `testing` feature enabled.
+///
+/// Bytes left over after decoding the value fail with
+/// [`Error::TrailingBytes`], since they show that the two impls disagree.
+#[cfg(feature = "testing")]
+pub fn round_trip<T>(value: &T) -> Result<T>
+where
+    T: Serialize + DeserializeOwned,
+{
+    let serialized = serialize(value)?;
+    let (value, len) = deserialize_with_len(&serialized)?;
+
+    if len < serialized.len() {
+        return Err(Error::TrailingBytes {
+            count: serialized.len() - len,
+        });
+    }
+
+    Ok(value)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::util::MAX_EMPTY_ELEMENTS;
     use once_cell::sync::Lazy;
     use serde::{Deserialize, Serialize};
-    use std::collections::HashMap;
+    use std::collections::{BTreeMap, HashMap};
     use std::io::Seek;
 
     macro_rules! map {
@@ -512,28 +1142,4201 @@ mod tests {
         let mut decoder = Decoder::new(&mut file);
         let res = MyStruct::deserialize(&mut decoder);
         assert!(matches!(
-            res,
-            Result::Err(Error::Custom(message)) if message.as_str() == "invalid type: string \"my string\", expected a borrowed string"
+            res.as_ref().map_err(Error::innermost),
+            Err(Error::CannotBorrowFromReader { ty: ValueType::Str })
+        ));
+
+        // a visitor that rejects strings altogether gets the usual type error
+        struct Number;
+
+        impl<'de> Deserialize<'de> for Number {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct NumberVisitor;
+
+                impl serde::de::Visitor<'_> for NumberVisitor {
+                    type Value = Number;
+
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        formatter.write_str("a number")
+                    }
+                }
+
+                deserializer.deserialize_str(NumberVisitor)
+            }
+        }
+
+        let mut file = tempfile::tempfile().unwrap();
+        serialize_into(&"seven", &mut file).unwrap();
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        assert!(matches!(
+            Number::deserialize(&mut decoder),
+            Err(Error::Custom(msg)) if msg == "invalid type: string \"seven\", expected a number"
         ));
     }
 
     #[test]
-    fn test_no_borrows_with_file() {
-        // test no borrows with file
+    fn test_borrowed_tuple_elements() {
+        let value = ("name", 42u32, &b"\x00\x01\x02"[..]);
+        let serialized = serialize(&value).unwrap();
+
+        // borrowed elements alias the input buffer
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader);
+        let deserialized = <(&str, u32, &[u8])>::deserialize(&mut decoder).unwrap();
+        assert_eq!(deserialized, value);
+        let input = serialized.as_ptr_range();
+        assert!(input.contains(&deserialized.0.as_ptr()));
+        assert!(input.contains(&deserialized.2.as_ptr()));
+
+        // a file can't lend its bytes to borrowed elements...
         let mut file = tempfile::tempfile().unwrap();
-        let mut encoder = Encoder::new(&mut file);
-        VALUE_NO_BORROWS.serialize(&mut encoder).unwrap();
+        serialize_into(&value, &mut file).unwrap();
         file.rewind().unwrap();
         let mut decoder = Decoder::new(&mut file);
-        let deserialized_value_no_borrows = MyStructNoBorrows::deserialize(&mut decoder).unwrap();
-        assert_eq!(*VALUE_NO_BORROWS, deserialized_value_no_borrows);
+        assert!(matches!(
+            <(&str, u32, &[u8])>::deserialize(&mut decoder),
+            Err(Error::InTupleElement { index: 0, source })
+                if matches!(*source, Error::CannotBorrowFromReader { ty: ValueType::Str })
+        ));
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        assert!(matches!(
+            <(String, u32, &[u8])>::deserialize(&mut decoder),
+            Err(Error::InTupleElement { index: 2, source })
+                if matches!(*source, Error::CannotBorrowFromReader { ty: ValueType::Bytes })
+        ));
+
+        // ...but decodes the same tuple with owned elements
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        assert_eq!(
+            <(String, u32, Vec<u8>)>::deserialize(&mut decoder).unwrap(),
+            ("name".to_owned(), 42, vec![0, 1, 2])
+        );
     }
 
     #[test]
-    fn test_skips() {
-        let serialized_value = serialize(&*VALUE_WITH_SKIPS).unwrap();
-        let deserialized_value = deserialize::<MyStructWithSkips>(&serialized_value).unwrap();
-        assert_eq!(*VALUE_WITH_SKIPS, deserialized_value);
+    fn test_borrowed_option() {
+        // the string inside an option borrows from the input
+        let serialized = serialize(&Some("inner")).unwrap();
+        assert_eq!(serialized[..3], [1, 1, 5]);
+        let deserialized = deserialize::<Option<&str>>(&serialized).unwrap();
+        assert_eq!(deserialized, Some("inner"));
+        assert!(serialized
+            .as_ptr_range()
+            .contains(&deserialized.unwrap().as_ptr()));
+
+        let serialized = serialize(&None::<&str>).unwrap();
+        assert_eq!(serialized, [0]);
+        assert_eq!(deserialize::<Option<&str>>(&serialized).unwrap(), None);
+
+        // a file can't lend its bytes to a present string, but has nothing to
+        // lend for an absent one
+        let mut file = tempfile::tempfile().unwrap();
+        serialize_into(&Some("inner"), &mut file).unwrap();
+        serialize_into(&None::<&str>, &mut file).unwrap();
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        assert!(matches!(
+            Option::<&str>::deserialize(&mut decoder),
+            Err(Error::CannotBorrowFromReader { ty: ValueType::Str })
+        ));
+
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        assert_eq!(
+            Option::<String>::deserialize(&mut decoder).unwrap(),
+            Some("inner".to_owned())
+        );
+        assert_eq!(Option::<&str>::deserialize(&mut decoder).unwrap(), None);
+    }
+
+    #[test]
+    fn test_explicit_reader_and_writer() {
+        // in-memory readers and writers implement only this crate's traits
+        let mut writer = BytesWriter::new();
+        serialize_into_writer(&*VALUE_NO_BORROWS, &mut writer).unwrap();
+        let serialized = writer.into_inner();
+        assert_eq!(serialized, serialize(&*VALUE_NO_BORROWS).unwrap());
+        let mut reader = BytesReader::new(&serialized);
+        assert_eq!(
+            deserialize_from_reader::<MyStructNoBorrows, _>(&mut reader).unwrap(),
+            *VALUE_NO_BORROWS
+        );
+
+        // files implement them through `std::io`
+        let mut file = tempfile::tempfile().unwrap();
+        serialize_into_writer(&(1u32, "file".to_owned()), &mut file).unwrap();
+        file.rewind().unwrap();
+        assert_eq!(
+            deserialize_from_reader::<(u32, String), _>(&mut file).unwrap(),
+            (1, "file".to_owned())
+        );
+    }
+
+    #[test]
+    fn test_borrows_with_buffered_file() {
+        let mut file = tempfile::tempfile().unwrap();
+        serialize_into(&*VALUE, &mut file).unwrap();
+        file.rewind().unwrap();
+        let buffered = BufferedReader::new(&mut file).unwrap();
+        let mut reader = buffered.reader();
+        let mut decoder = Decoder::new(&mut reader);
+        let value = MyStruct::deserialize(&mut decoder).unwrap();
+        assert_eq!(value, *VALUE);
+        assert!(buffered
+            .as_slice()
+            .as_ptr_range()
+            .contains(&value.str_field.as_ptr()));
+
+        // the limit applies to the whole stream
+        let len = buffered.as_slice().len();
+        file.rewind().unwrap();
+        assert!(matches!(
+            BufferedReader::with_limit(&mut file, len - 1),
+            Err(Error::InputLimitExceeded { limit }) if limit == len - 1
+        ));
+        file.rewind().unwrap();
+        let buffered = BufferedReader::with_limit(&mut file, len).unwrap();
+        assert_eq!(buffered.into_inner(), serialize(&*VALUE).unwrap());
+    }
+
+    #[test]
+    fn test_borrowed_bytes_with_file() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct BorrowedBytes<'a> {
+            bytes: &'a [u8],
+        }
+
+        let mut file = tempfile::tempfile().unwrap();
+        serialize_into(&BorrowedBytes { bytes: &[1, 2, 3] }, &mut file).unwrap();
+        file.rewind().unwrap();
+        let res = deserialize_from::<Vec<u8>, _>(&mut file);
+        assert_eq!(res.unwrap(), [1, 2, 3]);
+
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        let res = BorrowedBytes::deserialize(&mut decoder);
+        assert!(matches!(
+            res.as_ref().map_err(Error::innermost),
+            Err(Error::CannotBorrowFromReader {
+                ty: ValueType::Bytes
+            })
+        ));
+        assert!(res
+            .unwrap_err()
+            .to_string()
+            .contains("deserialize from a `BytesReader` or use an owned type"));
+    }
+
+    #[test]
+    fn test_no_borrows_with_file() {
+        // test no borrows with file
+        let mut file = tempfile::tempfile().unwrap();
+        let mut encoder = Encoder::new(&mut file);
+        VALUE_NO_BORROWS.serialize(&mut encoder).unwrap();
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file);
+        let deserialized_value_no_borrows = MyStructNoBorrows::deserialize(&mut decoder).unwrap();
+        assert_eq!(*VALUE_NO_BORROWS, deserialized_value_no_borrows);
+    }
+
+    #[test]
+    fn test_skips() {
+        let serialized_value = serialize(&*VALUE_WITH_SKIPS).unwrap();
+        let deserialized_value = deserialize::<MyStructWithSkips>(&serialized_value).unwrap();
+        assert_eq!(*VALUE_WITH_SKIPS, deserialized_value);
+    }
+
+    #[test]
+    fn test_canonical_lengths() {
+        // a length of 5 encoded with a redundant leading zero byte
+        let bytes = [2, 0, 5, 1, 2, 3, 4, 5];
+
+        // lenient by default
+        let value = deserialize::<Vec<u8>>(&bytes).unwrap();
+        assert_eq!(value, [1, 2, 3, 4, 5]);
+
+        // rejected in canonical mode
+        let mut reader = BytesReader::new(&bytes);
+        let mut decoder = Decoder::new(&mut reader).with_canonical(true);
+        let res = Vec::<u8>::deserialize(&mut decoder);
+        assert!(matches!(
+            res,
+            Err(Error::NonCanonicalLength(len_bytes)) if len_bytes == [2, 0, 5]
+        ));
+
+        // minimal lengths are accepted in canonical mode
+        let bytes = [1, 5, 1, 2, 3, 4, 5];
+        let mut reader = BytesReader::new(&bytes);
+        let mut decoder = Decoder::new(&mut reader).with_canonical(true);
+        let value = Vec::<u8>::deserialize(&mut decoder).unwrap();
+        assert_eq!(value, [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_multi_byte_chars() {
+        for (chr, expected) in [
+            ('A', vec![1, 0x41]),
+            ('é', vec![2, 0xc3, 0xa9]),
+            ('€', vec![3, 0xe2, 0x82, 0xac]),
+            ('🦀', vec![4, 0xf0, 0x9f, 0xa6, 0x80]),
+        ] {
+            let serialized = serialize(&chr).unwrap();
+            assert_eq!(serialized, expected);
+            let deserialized = deserialize::<char>(&serialized).unwrap();
+            assert_eq!(deserialized, chr);
+        }
+    }
+
+    #[test]
+    fn test_invalid_char_len() {
+        // zero-length char
+        let res = deserialize::<char>(&[0]);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidBytes { ty: ValueType::Char, bytes }) if bytes == [0]
+        ));
+
+        // char longer than four bytes
+        let res = deserialize::<char>(&[5, 0xf0, 0x9f, 0xa6, 0x80, 0x41]);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidBytes { ty: ValueType::Char, bytes }) if bytes == [5]
+        ));
+
+        // length byte covering more than one char
+        let res = deserialize::<char>(&[2, 0x41, 0x42]);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidBytes { ty: ValueType::Char, bytes }) if bytes == [0x41, 0x42]
+        ));
+    }
+
+    #[test]
+    fn test_fixed_char_encoding() {
+        fn serialize_fixed(chr: char) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_char_encoding(CharEncoding::Fixed);
+            chr.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_fixed(bytes: &[u8]) -> Result<char> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_char_encoding(CharEncoding::Fixed);
+            char::deserialize(&mut decoder)
+        }
+
+        for (chr, utf8, fixed) in [
+            ('A', &[1, 0x41][..], [0, 0, 0, 0x41]),
+            ('🦀', &[4, 0xf0, 0x9f, 0xa6, 0x80][..], [0, 1, 0xf9, 0x80]),
+        ] {
+            assert_eq!(serialize(&chr).unwrap(), utf8);
+            assert_eq!(serialize_fixed(chr), fixed);
+            assert_eq!(deserialize::<char>(utf8).unwrap(), chr);
+            assert_eq!(deserialize_fixed(&fixed).unwrap(), chr);
+        }
+
+        // beyond the last code point
+        let res = deserialize_fixed(&[0, 0x11, 0, 0]);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidBytes { ty: ValueType::Char, bytes }) if bytes == [0, 0x11, 0, 0]
+        ));
+
+        // a surrogate
+        let res = deserialize_fixed(&[0, 0, 0xd8, 0]);
+        assert!(matches!(
+            res,
+            Err(Error::InvalidBytes {
+                ty: ValueType::Char,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_serialize_into_atomic() {
+        struct FailsMidway;
+
+        impl Serialize for FailsMidway {
+            fn serialize<S>(&self, serializer: S) -> core::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                use serde::ser::{Error as _, SerializeTuple};
+
+                let mut tuple = serializer.serialize_tuple(3)?;
+                tuple.serialize_element(&1u8)?;
+                tuple.serialize_element(&2u8)?;
+                Err(S::Error::custom("failed midway"))
+            }
+        }
+
+        // a regular write leaves partial output behind
+        let mut writer = Vec::new();
+        assert!(serialize_into(&FailsMidway, &mut writer).is_err());
+        assert_eq!(writer, [1, 2]);
+
+        // an atomic write leaves the writer untouched
+        let mut writer = Vec::new();
+        assert!(serialize_into_atomic(&FailsMidway, &mut writer).is_err());
+        assert!(writer.is_empty());
+
+        // successful atomic writes reach the writer
+        let mut writer = Vec::new();
+        serialize_into_atomic(&*VALUE, &mut writer).unwrap();
+        assert_eq!(writer, serialize(&*VALUE).unwrap());
+    }
+
+    #[test]
+    fn test_signed_bytes() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Samples {
+            #[serde(with = "crate::signed_bytes")]
+            values: Vec<i8>,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct SequenceSamples {
+            values: Vec<i8>,
+        }
+
+        let values = (0..100_000)
+            .map(|i| (i % 256) as u8 as i8)
+            .collect::<Vec<_>>();
+        assert!(values.contains(&i8::MIN) && values.contains(&-1));
+        let samples = Samples { values };
+
+        // the block encoding matches the per-element sequence encoding
+        let serialized = serialize(&samples).unwrap();
+        assert_eq!(serialized, serialize(&samples.values).unwrap());
+
+        // the decoder hands byte buffers over as a whole block, so this
+        // round-trip doesn't go through the per-element path
+        let deserialized = deserialize::<Samples>(&serialized).unwrap();
+        assert_eq!(deserialized, samples);
+
+        // both encodings are interchangeable
+        let deserialized = deserialize::<Vec<i8>>(&serialized).unwrap();
+        assert_eq!(deserialized, samples.values);
+
+        // but only in the default configuration
+        for options in [
+            builder().tagged(true).build(),
+            builder().framed(true).build(),
+        ] {
+            let block = serialize_with(&samples, &options).unwrap();
+            let sequence = serialize_with(
+                &SequenceSamples {
+                    values: samples.values.clone(),
+                },
+                &options,
+            )
+            .unwrap();
+            assert_ne!(block, sequence);
+            assert_eq!(
+                deserialize_with::<Samples>(&block, &options).unwrap(),
+                samples
+            );
+        }
+
+        // formats that hand over sequences are still accepted
+        let values = serde::de::value::SeqDeserializer::<_, serde::de::value::Error>::new(
+            [1i8, -1, i8::MIN].into_iter(),
+        );
+        assert_eq!(
+            crate::signed_bytes::deserialize(values).unwrap(),
+            [1, -1, i8::MIN]
+        );
+    }
+
+    #[test]
+    fn test_path() {
+        use std::path::{Path, PathBuf};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct IndexEntry {
+            #[serde(with = "crate::path")]
+            path: PathBuf,
+            #[serde(with = "crate::path::lossy")]
+            target: PathBuf,
+        }
+
+        let entry = IndexEntry {
+            path: PathBuf::from("docs/read me.txt"),
+            target: PathBuf::from(r"C:\Users"),
+        };
+        let serialized = serialize(&entry).unwrap();
+        let mut expected = vec![1, 16];
+        expected.extend_from_slice(b"docs/read me.txt");
+        expected.extend_from_slice(&[1, 8]);
+        expected.extend_from_slice(br"C:\Users");
+        assert_eq!(serialized, expected);
+        assert_eq!(deserialize::<IndexEntry>(&serialized).unwrap(), entry);
+
+        // the layout matches serde's own for UTF-8 paths, and a plain string
+        assert_eq!(
+            serialize(&(&entry.path, &entry.target)).unwrap(),
+            serialized
+        );
+        assert_eq!(
+            deserialize::<(&str, &str)>(&serialized).unwrap(),
+            ("docs/read me.txt", r"C:\Users")
+        );
+        assert_eq!(serialize(&(Path::new(""), Path::new(""))).unwrap(), [0, 0]);
+
+        // paths that aren't valid UTF-8 fail, or are written lossily
+        #[cfg(unix)]
+        {
+            use std::ffi::OsStr;
+            use std::os::unix::ffi::OsStrExt;
+
+            let entry = IndexEntry {
+                path: PathBuf::from("ok"),
+                target: PathBuf::from(OsStr::from_bytes(b"caf\xe9")),
+            };
+            let serialized = serialize(&entry).unwrap();
+            assert_eq!(serialized[4..], [1, 6, b'c', b'a', b'f', 0xef, 0xbf, 0xbd]);
+            assert_eq!(
+                deserialize::<IndexEntry>(&serialized).unwrap().target,
+                PathBuf::from("caf\u{fffd}")
+            );
+
+            let entry = IndexEntry {
+                path: entry.target,
+                target: PathBuf::from("ok"),
+            };
+            assert!(matches!(
+                serialize(&entry).as_ref().map_err(Error::innermost),
+                Err(Error::Custom(message)) if message.contains("not valid UTF-8")
+            ));
+        }
+    }
+
+    #[test]
+    fn test_owned_encoder() {
+        let mut encoder = Encoder::owned(BytesWriter::new());
+        VALUE.serialize(&mut encoder).unwrap();
+        let writer: BytesWriter = encoder.into_writer();
+        assert_eq!(writer.into_inner(), serialize(&*VALUE).unwrap());
+
+        // settings carry over to owned encoders
+        let mut encoder = Encoder::owned(Vec::new()).with_length_encoding(LengthEncoding::Varint);
+        vec![1u8; 200].serialize(&mut encoder).unwrap();
+        assert_eq!(encoder.writer()[..2], [0xc8, 0x01]);
+        assert_eq!(encoder.into_writer().len(), 202);
+    }
+
+    #[test]
+    fn test_deserialize_with_len() {
+        let first = MyInnerStruct {
+            a: (),
+            b: true,
+            c: 1,
+        };
+        let second = MyInnerStruct {
+            a: (),
+            b: false,
+            c: 2,
+        };
+        let mut bytes = serialize(&first).unwrap();
+        bytes.extend(serialize(&second).unwrap());
+
+        let (value, len) = deserialize_with_len::<MyInnerStruct>(&bytes).unwrap();
+        assert_eq!(value, first);
+        assert_eq!(len, 2);
+
+        let (value, len) = deserialize_with_len::<MyInnerStruct>(&bytes[len..]).unwrap();
+        assert_eq!(value, second);
+        assert_eq!(len, 2);
+
+        let serialized_value = serialize(&*VALUE).unwrap();
+        let (value, len) = deserialize_with_len::<MyStruct>(&serialized_value).unwrap();
+        assert_eq!(value, *VALUE);
+        assert_eq!(len, serialized_value.len());
+    }
+
+    #[test]
+    fn test_nested_options() {
+        for (value, expected) in [
+            (None, vec![0]),
+            (Some(None), vec![1, 0]),
+            (Some(Some(5u8)), vec![1, 1, 5]),
+        ] {
+            let serialized = serialize(&value).unwrap();
+            assert_eq!(serialized, expected);
+            let deserialized = deserialize::<Option<Option<u8>>>(&serialized).unwrap();
+            assert_eq!(deserialized, value);
+        }
+    }
+
+    #[test]
+    fn test_serialize_into_references() {
+        let expected = serialize(&*VALUE).unwrap();
+
+        // a vector
+        let mut writer = Vec::new();
+        serialize_into(&*VALUE, &mut writer).unwrap();
+        assert_eq!(writer, expected);
+
+        // a nested mutable reference to a vector
+        let mut writer = Vec::new();
+        let mut writer_ref = &mut writer;
+        serialize_into(&*VALUE, &mut writer_ref).unwrap();
+        serialize_into(&*VALUE, &mut &mut writer_ref).unwrap();
+        assert_eq!(writer, [expected.clone(), expected.clone()].concat());
+
+        // a mutable slice
+        let mut buf = vec![0; expected.len()];
+        let mut writer = buf.as_mut_slice();
+        serialize_into(&*VALUE, &mut writer).unwrap();
+        assert!(writer.is_empty());
+        assert_eq!(buf, expected);
+    }
+
+    #[test]
+    fn test_unit_sequence() {
+        // units take no bytes, so only the length is written
+        let units = vec![(), (), ()];
+        let serialized = serialize(&units).unwrap();
+        assert_eq!(serialized, [1, 3]);
+        assert_eq!(deserialize::<Vec<()>>(&serialized).unwrap(), units);
+        assert_eq!(
+            deserialize_with_len::<Vec<()>>(&serialized).unwrap(),
+            (units, 2)
+        );
+        assert_eq!(deserialize::<Vec<()>>(&[0]).unwrap(), []);
+    }
+
+    #[test]
+    fn test_zero_sized_types() {
+        use std::marker::PhantomData;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct MyEmptyTupleStruct();
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Zsts {
+            phantom: PhantomData<u32>,
+            unit: (),
+            unit_struct: MyUnitStruct,
+            empty_tuple_struct: MyEmptyTupleStruct,
+            empty_array: [u64; 0],
+        }
+
+        let serialized = serialize(&PhantomData::<u32>).unwrap();
+        assert!(serialized.is_empty());
+        deserialize::<PhantomData<u32>>(&serialized).unwrap();
+
+        let serialized = serialize(&MyUnitStruct).unwrap();
+        assert!(serialized.is_empty());
+        assert_eq!(
+            deserialize::<MyUnitStruct>(&serialized).unwrap(),
+            MyUnitStruct
+        );
+
+        let value = Zsts {
+            phantom: PhantomData,
+            unit: (),
+            unit_struct: MyUnitStruct,
+            empty_tuple_struct: MyEmptyTupleStruct(),
+            empty_array: [],
+        };
+        let serialized = serialize(&value).unwrap();
+        assert!(serialized.is_empty());
+        assert_eq!(deserialize::<Zsts>(&serialized).unwrap(), value);
+    }
+
+    #[test]
+    fn test_large_strings() {
+        let value = "unbin ✓ ".repeat(1 << 16);
+        let serialized = serialize(&value).unwrap();
+
+        // borrowed from a byte slice
+        assert_eq!(deserialize::<&str>(&serialized).unwrap(), value);
+        // owned from a byte slice
+        assert_eq!(deserialize::<String>(&serialized).unwrap(), value);
+        // owned from an I/O reader
+        let mut reader = serialized.as_slice();
+        assert_eq!(deserialize_from::<String, _>(&mut reader).unwrap(), value);
+
+        // invalid UTF-8 is still rejected on every path
+        let mut invalid = serialized.clone();
+        *invalid.last_mut().unwrap() = 0xff;
+        assert!(matches!(
+            deserialize::<&str>(&invalid),
+            Err(Error::InvalidUtf8 {
+                ty: ValueType::Str,
+                ..
+            })
+        ));
+        assert!(matches!(
+            deserialize::<String>(&invalid),
+            Err(Error::InvalidUtf8 {
+                ty: ValueType::String,
+                ..
+            })
+        ));
+        let mut reader = invalid.as_slice();
+        assert!(matches!(
+            deserialize_from::<String, _>(&mut reader),
+            Err(Error::InvalidUtf8 {
+                ty: ValueType::String,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_net() {
+        use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Peer {
+            #[serde(with = "crate::net::ip_addr")]
+            ip: IpAddr,
+            #[serde(with = "crate::net::socket_addr")]
+            addr: SocketAddr,
+        }
+
+        let peer = Peer {
+            ip: IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)),
+            addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 8080),
+        };
+        let serialized = serialize(&peer).unwrap();
+        assert_eq!(serialized, [4, 192, 168, 0, 1, 4, 127, 0, 0, 1, 0x1f, 0x90]);
+        assert_eq!(deserialize::<Peer>(&serialized).unwrap(), peer);
+
+        let peer = Peer {
+            ip: IpAddr::V6(Ipv6Addr::LOCALHOST),
+            addr: SocketAddr::new(
+                IpAddr::V6(Ipv6Addr::new(0x2001, 0xdb8, 0, 0, 0, 0, 0, 0x42)),
+                443,
+            ),
+        };
+        let serialized = serialize(&peer).unwrap();
+        assert_eq!(
+            serialized,
+            [
+                6, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, //
+                6, 0x20, 0x01, 0x0d, 0xb8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x42, //
+                0x01, 0xbb,
+            ]
+        );
+        assert_eq!(deserialize::<Peer>(&serialized).unwrap(), peer);
+
+        // unknown tags are rejected
+        assert!(matches!(
+            deserialize::<Peer>(&[5, 192, 168, 0, 1]),
+            Err(Error::InStructField { field: "ip", source }) if matches!(*source, Error::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_varint_lengths() {
+        fn serialize_varint<T: Serialize>(value: &T) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder =
+                Encoder::new(&mut writer).with_length_encoding(LengthEncoding::Varint);
+            value.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_varint<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder =
+                Decoder::new(&mut reader).with_length_encoding(LengthEncoding::Varint);
+            T::deserialize(&mut decoder)
+        }
+
+        // collection lengths use varints
+        let value = vec![7u8; 300];
+        let serialized = serialize_varint(&value);
+        assert_eq!(serialized[..2], [0xac, 0x02]);
+        assert_eq!(serialized.len(), 302);
+        assert_eq!(deserialize_varint::<Vec<u8>>(&serialized).unwrap(), value);
+
+        let serialized = serialize_varint(&"my string");
+        assert_eq!(serialized[..1], [9]);
+        assert_eq!(
+            deserialize_varint::<&str>(&serialized).unwrap(),
+            "my string"
+        );
+
+        let serialized = serialize_varint(&Vec::<u8>::new());
+        assert_eq!(serialized, [0]);
+
+        // char lengths are untouched
+        for chr in ['A', 'é', '🦀'] {
+            assert_eq!(serialize_varint(&chr), serialize(&chr).unwrap());
+        }
+
+        // everything round-trips
+        let serialized = serialize_varint(&*VALUE);
+        assert_eq!(deserialize_varint::<MyStruct>(&serialized).unwrap(), *VALUE);
+
+        // overlong encodings are only rejected in canonical mode
+        let bytes = [0x83, 0x00, 1, 2, 3];
+        assert_eq!(deserialize_varint::<Vec<u8>>(&bytes).unwrap(), [1, 2, 3]);
+        let mut reader = BytesReader::new(&bytes);
+        let mut decoder = Decoder::new(&mut reader)
+            .with_length_encoding(LengthEncoding::Varint)
+            .with_canonical(true);
+        assert!(matches!(
+            Vec::<u8>::deserialize(&mut decoder),
+            Err(Error::NonCanonicalLength(len_bytes)) if len_bytes == [0x83, 0x00]
+        ));
+
+        // lengths that don't fit in a usize are rejected
+        let bytes = [0xff; 16];
+        assert!(matches!(
+            deserialize_varint::<Vec<u8>>(&bytes),
+            Err(Error::LengthOverflow(_))
+        ));
+    }
+
+    #[test]
+    fn test_chained_bytes_reader() {
+        use std::borrow::Cow;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Strings<'a> {
+            #[serde(borrow)]
+            first: Cow<'a, str>,
+            #[serde(borrow)]
+            second: Cow<'a, str>,
+            number: u32,
+        }
+
+        let value = Strings {
+            first: "within a chunk".into(),
+            second: "across chunks".into(),
+            number: 0x01020304,
+        };
+        let serialized = serialize(&value).unwrap();
+        // split partway through the second string and the number
+        let (first_chunk, rest) = serialized.split_at(serialized.len() - 8);
+        let (second_chunk, third_chunk) = rest.split_at(6);
+
+        let mut reader = ChainedBytesReader::new([first_chunk, &[], second_chunk, third_chunk]);
+        let mut decoder = Decoder::new(&mut reader);
+        let deserialized = Strings::deserialize(&mut decoder).unwrap();
+        assert_eq!(deserialized, value);
+        assert!(matches!(deserialized.first, Cow::Borrowed(_)));
+        assert!(matches!(deserialized.second, Cow::Owned(_)));
+
+        // running out of chunks is an error
+        let mut reader = ChainedBytesReader::new([first_chunk, second_chunk]);
+        let mut decoder = Decoder::new(&mut reader);
+        assert!(matches!(
+            Strings::deserialize(&mut decoder)
+                .as_ref()
+                .map_err(Error::innermost),
+            Err(Error::TruncatedValue { ty: ValueType::U32 })
+        ));
+    }
+
+    #[test]
+    fn test_chained_bytes_reader_borrowed_str() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Strings<'a> {
+            first: &'a str,
+            second: &'a str,
+        }
+
+        let value = Strings {
+            first: "within a chunk",
+            second: "across chunks",
+        };
+        let serialized = serialize(&value).unwrap();
+        let (first_chunk, second_chunk) = serialized.split_at(serialized.len() - 4);
+
+        // a string within a chunk is borrowed straight from it
+        let mut reader = ChainedBytesReader::new([first_chunk]);
+        let mut decoder = Decoder::new(&mut reader);
+        let first = <&str>::deserialize(&mut decoder).unwrap();
+        assert_eq!(first, value.first);
+        assert!(first_chunk.as_ptr_range().contains(&first.as_ptr()));
+
+        // one straddling chunks can only be copied, which `&str` can't accept
+        let mut reader = ChainedBytesReader::new([first_chunk, second_chunk]);
+        let mut decoder = Decoder::new(&mut reader);
+        assert!(matches!(
+            Strings::deserialize(&mut decoder),
+            Err(Error::InStructField { field: "second", source })
+                if matches!(*source, Error::CannotBorrowFromReader { ty: ValueType::Str })
+        ));
+
+        // the same split within a single chunk borrows both
+        let mut reader = ChainedBytesReader::new([&serialized[..]]);
+        let mut decoder = Decoder::new(&mut reader);
+        assert_eq!(Strings::deserialize(&mut decoder).unwrap(), value);
+    }
+
+    #[test]
+    fn test_float_bytes() {
+        for (value, expected) in [
+            (0.0f64, [0x00, 0, 0, 0, 0, 0, 0, 0]),
+            (-0.0, [0x80, 0, 0, 0, 0, 0, 0, 0]),
+            (f64::MIN_POSITIVE, [0x00, 0x10, 0, 0, 0, 0, 0, 0]),
+            (f64::from_bits(1), [0, 0, 0, 0, 0, 0, 0, 1]),
+            (f64::INFINITY, [0x7f, 0xf0, 0, 0, 0, 0, 0, 0]),
+            (f64::NEG_INFINITY, [0xff, 0xf0, 0, 0, 0, 0, 0, 0]),
+            (
+                f64::from_bits(0x7ff8_0000_0000_0001),
+                [0x7f, 0xf8, 0, 0, 0, 0, 0, 1],
+            ),
+        ] {
+            let serialized = serialize(&value).unwrap();
+            assert_eq!(serialized, expected);
+            let deserialized = deserialize::<f64>(&serialized).unwrap();
+            assert_eq!(deserialized.to_bits(), value.to_bits());
+        }
+
+        for (value, expected) in [
+            (0.0f32, [0x00, 0, 0, 0]),
+            (-0.0, [0x80, 0, 0, 0]),
+            (f32::MIN_POSITIVE, [0x00, 0x80, 0, 0]),
+            (f32::from_bits(1), [0, 0, 0, 1]),
+            (f32::INFINITY, [0x7f, 0x80, 0, 0]),
+            (f32::NEG_INFINITY, [0xff, 0x80, 0, 0]),
+            (f32::from_bits(0x7fc0_0001), [0x7f, 0xc0, 0, 1]),
+        ] {
+            let serialized = serialize(&value).unwrap();
+            assert_eq!(serialized, expected);
+            let deserialized = deserialize::<f32>(&serialized).unwrap();
+            assert_eq!(deserialized.to_bits(), value.to_bits());
+        }
+    }
+
+    #[test]
+    fn test_custom_errors() {
+        use std::borrow::Cow;
+
+        let err = Error::Custom(Cow::Borrowed("static message"));
+        assert_eq!(err.to_string(), "serialization error: static message");
+
+        let err = <Error as serde::de::Error>::custom(format_args!("dynamic {}", "message"));
+        assert!(matches!(&err, Error::Custom(Cow::Owned(message)) if message == "dynamic message"));
+        assert_eq!(err.to_string(), "serialization error: dynamic message");
+
+        let err = <Error as serde::ser::Error>::custom("message");
+        assert_eq!(err.to_string(), "serialization error: message");
+    }
+
+    #[test]
+    fn test_borrowed_bytes() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct BorrowedBytes<'a> {
+            number: u16,
+            bytes: &'a [u8],
+        }
+
+        let value = BorrowedBytes {
+            number: 7,
+            bytes: &[1, 2, 3, 4, 5],
+        };
+        let serialized = serialize(&value).unwrap();
+        let deserialized = deserialize::<BorrowedBytes>(&serialized).unwrap();
+        assert_eq!(deserialized, value);
+
+        // the deserialized slice points into the input buffer
+        let input = serialized.as_ptr_range();
+        let output = deserialized.bytes.as_ptr_range();
+        assert!(input.start <= output.start && output.end <= input.end);
+        assert_eq!(output.end, input.end);
+
+        // so does the bytes field of the full fixture
+        let serialized = serialize(&*VALUE).unwrap();
+        let deserialized = deserialize::<MyStruct>(&serialized).unwrap();
+        let input = serialized.as_ptr_range();
+        let output = deserialized.bytes_field.as_ptr_range();
+        assert!(input.start <= output.start && output.end <= input.end);
+    }
+
+    #[test]
+    fn test_serialize_reserved() {
+        let expected = serialize(&*VALUE).unwrap();
+        assert_eq!(serialized_size(&*VALUE).unwrap(), expected.len());
+
+        let serialized = serialize_reserved(&*VALUE).unwrap();
+        assert_eq!(serialized, expected);
+        assert_eq!(serialized.capacity(), serialized.len());
+
+        let value = (0..10_000u32).map(|i| i.to_string()).collect::<Vec<_>>();
+        let serialized = serialize_reserved(&value).unwrap();
+        assert_eq!(serialized, serialize(&value).unwrap());
+        assert_eq!(serialized.capacity(), serialized.len());
+    }
+
+    #[test]
+    fn test_strict_bools() {
+        assert_eq!(serialize(&false).unwrap(), [0]);
+        assert_eq!(serialize(&true).unwrap(), [1]);
+        assert!(!deserialize::<bool>(&[0]).unwrap());
+        assert!(deserialize::<bool>(&[1]).unwrap());
+
+        for byte in [2, 0x80, 0xff] {
+            assert!(matches!(
+                deserialize::<bool>(&[byte]),
+                Err(Error::InvalidBytes { ty: ValueType::Bool, bytes }) if bytes == [byte]
+            ));
+        }
+    }
+
+    #[test]
+    fn test_invalid_discriminants() {
+        // each type of value reports itself on an invalid discriminant
+        assert!(matches!(
+            deserialize::<bool>(&[2]),
+            Err(Error::InvalidBytes { ty: ValueType::Bool, bytes }) if bytes == [2]
+        ));
+        assert!(matches!(
+            deserialize::<Option<u8>>(&[2, 7]),
+            Err(Error::InvalidBytes { ty: ValueType::Option, bytes }) if bytes == [2]
+        ));
+        assert!(matches!(
+            deserialize::<Option<bool>>(&[1, 2]),
+            Err(Error::InvalidBytes { ty: ValueType::Bool, bytes }) if bytes == [2]
+        ));
+
+        // valid discriminants still decode
+        assert_eq!(deserialize::<Option<bool>>(&[0]).unwrap(), None);
+        assert_eq!(deserialize::<Option<bool>>(&[1, 1]).unwrap(), Some(true));
+    }
+
+    #[test]
+    fn test_large_tuples() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Tuple20(
+            u8,
+            u16,
+            u32,
+            u64,
+            i8,
+            i16,
+            i32,
+            i64,
+            bool,
+            char,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+            u8,
+        );
+
+        let value = Tuple20(
+            1, 2, 3, 4, -5, -6, -7, -8, true, 'a', 11, 12, 13, 14, 15, 16, 17, 18, 19, 20,
+        );
+        let serialized = serialize(&value).unwrap();
+        assert_eq!(
+            serialized,
+            [
+                vec![1],
+                vec![0, 2],
+                vec![0, 0, 0, 3],
+                vec![0, 0, 0, 0, 0, 0, 0, 4],
+                vec![0xfb],
+                vec![0xff, 0xfa],
+                vec![0xff, 0xff, 0xff, 0xf9],
+                vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xf8],
+                vec![1],
+                vec![1, b'a'],
+                (11..=20).collect(),
+            ]
+            .concat()
+        );
+        assert_eq!(deserialize::<Tuple20>(&serialized).unwrap(), value);
+
+        // a sequence of the same elements is length-prefixed, a tuple isn't
+        let tuple = (1u8, 2u8, 3u8, 4u8, 5u8, 6u8, 7u8, 8u8);
+        let elements = (1..=8u8).collect::<Vec<_>>();
+        assert_eq!(serialize(&tuple).unwrap(), elements);
+        assert_eq!(serialize(&elements).unwrap()[..2], [1, 8]);
+        assert_eq!(serialize(&elements).unwrap()[2..], elements);
+    }
+
+    #[test]
+    fn test_hashing_writer() {
+        use std::hash::{DefaultHasher, Hasher};
+
+        let mut writer = HashingWriter::new(DefaultHasher::new(), BytesWriter::new());
+        serialize_into(&*VALUE, &mut writer).unwrap();
+        let hash = writer.finish();
+        let (_, bytes) = writer.into_inner();
+        let bytes = bytes.into_inner();
+
+        let serialized = serialize(&*VALUE).unwrap();
+        assert_eq!(bytes, serialized);
+
+        let mut hasher = DefaultHasher::new();
+        hasher.write(&serialized);
+        assert_eq!(hash, hasher.finish());
+    }
+
+    #[test]
+    fn test_flush_on_collection_end() {
+        /// A writer that counts how often it is flushed.
+        #[derive(Default)]
+        struct FlushCounter {
+            bytes: Vec<u8>,
+            flushes: usize,
+        }
+
+        impl Write for FlushCounter {
+            fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+                self.bytes.extend_from_slice(buf);
+                Ok(())
+            }
+
+            fn flush(&mut self) -> Result<()> {
+                self.flushes += 1;
+                Ok(())
+            }
+        }
+
+        fn count_flushes<T: Serialize>(value: &T, flush: bool) -> usize {
+            let mut writer = FlushCounter::default();
+            let mut encoder = Encoder::new(&mut writer).with_flush_on_collection_end(flush);
+            value.serialize(&mut encoder).unwrap();
+            assert_eq!(writer.bytes, serialize(value).unwrap());
+            writer.flushes
+        }
+
+        // flushing is off by default
+        assert_eq!(count_flushes(&vec![1u8, 2, 3], false), 0);
+        assert_eq!(count_flushes(&map! { 1u8 => 2u8 }, false), 0);
+
+        // each sequence or map flushes once at its end, not per element
+        assert_eq!(count_flushes(&vec![1u8, 2, 3], true), 1);
+        assert_eq!(count_flushes(&map! { 1u8 => 2u8, 3 => 4 }, true), 1);
+        assert_eq!(count_flushes(&vec![vec![1u8], vec![2]], true), 3);
+
+        // other values don't flush
+        assert_eq!(count_flushes(&(1u8, "a", Some(2u16)), true), 0);
+    }
+
+    #[test]
+    fn test_length_codec() {
+        /// Every length as two little-endian bytes.
+        #[derive(Debug)]
+        struct TwoByteLength;
+
+        impl LengthCodec for TwoByteLength {
+            fn encode_len(&self, len: usize, out: &mut dyn Write) -> Result<()> {
+                let len =
+                    u16::try_from(len).map_err(|_| Error::Custom("length too large".into()))?;
+                out.write_all(&len.to_le_bytes())
+            }
+
+            fn decode_len(&self, reader: &mut dyn LengthReader) -> Result<usize> {
+                let mut bytes = [0; 2];
+                reader.read_exact(&mut bytes)?;
+                Ok(u16::from_le_bytes(bytes).into())
+            }
+        }
+
+        let options = builder()
+            .length_encoding(LengthEncoding::Custom(&TwoByteLength))
+            .build();
+        let value = vec![1u8, 2, 3];
+        let serialized = serialize_with(&value, &options).unwrap();
+        assert_eq!(serialized, [3, 0, 1, 2, 3]);
+        assert_eq!(
+            deserialize_with::<Vec<u8>>(&serialized, &options).unwrap(),
+            value
+        );
+
+        let value = vec!["a".to_owned(); 300];
+        let serialized = serialize_with(&value, &options).unwrap();
+        assert_eq!(serialized[..5], [0x2c, 0x01, 1, 0, b'a']);
+        assert_eq!(
+            deserialize_with::<Vec<String>>(&serialized, &options).unwrap(),
+            value
+        );
+        assert!(matches!(
+            deserialize_with::<Vec<u8>>(&[3, 0, 1], &options),
+            Err(Error::TruncatedValue { ty: ValueType::U8 })
+        ));
+
+        // codec errors surface from the encoder
+        assert!(matches!(
+            serialize_with(&vec![0u8; 70_000], &options),
+            Err(Error::Custom(_))
+        ));
+
+        // checksums cover the bytes the codec writes
+        let options = builder()
+            .length_encoding(LengthEncoding::Custom(&TwoByteLength))
+            .length_checksums(true)
+            .build();
+        let serialized = serialize_with(&vec![0u8; 258], &options).unwrap();
+        assert_eq!(serialized[..3], [0x02, 0x01, 0x03]);
+        assert_eq!(
+            deserialize_with::<Vec<u8>>(&serialized, &options).unwrap(),
+            vec![0u8; 258]
+        );
+
+        // the built-in codecs match the built-in encodings, down to which
+        // lengths canonical mode rejects
+        for (codec, length_encoding, non_canonical) in [
+            (
+                &PrefixedLength as &dyn LengthCodec,
+                LengthEncoding::Prefixed,
+                &[1, 0][..],
+            ),
+            (&VarintLength, LengthEncoding::Varint, &[0x80, 0][..]),
+        ] {
+            for length_encoding in [length_encoding, LengthEncoding::Custom(codec)] {
+                let options = builder().length_encoding(length_encoding).build();
+                assert_eq!(
+                    deserialize_with::<Vec<u8>>(non_canonical, &options).unwrap(),
+                    Vec::<u8>::new()
+                );
+                let options = builder()
+                    .length_encoding(length_encoding)
+                    .canonical(true)
+                    .build();
+                assert!(matches!(
+                    deserialize_with::<Vec<u8>>(non_canonical, &options),
+                    Err(Error::NonCanonicalLength(bytes)) if bytes == non_canonical
+                ));
+            }
+
+            let options = builder().length_encoding(length_encoding).build();
+            let custom = builder()
+                .length_encoding(LengthEncoding::Custom(codec))
+                .build();
+            let serialized = serialize_with(&*VALUE, &options).unwrap();
+            assert_eq!(serialize_with(&*VALUE, &custom).unwrap(), serialized);
+            assert_eq!(
+                deserialize_with::<MyStruct>(&serialized, &custom).unwrap(),
+                *VALUE
+            );
+        }
+
+        assert_eq!(
+            LengthEncoding::Custom(&TwoByteLength),
+            LengthEncoding::Custom(&TwoByteLength)
+        );
+        assert_ne!(
+            LengthEncoding::Custom(&PrefixedLength),
+            LengthEncoding::Prefixed
+        );
+
+        // distinct zero-sized codecs are told apart, even at the same address
+        assert_ne!(
+            LengthEncoding::Custom(&PrefixedLength),
+            LengthEncoding::Custom(&VarintLength)
+        );
+        assert_ne!(
+            LengthEncoding::Custom(&TwoByteLength),
+            LengthEncoding::Custom(&VarintLength)
+        );
+        let encodings = std::collections::HashSet::from([
+            LengthEncoding::Custom(&PrefixedLength),
+            LengthEncoding::Custom(&VarintLength),
+            LengthEncoding::Custom(&TwoByteLength),
+        ]);
+        assert_eq!(encodings.len(), 3);
+    }
+
+    #[test]
+    fn test_length_checksums() {
+        for length_encoding in [LengthEncoding::Prefixed, LengthEncoding::Varint] {
+            let options = builder()
+                .length_encoding(length_encoding)
+                .length_checksums(true)
+                .build();
+            let serialized = serialize_with(&*VALUE, &options).unwrap();
+            assert_eq!(
+                deserialize_with::<MyStruct>(&serialized, &options).unwrap(),
+                *VALUE
+            );
+        }
+
+        // the checksum follows the length bytes, all of them XORed together
+        let options = builder().length_checksums(true).build();
+        let serialized = serialize_with(&"abc", &options).unwrap();
+        assert_eq!(serialized, [1, 3, 1 ^ 3, b'a', b'b', b'c']);
+        let serialized = serialize_with(&"", &options).unwrap();
+        assert_eq!(serialized, [0, 0]);
+
+        let options = builder()
+            .length_encoding(LengthEncoding::Varint)
+            .length_checksums(true)
+            .build();
+        let serialized = serialize_with(&vec![0u8; 300], &options).unwrap();
+        assert_eq!(serialized[..3], [0xac, 0x02, 0xac ^ 0x02]);
+
+        // a flipped bit in a length prefix is caught
+        let mut corrupted = serialized.clone();
+        corrupted[1] ^= 0x01;
+        assert!(matches!(
+            deserialize_with::<Vec<u8>>(&corrupted, &options),
+            Err(Error::LengthChecksumMismatch {
+                expected: 0xaf,
+                found: 0xae
+            })
+        ));
+
+        let options = builder().length_checksums(true).build();
+        let mut corrupted = serialize_with(&"abc", &options).unwrap();
+        corrupted[1] ^= 0x80;
+        assert!(matches!(
+            deserialize_with::<String>(&corrupted, &options),
+            Err(Error::LengthChecksumMismatch {
+                expected: 0x82,
+                found: 0x02
+            })
+        ));
+    }
+
+    #[test]
+    fn test_serialize_into_cancellable() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        // cancel once the first element has been written
+        let value = vec![7u32; 100_000];
+        let checks = AtomicUsize::new(0);
+        let mut writer = BytesWriter::new();
+        let result = serialize_into_cancellable(&value, &mut writer, || {
+            checks.fetch_add(1, Ordering::Relaxed) >= 1
+        });
+        assert!(matches!(result, Err(Error::Cancelled)));
+        assert_eq!(checks.into_inner(), 2);
+        assert_eq!(writer.into_inner(), [3, 0x01, 0x86, 0xa0, 0, 0, 0, 7]);
+
+        // nested collections and struct fields are checked too
+        let checks = AtomicUsize::new(0);
+        let mut writer = BytesWriter::new();
+        let result = serialize_into_cancellable(&*VALUE, &mut writer, || {
+            checks.fetch_add(1, Ordering::Relaxed) >= 3
+        });
+        assert!(matches!(
+            result.as_ref().map_err(Error::innermost),
+            Err(Error::Cancelled)
+        ));
+
+        // without cancelling, the output matches `serialize`
+        let mut writer = BytesWriter::new();
+        serialize_into_cancellable(&*VALUE, &mut writer, || false).unwrap();
+        assert_eq!(writer.into_inner(), serialize(&*VALUE).unwrap());
+    }
+
+    #[test]
+    fn test_builder() {
+        let options = builder()
+            .length_encoding(LengthEncoding::Varint)
+            .int_encoding(IntEncoding::Varint)
+            .float_encoding(FloatEncoding::Compact)
+            .canonical(true)
+            .build();
+        let value = (vec![300u32, 1], 2.0f64);
+        let serialized = serialize_with(&value, &options).unwrap();
+        assert_eq!(serialized, [2, 0xac, 0x02, 1, 0, 2]);
+        assert_eq!(
+            deserialize_with::<(Vec<u32>, f64)>(&serialized, &options).unwrap(),
+            value
+        );
+
+        // the same options drive an encoder and decoder directly
+        let mut writer = BytesWriter::new();
+        let mut encoder = Encoder::new(&mut writer).with_options(options);
+        value.serialize(&mut encoder).unwrap();
+        assert_eq!(writer.into_inner(), serialized);
+
+        // decoding settings take effect too
+        let options = builder()
+            .tagged(true)
+            .finite_floats(true)
+            .max_total_bytes(16)
+            .build();
+        let serialized = serialize_with(&f64::NAN, &builder().tagged(true).build()).unwrap();
+        assert!(matches!(
+            deserialize_with::<f64>(&serialized, &options),
+            Err(Error::NonFiniteFloat { .. })
+        ));
+        let serialized = serialize_with(&vec![0u8; 32], &options).unwrap();
+        assert!(matches!(
+            deserialize_with::<Vec<u8>>(&serialized, &options),
+            Err(Error::InputLimitExceeded { .. })
+        ));
+
+        // default options match the plain functions
+        let options = builder().build();
+        assert_eq!(
+            serialize_with(&*VALUE, &options).unwrap(),
+            serialize(&*VALUE).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_compact_floats() {
+        fn serialize_compact<T: Serialize>(value: &T) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_float_encoding(FloatEncoding::Compact);
+            value.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_compact<T: DeserializeOwned>(bytes: &[u8], canonical: bool) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader)
+                .with_float_encoding(FloatEncoding::Compact)
+                .with_canonical(canonical);
+            T::deserialize(&mut decoder)
+        }
+
+        // a small integer compacts to a single byte
+        assert_eq!(serialize_compact(&3.0f64), [0, 3]);
+        assert_eq!(serialize_compact(&-128.0f64), [0, 0x80]);
+        assert_eq!(serialize_compact(&3.0f32), [0, 3]);
+
+        // a value exactly representable as an `f32` takes four bytes
+        let mut expected = vec![1];
+        expected.extend_from_slice(&0.5f32.to_be_bytes());
+        assert_eq!(serialize_compact(&0.5f64), expected);
+
+        // anything else stays full-width
+        let mut expected = vec![2];
+        expected.extend_from_slice(&std::f64::consts::PI.to_be_bytes());
+        assert_eq!(serialize_compact(&std::f64::consts::PI), expected);
+        let mut expected = vec![1];
+        expected.extend_from_slice(&0.1f32.to_be_bytes());
+        assert_eq!(serialize_compact(&0.1f32), expected);
+
+        // every value round-trips bit for bit
+        let values = [
+            3.0,
+            -3.0,
+            0.0,
+            -0.0,
+            0.5,
+            128.0,
+            1.0e-300,
+            f64::MAX,
+            f64::MIN_POSITIVE,
+            f64::INFINITY,
+            f64::NAN,
+            f64::from_bits(0x7ff8_0000_0000_0001),
+        ];
+        for value in values {
+            let decoded: f64 = deserialize_compact(&serialize_compact(&value), true).unwrap();
+            assert_eq!(decoded.to_bits(), value.to_bits());
+            let decoded: f32 =
+                deserialize_compact(&serialize_compact(&(value as f32)), true).unwrap();
+            assert_eq!(decoded.to_bits(), (value as f32).to_bits());
+        }
+
+        // an unknown width, or an `f64` width for an `f32`, is rejected
+        assert!(matches!(
+            deserialize_compact::<f64>(&[3, 0], false),
+            Err(Error::InvalidBytes {
+                ty: ValueType::F64,
+                ..
+            })
+        ));
+        let mut bytes = vec![2];
+        bytes.extend_from_slice(&0.1f64.to_be_bytes());
+        assert!(matches!(
+            deserialize_compact::<f32>(&bytes, false),
+            Err(Error::InvalidBytes {
+                ty: ValueType::F32,
+                ..
+            })
+        ));
+
+        // a wider form than needed is only rejected in canonical mode
+        let mut bytes = vec![2];
+        bytes.extend_from_slice(&3.0f64.to_be_bytes());
+        assert_eq!(deserialize_compact::<f64>(&bytes, false).unwrap(), 3.0);
+        assert!(matches!(
+            deserialize_compact::<f64>(&bytes, true),
+            Err(Error::InvalidBytes {
+                ty: ValueType::F64,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_n() {
+        let mut records = Vec::new();
+        for record in [(1u32, "one"), (2, "two"), (3, "three")] {
+            serialize_into(&record, &mut records).unwrap();
+        }
+
+        let expected = vec![
+            (1, "one".to_owned()),
+            (2, "two".to_owned()),
+            (3, "three".to_owned()),
+        ];
+        assert_eq!(
+            deserialize_n::<(u32, String), _>(&mut BytesReader::new(&records), 3).unwrap(),
+            expected
+        );
+        assert_eq!(
+            deserialize_n::<(u32, String), _>(&mut records.as_slice(), 3).unwrap(),
+            expected
+        );
+
+        // no values are read from empty input
+        assert_eq!(
+            deserialize_n::<(u32, String), _>(&mut BytesReader::new(&[]), 0).unwrap(),
+            []
+        );
+
+        // too few values
+        assert!(matches!(
+            deserialize_n::<(u32, String), _>(&mut BytesReader::new(&records), 4),
+            Err(Error::UnexpectedEof)
+        ));
+
+        // a hostile count isn't reserved for up front
+        assert!(matches!(
+            deserialize_n::<u8, _>(&mut BytesReader::new(&[1, 2]), usize::MAX),
+            Err(Error::UnexpectedEof)
+        ));
+        assert!(matches!(
+            deserialize_n::<u8, _>(&mut [1u8, 2].as_slice(), usize::MAX),
+            Err(Error::TruncatedValue { ty: ValueType::U8 })
+        ));
+
+        // too many values
+        let last_len = serialize(&(3u32, "three")).unwrap().len();
+        assert!(matches!(
+            deserialize_n::<(u32, String), _>(&mut BytesReader::new(&records), 2),
+            Err(Error::TrailingBytes { count }) if count == last_len
+        ));
+        // a stream has only one byte read to find that there are more
+        let mut stream = records.as_slice();
+        assert!(matches!(
+            deserialize_n::<(u32, String), _>(&mut stream, 2),
+            Err(Error::TrailingBytes { count: 1 })
+        ));
+        assert_eq!(stream.len(), last_len - 1);
+        assert!(matches!(
+            deserialize_n::<(u32, String), _>(&mut BytesReader::new(&records), 0),
+            Err(Error::TrailingBytes { count }) if count == records.len()
+        ));
+    }
+
+    #[test]
+    fn test_trust_length_hints() {
+        /// Records the size hint a sequence was decoded with.
+        struct SizeHint(Option<usize>);
+
+        impl<'de> Deserialize<'de> for SizeHint {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                struct SizeHintVisitor;
+
+                impl<'de> serde::de::Visitor<'de> for SizeHintVisitor {
+                    type Value = SizeHint;
+
+                    fn expecting(&self, formatter: &mut std::fmt::Formatter) -> std::fmt::Result {
+                        formatter.write_str("a sequence")
+                    }
+
+                    fn visit_seq<A>(self, seq: A) -> std::result::Result<SizeHint, A::Error>
+                    where
+                        A: serde::de::SeqAccess<'de>,
+                    {
+                        Ok(SizeHint(seq.size_hint()))
+                    }
+                }
+
+                deserializer.deserialize_seq(SizeHintVisitor)
+            }
+        }
+
+        fn size_hint<'de, R: Read<'de>>(reader: &mut R, trusted: bool) -> Option<usize> {
+            let mut decoder = Decoder::new(reader).with_trust_length_hints(trusted);
+            SizeHint::deserialize(&mut decoder).unwrap().0
+        }
+
+        let serialized = serialize(&vec![7u32; 1000]).unwrap();
+
+        // trusted lengths are passed on as they are
+        assert_eq!(
+            size_hint(&mut BytesReader::new(&serialized), true),
+            Some(1000)
+        );
+        assert_eq!(size_hint(&mut serialized.as_slice(), true), Some(1000));
+
+        // otherwise the hint is suppressed when the remaining input is
+        // unknown...
+        assert_eq!(size_hint(&mut serialized.as_slice(), false), None);
+
+        // ...and clamped to it when known, so a hostile length can't inflate
+        // it
+        assert_eq!(
+            size_hint(&mut BytesReader::new(&serialized), false),
+            Some(1000)
+        );
+        let hostile = [4, 0xff, 0xff, 0xff, 0xff, 1, 2, 3];
+        assert_eq!(size_hint(&mut BytesReader::new(&hostile), false), Some(3));
+        assert_eq!(
+            size_hint(&mut BytesReader::new(&hostile), true),
+            Some(0xffff_ffff)
+        );
+    }
+
+    #[test]
+    fn test_writer_pool() {
+        let pool = WriterPool::new();
+
+        let mut writer = pool.checkout();
+        serialize_into(&*VALUE, &mut *writer).unwrap();
+        assert_eq!(writer.as_slice(), serialize(&*VALUE).unwrap());
+        let capacity = writer.capacity();
+        let buffer = writer.as_slice().as_ptr();
+        assert_eq!(pool.idle(), 0);
+        drop(writer);
+        assert_eq!(pool.idle(), 1);
+
+        // the next checkout reuses the returned writer, emptied
+        let mut writer = pool.checkout();
+        assert_eq!(pool.idle(), 0);
+        assert!(writer.as_slice().is_empty());
+        assert_eq!(writer.capacity(), capacity);
+        serialize_into(&7u32, &mut *writer).unwrap();
+        assert_eq!(writer.as_slice(), [0, 0, 0, 7]);
+        assert_eq!(writer.as_slice().as_ptr(), buffer);
+
+        // a second writer checked out at the same time is a new one
+        let other = pool.checkout();
+        assert_eq!(other.capacity(), 0);
+        drop(writer);
+        drop(other);
+        assert_eq!(pool.idle(), 2);
+    }
+
+    #[test]
+    fn test_read_n_vec_from_stream() {
+        let data = (0..100_001u32).map(|i| (i * 7) as u8).collect::<Vec<_>>();
+
+        for n in [0, 1, 31, 32, 33, 4096, 100_000] {
+            // `BytesReader` uses the default, zero-filling implementation
+            let expected = BytesReader::new(&data).read_n_vec(n).unwrap();
+            assert_eq!(expected, data[..n]);
+
+            let mut stream = data.as_slice();
+            assert_eq!(stream.read_n_vec(n).unwrap(), expected);
+            assert_eq!(Read::read_u8(&mut stream).unwrap(), data[n]);
+
+            let mut file = tempfile::tempfile().unwrap();
+            file.write_all(&data).unwrap();
+            file.rewind().unwrap();
+            assert_eq!(file.read_n_vec(n).unwrap(), expected);
+            assert_eq!(Read::read_u8(&mut file).unwrap(), data[n]);
+        }
+
+        // a stream too short fails just as a byte array does
+        assert!(matches!(
+            BytesReader::new(&data[..10]).read_n_vec(11),
+            Err(Error::UnexpectedEof)
+        ));
+        assert!(matches!(
+            (&data[..10]).read_n_vec(11),
+            Err(Error::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_canonical_set_of_shared_values() {
+        use std::collections::HashSet;
+        use std::sync::Arc;
+
+        #[derive(Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+        struct Shared(#[serde(with = "crate::shared")] Arc<String>);
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Holder {
+            #[serde(with = "crate::canonical_set")]
+            set: HashSet<Shared>,
+            again: Shared,
+        }
+
+        let string = Arc::new("shared".to_owned());
+        let value = Holder {
+            set: [
+                Shared(Arc::clone(&string)),
+                Shared(Arc::new("other".to_owned())),
+            ]
+            .into_iter()
+            .collect(),
+            again: Shared(Arc::clone(&string)),
+        };
+
+        // sorting the set doesn't record its elements as already written
+        let serialized = serialize_shared(&value).unwrap();
+        let deserialized = deserialize_shared::<Holder>(&serialized).unwrap();
+        assert_eq!(deserialized, value);
+        let in_set = deserialized.set.get(&deserialized.again).unwrap();
+        assert!(Arc::ptr_eq(&in_set.0, &deserialized.again.0));
+
+        // the set is sorted all the same
+        let other_first = serialize(&vec![
+            Shared(Arc::new("other".to_owned())),
+            Shared(Arc::new("shared".to_owned())),
+        ])
+        .unwrap();
+        assert_eq!(serialized[..other_first.len()], other_first);
+    }
+
+    #[test]
+    fn test_canonical_set() {
+        use std::collections::{BTreeSet, HashSet};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Permissions {
+            #[serde(with = "crate::canonical_set")]
+            scopes: HashSet<String>,
+            #[serde(with = "crate::canonical_set")]
+            ids: HashSet<u32>,
+        }
+
+        let scopes = ["read", "write", "admin", "audit", "billing", "deploy"];
+        let ids = (0..64).map(|i| i * 37).collect::<Vec<_>>();
+        let first = Permissions {
+            scopes: scopes.iter().map(|&scope| scope.to_owned()).collect(),
+            ids: ids.iter().copied().collect(),
+        };
+        let mut second = Permissions {
+            scopes: HashSet::with_capacity(100),
+            ids: HashSet::with_capacity(100),
+        };
+        second
+            .scopes
+            .extend(scopes.iter().rev().map(|&scope| scope.to_owned()));
+        second.ids.extend(ids.iter().rev());
+        assert_eq!(first, second);
+
+        // equal sets produce equal bytes, whatever their iteration order
+        let serialized = serialize(&first).unwrap();
+        assert_eq!(serialize(&second).unwrap(), serialized);
+        assert_eq!(deserialize::<Permissions>(&serialized).unwrap(), first);
+
+        // elements are ordered by their encoded bytes, which for integers
+        // matches a `BTreeSet`
+        let sorted = ids.iter().copied().collect::<BTreeSet<_>>();
+        let serialized_ids = serialize(&sorted).unwrap();
+        assert!(serialized.ends_with(&serialized_ids));
+    }
+
+    #[test]
+    fn test_sparse_vec() {
+        let mut elements = vec![None; 100];
+        elements[3] = Some(7u8);
+        elements[64] = Some(9);
+        elements[99] = Some(0);
+
+        // the bitmap, its length, and the three present values
+        let serialized = serialize(&SparseVec(elements.clone())).unwrap();
+        assert_eq!(serialized.len(), 2 + 13 + 3);
+        assert!(serialized.len() < serialize(&elements).unwrap().len());
+        assert_eq!(&serialized[..2], [1, 13]);
+        assert_eq!(serialized[2], 0b1000);
+        // element 99 and the end marker after it
+        assert_eq!(serialized[14], 0b1_1000);
+        assert_eq!(&serialized[15..], [7, 9, 0]);
+        assert_eq!(
+            deserialize::<SparseVec<u8>>(&serialized).unwrap(),
+            SparseVec(elements)
+        );
+
+        // the end marker records the exact length, including trailing `None`s
+        for len in 0..=17 {
+            let elements = (0..len)
+                .map(|i| (i % 3 == 0).then_some(i as u32))
+                .collect::<Vec<_>>();
+            let serialized = serialize(&SparseVec(elements.clone())).unwrap();
+            assert_eq!(
+                deserialize::<SparseVec<u32>>(&serialized).unwrap(),
+                SparseVec(elements.clone())
+            );
+
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_tagged(true);
+            SparseVec(elements.clone()).serialize(&mut encoder).unwrap();
+            let serialized = writer.into_inner();
+            let mut reader = BytesReader::new(&serialized);
+            let mut decoder = Decoder::new(&mut reader).with_tagged(true);
+            assert_eq!(
+                SparseVec::<u32>::deserialize(&mut decoder).unwrap(),
+                SparseVec(elements)
+            );
+        }
+
+        assert_eq!(serialize(&SparseVec::<u8>(vec![])).unwrap(), [1, 1, 1]);
+
+        // a bitmap without its end marker in the last byte is rejected
+        assert!(deserialize::<SparseVec<u8>>(&[1, 2, 1, 0]).is_err());
+        assert!(deserialize::<SparseVec<u8>>(&[0]).is_err());
+
+        // as is a missing value
+        assert!(deserialize::<SparseVec<u8>>(&[1, 1, 0b11]).is_err());
+    }
+
+    #[test]
+    fn test_fixed_bytes() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Key {
+            #[serde(with = "crate::fixed_bytes")]
+            bytes: [u8; 32],
+        }
+
+        let key = Key {
+            bytes: std::array::from_fn(|i| i as u8),
+        };
+
+        // exactly the 32 bytes go on the wire, as with a plain array
+        let serialized = serialize(&key).unwrap();
+        assert_eq!(serialized, key.bytes);
+        assert_eq!(serialized, serialize(&key.bytes).unwrap());
+        assert_eq!(deserialize::<Key>(&serialized).unwrap(), key);
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&serialized).unwrap();
+        file.rewind().unwrap();
+        assert_eq!(deserialize_from::<Key, _>(&mut file).unwrap(), key);
+
+        let mut writer = BytesWriter::new();
+        let mut encoder = Encoder::new(&mut writer).with_tagged(true);
+        key.serialize(&mut encoder).unwrap();
+        let serialized = writer.into_inner();
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_tagged(true);
+        assert_eq!(Key::deserialize(&mut decoder).unwrap(), key);
+
+        // the whole block must be present
+        assert!(matches!(
+            deserialize::<Key>(&key.bytes[..31])
+                .as_ref()
+                .map_err(Error::innermost),
+            Err(Error::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_flags() {
+        crate::flags! {
+            #[derive(Debug, Clone, Copy, Default, PartialEq)]
+            struct Permissions {
+                owner_read,
+                owner_write,
+                owner_execute,
+                group_read,
+                group_write,
+                group_execute,
+                other_read,
+                /// Doc comments and other attributes are kept on fields.
+                other_write,
+            }
+        }
+
+        let permissions = Permissions {
+            owner_read: true,
+            owner_write: true,
+            group_read: true,
+            other_write: true,
+            ..Permissions::default()
+        };
+
+        // the eight flags pack into one byte, least significant bit first
+        let serialized = serialize(&permissions).unwrap();
+        assert_eq!(serialized, [0b1000_1011]);
+        assert_eq!(
+            deserialize::<Permissions>(&serialized).unwrap(),
+            permissions
+        );
+        assert_eq!(
+            serialize(&[true; 8]).unwrap().len(),
+            8 * serialize(&true).unwrap().len()
+        );
+
+        let flags = Flags::from(permissions);
+        assert_eq!(flags.bits(), 0b1000_1011);
+        assert!(flags.get(7));
+        assert!(!flags.get(6));
+        assert_eq!(Permissions::from(flags), permissions);
+
+        // groups of flags take only as many bytes as they need
+        let mut flags = Flags::<10>::new();
+        flags.set(9, true);
+        flags.set(0, true);
+        let serialized = serialize(&flags).unwrap();
+        assert_eq!(serialized, [0b01, 0b10]);
+        assert_eq!(deserialize::<Flags<10>>(&serialized).unwrap(), flags);
+        flags.set(0, false);
+        assert_eq!(<[bool; 10]>::from(flags), {
+            let mut bools = [false; 10];
+            bools[9] = true;
+            bools
+        });
+
+        let flags = Flags::<64>::from_bits(u64::MAX).unwrap();
+        let serialized = serialize(&flags).unwrap();
+        assert_eq!(serialized, [0xff; 8]);
+        assert_eq!(deserialize::<Flags<64>>(&serialized).unwrap(), flags);
+
+        // unused bits must be clear, so that each group has one encoding
+        assert_eq!(Flags::<10>::from_bits(1 << 10), None);
+        assert!(matches!(
+            deserialize::<Flags<10>>(&[0, 0b100]),
+            Err(Error::Custom(_))
+        ));
+        assert!(matches!(
+            deserialize::<Flags<10>>(&[0]),
+            Err(Error::IncompleteStruct {
+                expected: 2,
+                read: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_peek_variant_index() {
+        fn peek_and_decode(bytes: &[u8], tagged: bool) -> (Option<u8>, MyEnum) {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_tagged(tagged);
+            let index = decoder.peek_variant_index();
+            (index, MyEnum::deserialize(&mut decoder).unwrap())
+        }
+
+        for (value, expected) in [
+            (MyEnum::UnitVariant, 0),
+            (MyEnum::NewtypeVariant(7), 1),
+            (MyEnum::TupleVariant((), true, 8), 2),
+            (
+                MyEnum::StructVariant {
+                    a: (),
+                    b: false,
+                    c: 9,
+                },
+                3,
+            ),
+        ] {
+            // peeking consumes nothing, so the value still decodes in full
+            let serialized = serialize(&value).unwrap();
+            assert_eq!(
+                peek_and_decode(&serialized, false),
+                (Some(expected), value.clone())
+            );
+
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_tagged(true);
+            value.serialize(&mut encoder).unwrap();
+            let serialized = writer.into_inner();
+            assert_eq!(peek_and_decode(&serialized, true), (Some(expected), value));
+        }
+
+        // the index can also be peeked across chunks, as long as it lies
+        // within one
+        let mut reader = ChainedBytesReader::new([&[][..], &[2, 0, 1, 8][..]]);
+        let decoder = Decoder::new(&mut reader);
+        assert_eq!(decoder.peek_variant_index(), Some(2));
+
+        // there is nothing to peek at the end of input, or from a stream
+        let mut reader = BytesReader::new(&[]);
+        assert_eq!(Decoder::new(&mut reader).peek_variant_index(), None);
+        let mut stream = [1u8, 7].as_slice();
+        assert_eq!(Decoder::new(&mut stream).peek_variant_index(), None);
+
+        // in tagged mode, only an enum has a variant index
+        let mut writer = BytesWriter::new();
+        7u8.serialize(&mut Encoder::new(&mut writer).with_tagged(true))
+            .unwrap();
+        let serialized = writer.into_inner();
+        let mut reader = BytesReader::new(&serialized);
+        let decoder = Decoder::new(&mut reader).with_tagged(true);
+        assert_eq!(decoder.peek_variant_index(), None);
+    }
+
+    #[test]
+    fn test_tee_writer() {
+        let mut writer = TeeWriter::new(BytesWriter::new(), BytesWriter::new());
+        serialize_into(&*VALUE, &mut writer).unwrap();
+        assert_eq!(writer.get_ref().0, writer.get_ref().1);
+
+        let (first, second) = writer.into_inner();
+        let serialized = serialize(&*VALUE).unwrap();
+        assert_eq!(first.into_inner(), serialized);
+        assert_eq!(second.into_inner(), serialized);
+    }
+
+    #[test]
+    fn test_framed() {
+        fn serialize_framed<T: Serialize>(value: &T) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_framed(true);
+            value.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_framed<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_framed(true);
+            T::deserialize(&mut decoder)
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Record {
+            id: u32,
+            flag: u8,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct NarrowRecord {
+            id: u16,
+            flag: u8,
+        }
+
+        // structs and sequences are followed by the sentinel
+        let record = Record { id: 7, flag: 1 };
+        let serialized = serialize_framed(&record);
+        assert_eq!(serialized, [0, 0, 0, 7, 1, 0xa5]);
+        assert_eq!(deserialize_framed::<Record>(&serialized).unwrap(), record);
+
+        let records = vec![Record { id: 1, flag: 2 }, Record { id: 3, flag: 4 }];
+        let serialized = serialize_framed(&records);
+        assert_eq!(
+            serialized,
+            [1, 2, 0, 0, 0, 1, 2, 0xa5, 0, 0, 0, 3, 4, 0xa5, 0xa5]
+        );
+        assert_eq!(
+            deserialize_framed::<Vec<Record>>(&serialized).unwrap(),
+            records
+        );
+
+        // tuples and maps are not framed
+        assert_eq!(serialize_framed(&(1u8, 2u8)), [1, 2]);
+
+        // struct variants are framed like structs
+        let variant = MyEnum::StructVariant {
+            a: (),
+            b: true,
+            c: 3,
+        };
+        let serialized = serialize_framed(&variant);
+        assert_eq!(serialized, [3, 1, 3, 1, 3, 0xa5]);
+        assert_eq!(deserialize_framed::<MyEnum>(&serialized).unwrap(), variant);
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        enum Command {
+            Move(u8, u8),
+            Stop { code: u8 },
+        }
+
+        // tuple variants are written with their number of fields
+        let command = Command::Move(4, 5);
+        let serialized = serialize_framed(&command);
+        assert_eq!(serialized, [0, 1, 2, 4, 5]);
+        assert_eq!(deserialize_framed::<Command>(&serialized).unwrap(), command);
+
+        // a corrupted variant index goes unnoticed without framing...
+        let mut serialized = serialize(&command).unwrap();
+        serialized[0] = 1;
+        assert_eq!(
+            deserialize::<Command>(&serialized).unwrap(),
+            Command::Stop { code: 4 }
+        );
+
+        // ...but is caught by the arity check with it
+        let mut serialized = serialize_framed(&command);
+        serialized[0] = 1;
+        assert!(matches!(
+            deserialize_framed::<Command>(&serialized),
+            Err(Error::VariantArityMismatch {
+                expected: 1,
+                found: 2
+            })
+        ));
+
+        // misreading a field's width goes unnoticed without framing...
+        let serialized = serialize(&record).unwrap();
+        assert_eq!(
+            deserialize::<NarrowRecord>(&serialized).unwrap(),
+            NarrowRecord { id: 0, flag: 0 }
+        );
+
+        // ...but is caught by the sentinel check with it
+        let serialized = serialize_framed(&record);
+        assert!(matches!(
+            deserialize_framed::<NarrowRecord>(&serialized),
+            Err(Error::FrameDesync {
+                expected_sentinel: 0xa5,
+                found: 7
+            })
+        ));
+    }
+
+    #[test]
+    fn test_varint_ints() {
+        fn serialize_varint<T: Serialize>(value: &T) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_int_encoding(IntEncoding::Varint);
+            value.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_varint<'de, T: Deserialize<'de>>(
+            bytes: &'de [u8],
+            canonical: bool,
+        ) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader)
+                .with_int_encoding(IntEncoding::Varint)
+                .with_canonical(canonical);
+            T::deserialize(&mut decoder)
+        }
+
+        macro_rules! assert_round_trips {
+            ($($ty:ty),*) => {
+                $(
+                    for value in [<$ty>::MIN, <$ty>::MIN + 1, 0, 1, <$ty>::MAX - 1, <$ty>::MAX] {
+                        let serialized = serialize_varint(&value);
+                        assert_eq!(deserialize_varint::<$ty>(&serialized, true).unwrap(), value);
+                    }
+                )*
+            };
+        }
+
+        assert_round_trips!(i8, i16, i32, i64, i128, u8, u16, u32, u64, u128);
+
+        // small values are small, whatever their sign
+        assert_eq!(serialize_varint(&0u64), [0]);
+        assert_eq!(serialize_varint(&300u32), [0xac, 0x02]);
+        assert_eq!(serialize_varint(&0i32), [0]);
+        assert_eq!(serialize_varint(&-1i32), [1]);
+        assert_eq!(serialize_varint(&1i32), [2]);
+        assert_eq!(serialize_varint(&-64i64), [0x7f]);
+        assert_eq!(serialize_varint(&64i64), [0x80, 0x01]);
+
+        // bytes are unaffected
+        assert_eq!(serialize_varint(&200u8), [200]);
+        assert_eq!(serialize_varint(&-1i8), [0xff]);
+
+        // 128-bit extremes take the full 19 bytes
+        let serialized = serialize_varint(&u128::MAX);
+        assert_eq!(serialized.len(), 19);
+        assert_eq!(serialized[..18], [0xff; 18]);
+        assert_eq!(serialized[18], 0x03);
+        assert_eq!(serialize_varint(&u128::MIN), [0]);
+        assert_eq!(serialize_varint(&i128::MIN).len(), 19);
+        assert_eq!(serialize_varint(&i128::MAX).len(), 19);
+
+        // overlong encodings are only rejected in canonical mode
+        assert_eq!(deserialize_varint::<u128>(&[0x81, 0x00], false).unwrap(), 1);
+        assert!(matches!(
+            deserialize_varint::<u128>(&[0x81, 0x00], true),
+            Err(Error::InvalidBytes { ty: ValueType::U128, bytes }) if bytes == [0x81, 0x00]
+        ));
+
+        // values too large for the type are rejected
+        let mut too_large = serialize_varint(&u128::MAX);
+        too_large[18] = 0x04;
+        assert!(matches!(
+            deserialize_varint::<u128>(&too_large, false),
+            Err(Error::InvalidBytes {
+                ty: ValueType::U128,
+                ..
+            })
+        ));
+        assert!(matches!(
+            deserialize_varint::<u16>(&serialize_varint(&65536u32), false),
+            Err(Error::InvalidBytes {
+                ty: ValueType::U16,
+                ..
+            })
+        ));
+        assert!(matches!(
+            deserialize_varint::<u16>(&[0x80, 0x80, 0x80, 0x00], false),
+            Err(Error::InvalidBytes { ty: ValueType::U16, bytes }) if bytes == [0x80; 3]
+        ));
+
+        // truncation mid-varint is an early end of input
+        assert!(matches!(
+            deserialize_varint::<u128>(&serialized[..10], false),
+            Err(Error::TruncatedValue {
+                ty: ValueType::U128
+            })
+        ));
+        assert!(matches!(
+            deserialize_varint::<i128>(&[0x80], false),
+            Err(Error::TruncatedValue {
+                ty: ValueType::I128
+            })
+        ));
+
+        // everything round-trips
+        let serialized = serialize_varint(&*VALUE);
+        assert_eq!(
+            deserialize_varint::<MyStruct>(&serialized, true).unwrap(),
+            *VALUE
+        );
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let err = std::io::Error::from(Error::Custom("bad record".into()));
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(err.to_string(), "serialization error: bad record");
+
+        let err = std::io::Error::from(Error::UnexpectedEof);
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+
+        // errors from decoding keep their context
+        let err = std::io::Error::from(deserialize::<(u8, u16)>(&[1, 0]).unwrap_err());
+        assert_eq!(err.kind(), std::io::ErrorKind::UnexpectedEof);
+        assert!(matches!(
+            err.get_ref().and_then(|err| err.downcast_ref::<Error>()),
+            Some(Error::InTupleElement { index: 1, .. })
+        ));
+
+        // I/O errors convert back into themselves
+        let source = std::io::Error::new(std::io::ErrorKind::BrokenPipe, "closed");
+        let err = std::io::Error::from(Error::IoError(source));
+        assert_eq!(err.kind(), std::io::ErrorKind::BrokenPipe);
+        assert_eq!(err.to_string(), "closed");
+
+        // so the crate's functions can be used with `?` in I/O code
+        fn write_record(writer: &mut Vec<u8>) -> std::io::Result<()> {
+            serialize_into(&(1u8, 2u8), writer)?;
+            Ok(())
+        }
+
+        let mut bytes = Vec::new();
+        write_record(&mut bytes).unwrap();
+        assert_eq!(bytes, [1, 2]);
+    }
+
+    #[test]
+    fn test_value_type_names() {
+        let names = [
+            (ValueType::Bool, "deserialize_bool", "boolean"),
+            (ValueType::I8, "deserialize_i8", "signed 8-bit integer"),
+            (ValueType::I16, "deserialize_i16", "signed 16-bit integer"),
+            (ValueType::I32, "deserialize_i32", "signed 32-bit integer"),
+            (ValueType::I64, "deserialize_i64", "signed 64-bit integer"),
+            (
+                ValueType::I128,
+                "deserialize_i128",
+                "signed 128-bit integer",
+            ),
+            (ValueType::U8, "deserialize_u8", "unsigned 8-bit integer"),
+            (ValueType::U16, "deserialize_u16", "unsigned 16-bit integer"),
+            (ValueType::U32, "deserialize_u32", "unsigned 32-bit integer"),
+            (ValueType::U64, "deserialize_u64", "unsigned 64-bit integer"),
+            (
+                ValueType::U128,
+                "deserialize_u128",
+                "unsigned 128-bit integer",
+            ),
+            (ValueType::F32, "deserialize_f32", "32-bit float"),
+            (ValueType::F64, "deserialize_f64", "64-bit float"),
+            (ValueType::Char, "deserialize_char", "character"),
+            (ValueType::Str, "deserialize_str", "string slice"),
+            (ValueType::String, "deserialize_string", "string"),
+            (ValueType::Bytes, "deserialize_bytes", "byte slice"),
+            (ValueType::ByteBuf, "deserialize_byte_buf", "byte array"),
+            (ValueType::Option, "deserialize_option", "option"),
+            (ValueType::Unit, "deserialize_unit", "unit"),
+            (
+                ValueType::UnitStruct,
+                "deserialize_unit_struct",
+                "unit struct",
+            ),
+            (
+                ValueType::NewtypeStruct,
+                "deserialize_newtype_struct",
+                "newtype struct",
+            ),
+            (ValueType::Seq, "deserialize_seq", "sequence"),
+            (ValueType::Tuple, "deserialize_tuple", "tuple"),
+            (
+                ValueType::TupleStruct,
+                "deserialize_tuple_struct",
+                "tuple struct",
+            ),
+            (ValueType::Map, "deserialize_map", "map"),
+            (ValueType::Struct, "deserialize_struct", "struct"),
+            (ValueType::Enum, "deserialize_enum", "enum"),
+        ];
+
+        for (ty, method, name) in names {
+            assert_eq!(ty.to_string(), name);
+            assert_eq!(ValueType::from_deserialize_method(method), Some(ty));
+        }
+
+        for method in [
+            "deserialize_any",
+            "deserialize_identifier",
+            "deserialize_ignored_any",
+            "u32",
+        ] {
+            assert_eq!(ValueType::from_deserialize_method(method), None);
+        }
+
+        let err = deserialize::<bool>(&[2]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid byte sequence while deserializing boolean: `[0x02]`"
+        );
+
+        // the bytes are displayed in hex, and debugged as-is
+        let err = deserialize::<char>(&[2, 0x41, 0x42]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid byte sequence while deserializing character: `[0x41, 0x42]`"
+        );
+        assert!(format!("{err:?}").contains("bytes: [65, 66]"));
+    }
+
+    #[test]
+    fn test_serialize_streaming() {
+        let mut chunks = Vec::new();
+        serialize_streaming(&*VALUE, |chunk| {
+            chunks.push(chunk.to_vec());
+            Ok(())
+        })
+        .unwrap();
+        assert!(chunks.len() > 1);
+        assert_eq!(chunks.concat(), serialize(&*VALUE).unwrap());
+
+        // errors from the sink stop serialization
+        let mut num_chunks = 0;
+        let result = serialize_streaming(&*VALUE, |_| {
+            num_chunks += 1;
+            Err(Error::Custom("sink closed".into()))
+        });
+        assert!(matches!(result, Err(Error::Custom(msg)) if msg == "sink closed"));
+        assert_eq!(num_chunks, 1);
+    }
+
+    #[test]
+    fn test_max_total_bytes() {
+        fn deserialize_limited<'de, T: Deserialize<'de>>(
+            bytes: &'de [u8],
+            limit: usize,
+        ) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_max_total_bytes(limit);
+            T::deserialize(&mut decoder)
+        }
+
+        // a long sequence of tiny elements
+        let value = vec![7u8; 10_000];
+        let serialized = serialize(&value).unwrap();
+        assert_eq!(serialized.len(), 10_003);
+
+        assert!(matches!(
+            deserialize_limited::<Vec<u8>>(&serialized, 1000),
+            Err(Error::InputLimitExceeded { limit: 1000 })
+        ));
+        assert!(matches!(
+            deserialize_limited::<Vec<u8>>(&serialized, 10_002),
+            Err(Error::InputLimitExceeded { limit: 10_002 })
+        ));
+        assert_eq!(
+            deserialize_limited::<Vec<u8>>(&serialized, 10_003).unwrap(),
+            value
+        );
+
+        // strings and byte arrays are rejected before being read
+        let serialized = serialize(&"a".repeat(100)).unwrap();
+        assert!(matches!(
+            deserialize_limited::<&str>(&serialized, 50),
+            Err(Error::InputLimitExceeded { limit: 50 })
+        ));
+
+        // the count spans every value decoded
+        let serialized = serialize(&(1u32, 2u32)).unwrap();
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_max_total_bytes(6);
+        assert_eq!(u32::deserialize(&mut decoder).unwrap(), 1);
+        assert_eq!(decoder.bytes_read(), 4);
+        assert!(matches!(
+            u32::deserialize(&mut decoder),
+            Err(Error::InputLimitExceeded { limit: 6 })
+        ));
+    }
+
+    #[test]
+    fn test_empty_elements() {
+        let value = vec![(); 1000];
+        let serialized = serialize(&value).unwrap();
+        assert_eq!(serialized, [2, 0x03, 0xe8]);
+        assert_eq!(deserialize::<Vec<()>>(&serialized).unwrap(), value);
+
+        // a huge length of units fails rather than decoding them forever
+        let serialized = encode_len_large(usize::MAX);
+        assert!(matches!(
+            deserialize::<Vec<()>>(&serialized),
+            Err(Error::TooManyEmptyElements {
+                limit: MAX_EMPTY_ELEMENTS
+            })
+        ));
+
+        // as do maps of entries that take no bytes
+        assert!(matches!(
+            deserialize::<BTreeMap<(), ()>>(&serialized),
+            Err(Error::TooManyEmptyElements {
+                limit: MAX_EMPTY_ELEMENTS
+            })
+        ));
+        assert!(matches!(
+            deserialize::<HashMap<(), ()>>(&[5, 0xff, 0xff, 0xff, 0xff, 0xff]),
+            Err(Error::TooManyEmptyElements {
+                limit: MAX_EMPTY_ELEMENTS
+            })
+        ));
+        assert_eq!(
+            deserialize::<BTreeMap<(), ()>>(&[1, 3]).unwrap(),
+            BTreeMap::from([((), ())])
+        );
+
+        // the limit can be lowered...
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_max_empty_elements(1000);
+        assert!(matches!(
+            Vec::<()>::deserialize(&mut decoder),
+            Err(Error::TooManyEmptyElements { limit: 1000 })
+        ));
+
+        let serialized = serialize(&vec![(); 1000]).unwrap();
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_max_empty_elements(1000);
+        assert_eq!(Vec::<()>::deserialize(&mut decoder).unwrap(), value);
+
+        // ...or raised for valid data beyond it, independently of the total
+        // byte limit
+        let value = vec![(); MAX_EMPTY_ELEMENTS + 1];
+        let serialized = serialize(&value).unwrap();
+        assert!(matches!(
+            deserialize::<Vec<()>>(&serialized),
+            Err(Error::TooManyEmptyElements {
+                limit: MAX_EMPTY_ELEMENTS
+            })
+        ));
+        let options = builder()
+            .max_empty_elements(MAX_EMPTY_ELEMENTS + 1)
+            .max_total_bytes(16)
+            .build();
+        assert_eq!(
+            deserialize_with::<Vec<()>>(&serialized, &options).unwrap(),
+            value
+        );
+
+        // elements that take bytes don't count
+        let value = vec![Some(()); 2000];
+        let serialized = serialize(&value).unwrap();
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_max_empty_elements(1000);
+        assert_eq!(Vec::<Option<()>>::deserialize(&mut decoder).unwrap(), value);
+    }
+
+    #[test]
+    fn test_trace() {
+        let value = MyInnerStruct {
+            a: (),
+            b: true,
+            c: 7,
+        };
+        let mut serialized = serialize(&value).unwrap();
+        serialized.extend(serialize(&"hi").unwrap());
+
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_trace(true);
+        assert_eq!(MyInnerStruct::deserialize(&mut decoder).unwrap(), value);
+        assert_eq!(
+            decoder.trace(),
+            [
+                (0, ValueType::Unit, 0),
+                (0, ValueType::Bool, 1),
+                (1, ValueType::U8, 1),
+            ]
+        );
+
+        // strings are recorded with their length prefix, and failed reads
+        // are recorded too
+        assert_eq!(<&str>::deserialize(&mut decoder).unwrap(), "hi");
+        assert!(u32::deserialize(&mut decoder).is_err());
+        assert_eq!(
+            decoder.trace()[3..],
+            [(2, ValueType::Str, 4), (6, ValueType::U32, 4)]
+        );
+
+        // option discriminants, variant indices and length prefixes are
+        // recorded before their contents
+        let value = (Some(vec![1u8]), Ok::<_, ()>(BTreeMap::from([(2u8, 3u8)])));
+        let nested = serialize(&value).unwrap();
+        let mut reader = BytesReader::new(&nested);
+        let mut decoder = Decoder::new(&mut reader).with_trace(true);
+        assert_eq!(Deserialize::deserialize(&mut decoder).ok(), Some(value));
+        assert_eq!(
+            decoder.trace(),
+            [
+                (0, ValueType::Option, 1),
+                (1, ValueType::Seq, 2),
+                (3, ValueType::U8, 1),
+                (4, ValueType::Enum, 1),
+                (5, ValueType::Map, 2),
+                (7, ValueType::U8, 1),
+                (8, ValueType::U8, 1),
+            ]
+        );
+
+        // so a corrupt discriminant or variant index is the last entry
+        let mut reader = BytesReader::new(&[7, 1]);
+        let mut decoder = Decoder::new(&mut reader).with_trace(true);
+        assert!(Option::<u8>::deserialize(&mut decoder).is_err());
+        assert_eq!(decoder.trace(), [(0, ValueType::Option, 1)]);
+
+        let mut reader = BytesReader::new(&[0, 0, 0, 1, 9, 2]);
+        let mut decoder = Decoder::new(&mut reader).with_trace(true);
+        assert!(<(u32, std::result::Result<u8, u8>)>::deserialize(&mut decoder).is_err());
+        assert_eq!(
+            decoder.trace(),
+            [(0, ValueType::U32, 4), (4, ValueType::Enum, 1)]
+        );
+
+        // nothing is recorded by default
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader);
+        MyInnerStruct::deserialize(&mut decoder).unwrap();
+        assert!(decoder.trace().is_empty());
+    }
+
+    #[test]
+    fn test_bytes_written() {
+        let mut writer = BytesWriter::new();
+        assert_eq!(writer.bytes_written(), Some(0));
+        serialize_into(&*VALUE, &mut writer).unwrap();
+        let written = writer.bytes_written();
+        assert_eq!(written, Some(writer.into_inner().len()));
+
+        let mut counter = SizeCounter::new();
+        serialize_into(&*VALUE, &mut counter).unwrap();
+        assert_eq!(counter.bytes_written(), written);
+
+        // plain `io::Write` types don't keep count
+        assert_eq!(Write::bytes_written(&Vec::<u8>::new()), None);
+    }
+
+    #[test]
+    fn test_bytes_writer_large_writes() {
+        const LEN: usize = 4 * 1024 * 1024;
+        let buf: Vec<u8> = (0..LEN).map(|i| i as u8).collect();
+
+        // every byte of a multi-megabyte write is kept
+        let mut writer = BytesWriter::new();
+        writer.write_all(&buf).unwrap();
+        writer.write_all(&buf[..LEN / 2]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(writer.as_slice().len(), LEN + LEN / 2);
+        assert_eq!(&writer.as_slice()[..LEN], buf);
+        assert_eq!(&writer.as_slice()[LEN..], &buf[..LEN / 2]);
+    }
+
+    #[test]
+    fn test_bytes_writer_io_write() {
+        use std::io::Write as _;
+
+        let mut writer = BytesWriter::new();
+        serialize_into(&1u16, &mut writer).unwrap();
+        std::io::Write::write_all(&mut &mut writer, &[2, 3]).unwrap();
+        write!(&mut writer, "{}", 45).unwrap();
+        std::io::copy(&mut &[6u8, 7][..], &mut &mut writer).unwrap();
+        serialize_into(&8u8, &mut writer).unwrap();
+
+        assert_eq!(writer.into_inner(), [0, 1, 2, 3, b'4', b'5', 6, 7, 8]);
+    }
+
+    #[test]
+    fn test_shared() {
+        use std::sync::Arc;
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Shared(#[serde(with = "crate::shared")] Arc<String>);
+
+        let string = Arc::new("a shared string".to_owned());
+        let other = Arc::new("another string".to_owned());
+        let value = vec![
+            Shared(Arc::clone(&string)),
+            Shared(Arc::clone(&other)),
+            Shared(Arc::clone(&string)),
+        ];
+
+        // repeated `Arc`s are written once, then referenced by index
+        let serialized = serialize_shared(&value).unwrap();
+        let string_bytes = serialize(&*string).unwrap();
+        let other_bytes = serialize(&*other).unwrap();
+        assert_eq!(
+            serialized,
+            [
+                vec![1, 3],
+                vec![0],
+                string_bytes.clone(),
+                vec![0],
+                other_bytes,
+                vec![1, 0, 0, 0, 0],
+            ]
+            .concat()
+        );
+        assert_eq!(
+            serialized
+                .windows(string_bytes.len())
+                .filter(|window| *window == string_bytes)
+                .count(),
+            1
+        );
+
+        // sharing is restored when decoding
+        let deserialized = deserialize_shared::<Vec<Shared>>(&serialized).unwrap();
+        assert_eq!(deserialized, value);
+        assert!(Arc::ptr_eq(&deserialized[0].0, &deserialized[2].0));
+        assert!(!Arc::ptr_eq(&deserialized[0].0, &deserialized[1].0));
+
+        // back-references can't be resolved outside of `deserialize_shared`
+        assert!(matches!(
+            deserialize::<Vec<Shared>>(&serialized),
+            Err(Error::Custom(_))
+        ));
+
+        // without deduplication, every `Arc` is written in full
+        let serialized = serialize(&value).unwrap();
+        let deserialized = deserialize::<Vec<Shared>>(&serialized).unwrap();
+        assert_eq!(deserialized, value);
+        assert!(!Arc::ptr_eq(&deserialized[0].0, &deserialized[2].0));
+    }
+
+    #[test]
+    fn test_empty_collections() {
+        use std::collections::HashMap;
+
+        struct Bytes<'a>(&'a [u8]);
+
+        impl Serialize for Bytes<'_> {
+            fn serialize<S: serde::Serializer>(
+                &self,
+                serializer: S,
+            ) -> std::result::Result<S::Ok, S::Error> {
+                serializer.serialize_bytes(self.0)
+            }
+        }
+
+        assert_eq!(serialize(&Vec::<u32>::new()).unwrap(), [0]);
+        assert_eq!(serialize(&HashMap::<u8, u32>::new()).unwrap(), [0]);
+        assert_eq!(serialize(&String::new()).unwrap(), [0]);
+        assert_eq!(serialize(&Bytes(&[])).unwrap(), [0]);
+
+        assert_eq!(deserialize::<Vec<u32>>(&[0]).unwrap(), Vec::<u32>::new());
+        assert_eq!(
+            deserialize::<HashMap<u8, u32>>(&[0]).unwrap(),
+            HashMap::new()
+        );
+        assert_eq!(deserialize::<String>(&[0]).unwrap(), "");
+        assert_eq!(deserialize::<&str>(&[0]).unwrap(), "");
+        assert_eq!(deserialize::<&[u8]>(&[0]).unwrap(), &[] as &[u8]);
+    }
+
+    #[test]
+    fn test_finite_floats() {
+        fn deserialize_finite<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_finite_floats(true);
+            T::deserialize(&mut decoder)
+        }
+
+        for value in [f32::NAN, f32::INFINITY, f32::NEG_INFINITY] {
+            let serialized = serialize(&value).unwrap();
+            assert!(deserialize::<f32>(&serialized).is_ok());
+            assert!(matches!(
+                deserialize_finite::<f32>(&serialized),
+                Err(Error::NonFiniteFloat {
+                    ty: ValueType::F32,
+                    ..
+                })
+            ));
+        }
+
+        for bytes in [
+            [0x7f, 0xf8, 0, 0, 0, 0, 0, 0],
+            [0x7f, 0xf0, 0, 0, 0, 0, 0, 0],
+            [0xff, 0xf0, 0, 0, 0, 0, 0, 0],
+            [0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff],
+        ] {
+            assert!(!deserialize::<f64>(&bytes).unwrap().is_finite());
+            assert!(matches!(
+                deserialize_finite::<f64>(&bytes),
+                Err(Error::NonFiniteFloat {
+                    ty: ValueType::F64,
+                    ..
+                })
+            ));
+        }
+
+        for value in [0.0, -0.0, f64::MIN, f64::MAX, f64::MIN_POSITIVE, 1e-310] {
+            let serialized = serialize(&value).unwrap();
+            assert_eq!(
+                deserialize_finite::<f64>(&serialized).unwrap().to_bits(),
+                value.to_bits()
+            );
+        }
+
+        // encoding rejects them too, before writing anything
+        let mut writer = BytesWriter::new();
+        let mut encoder = Encoder::new(&mut writer).with_finite_floats(true);
+        assert!(matches!(
+            f64::NAN.serialize(&mut encoder),
+            Err(Error::NonFiniteFloat {
+                ty: ValueType::F64,
+                value
+            }) if value.is_nan()
+        ));
+        assert!(writer.as_slice().is_empty());
+        let finite = builder().finite_floats(true).build();
+        assert!(matches!(
+            serialize_with(&(1u8, f32::NEG_INFINITY), &finite),
+            Err(Error::NonFiniteFloat {
+                ty: ValueType::F32,
+                value: f64::NEG_INFINITY
+            })
+        ));
+
+        for value in [0.0, -0.0, f64::MIN, f64::MAX, f64::MIN_POSITIVE, 1e-310] {
+            assert_eq!(
+                serialize_with(&value, &finite).unwrap(),
+                serialize(&value).unwrap()
+            );
+        }
+        assert_eq!(
+            serialize_with(&1.5f32, &finite).unwrap(),
+            serialize(&1.5f32).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_time() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct CompactDuration(#[serde(with = "crate::time::duration")] Duration);
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct CompactTime(#[serde(with = "crate::time::system_time")] SystemTime);
+
+        let cases = [
+            (Duration::ZERO, vec![0, 0]),
+            (Duration::from_secs(1), vec![1, 0]),
+            (
+                Duration::from_millis(1500),
+                vec![1, 0x80, 0xca, 0xb5, 0xee, 0x01],
+            ),
+            (
+                Duration::MAX,
+                [vec![0xff; 9], vec![0x01, 0xff, 0x93, 0xeb, 0xdc, 0x03]].concat(),
+            ),
+        ];
+
+        for (duration, bytes) in cases {
+            let serialized = serialize(&CompactDuration(duration)).unwrap();
+            assert_eq!(serialized, bytes);
+            assert_eq!(
+                deserialize::<CompactDuration>(&serialized).unwrap(),
+                CompactDuration(duration)
+            );
+        }
+
+        // small durations are far smaller than serde's 12-byte layout
+        assert_eq!(serialize(&Duration::from_secs(1)).unwrap().len(), 12);
+
+        // nanoseconds must be less than a second
+        let mut serialized = serialize(&CompactDuration(Duration::from_secs(1))).unwrap();
+        serialized.truncate(1);
+        serialized.extend_from_slice(&[0x80, 0x94, 0xeb, 0xdc, 0x03]);
+        assert!(matches!(
+            deserialize::<CompactDuration>(&serialized),
+            Err(Error::Custom(_))
+        ));
+
+        // points in time are encoded relative to the Unix epoch
+        let time = UNIX_EPOCH + Duration::new(1_700_000_000, 123_456_789);
+        let serialized = serialize(&CompactTime(time)).unwrap();
+        assert_eq!(
+            serialized,
+            serialize(&CompactDuration(Duration::new(1_700_000_000, 123_456_789))).unwrap()
+        );
+        assert_eq!(
+            deserialize::<CompactTime>(&serialized).unwrap(),
+            CompactTime(time)
+        );
+        assert!(serialize(&CompactTime(UNIX_EPOCH - Duration::from_secs(1))).is_err());
+    }
+
+    #[test]
+    fn test_tolerant() {
+        fn deserialize_tolerant<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_tolerant(true);
+            T::deserialize(&mut decoder)
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct OldRecord {
+            id: u32,
+            name: String,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct NewRecord {
+            id: u32,
+            name: String,
+            #[serde(default)]
+            tags: Vec<String>,
+        }
+
+        // a payload written before the field was added decodes with its
+        // default
+        let old = OldRecord {
+            id: 7,
+            name: "old".to_owned(),
+        };
+        let serialized = serialize(&old).unwrap();
+        assert_eq!(
+            deserialize_tolerant::<NewRecord>(&serialized).unwrap(),
+            NewRecord {
+                id: 7,
+                name: "old".to_owned(),
+                tags: Vec::new(),
+            }
+        );
+        assert!(deserialize::<NewRecord>(&serialized).is_err());
+
+        // new payloads decode as usual
+        let new = NewRecord {
+            id: 8,
+            name: "new".to_owned(),
+            tags: vec!["a".to_owned()],
+        };
+        let serialized = serialize(&new).unwrap();
+        assert_eq!(deserialize_tolerant::<NewRecord>(&serialized).unwrap(), new);
+
+        // fields without a default are still required
+        let serialized = serialize(&7u32).unwrap();
+        assert!(deserialize_tolerant::<NewRecord>(&serialized).is_err());
+
+        // ending partway through a field is still an error
+        let serialized = serialize(&old).unwrap();
+        assert!(matches!(
+            deserialize_tolerant::<NewRecord>(&serialized[..serialized.len() - 1])
+                .as_ref()
+                .map_err(Error::innermost),
+            Err(Error::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_tagged() {
+        fn serialize_tagged<T: Serialize>(value: &T) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_tagged(true);
+            value.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_tagged<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_tagged(true);
+            T::deserialize(&mut decoder)
+        }
+
+        // every value is preceded by its tag, and tuples by their length
+        assert_eq!(
+            serialize_tagged(&(1u8, "a")),
+            [18, 1, 2, 7, 1, 15, 1, 1, b'a']
+        );
+
+        // dynamic values round trip through `deserialize_any`
+        let value = serde_json::json!({
+            "name": "unbin",
+            "version": [0, 1, 0],
+            "ratio": -1.5,
+            "offset": -42,
+            "nested": {
+                "empty": [],
+                "flags": [true, false, null],
+                "items": [{ "id": 1 }, { "id": u64::MAX }],
+            },
+        });
+        let serialized = serialize_tagged(&value);
+        assert_eq!(
+            deserialize_tagged::<serde_json::Value>(&serialized).unwrap(),
+            value
+        );
+
+        // typed values still round trip, except for borrowed byte slices,
+        // which serde serializes as sequences but deserializes as bytes
+        let serialized = serialize_tagged(&*VALUE_NO_BORROWS);
+        assert_eq!(
+            deserialize_tagged::<MyStructNoBorrows>(&serialized).unwrap(),
+            *VALUE_NO_BORROWS
+        );
+
+        // a value of the wrong type is caught by its tag
+        let serialized = serialize_tagged(&1u32);
+        assert!(matches!(
+            deserialize_tagged::<u16>(&serialized),
+            Err(Error::UnexpectedTag {
+                expected: ValueType::U16,
+                found: ValueType::U32
+            })
+        ));
+        assert!(matches!(
+            deserialize_tagged::<serde_json::Value>(&[0xff]),
+            Err(Error::InvalidTag(0xff))
+        ));
+
+        // untagged data can't be decoded dynamically
+        let serialized = serialize(&value).unwrap();
+        assert!(matches!(
+            deserialize::<serde_json::Value>(&serialized),
+            Err(Error::CannotDeserializeAny)
+        ));
+    }
+
+    #[test]
+    fn test_frames_lossy() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Record<'a> {
+            id: u32,
+            name: &'a str,
+        }
+
+        let records = [
+            Record { id: 1, name: "a" },
+            Record { id: 2, name: "b" },
+            Record { id: 3, name: "c" },
+        ];
+        let mut frames = records
+            .iter()
+            .map(|record| serialize_length_prefixed(record).unwrap())
+            .collect::<Vec<_>>();
+
+        // corrupt the middle record's string with invalid UTF-8
+        let last = frames[1].len() - 1;
+        frames[1][last] = 0xff;
+        let serialized = frames.concat();
+
+        let results = deserialize_frames_lossy::<Record>(&serialized);
+        assert_eq!(results.len(), 3);
+        assert_eq!(results[0].as_ref().unwrap(), &records[0]);
+        assert!(results[1].is_err());
+        assert_eq!(results[2].as_ref().unwrap(), &records[2]);
+
+        // a frame too short for its record is skipped too
+        let serialized = [
+            serialize_length_prefixed(&1u16).unwrap(),
+            vec![1, 1, 0],
+            serialize_length_prefixed(&3u16).unwrap(),
+        ]
+        .concat();
+        let results = deserialize_frames_lossy::<u16>(&serialized);
+        assert!(matches!(
+            results[..],
+            [
+                Ok(1),
+                Err(Error::TruncatedValue { ty: ValueType::U16 }),
+                Ok(3)
+            ]
+        ));
+
+        // a length running past the end of the input can't be skipped
+        let mut serialized = frames.concat();
+        serialized.extend_from_slice(&[1, 9, 0]);
+        let results = deserialize_frames_lossy::<Record>(&serialized);
+        assert_eq!(results.len(), 4);
+        assert!(matches!(results[3], Err(Error::UnexpectedEof)));
+
+        assert!(deserialize_frames_lossy::<u16>(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_header() {
+        const MAGIC: [u8; 4] = *b"UNBN";
+
+        let serialized = serialize_with_header(&(7u16, "hi"), MAGIC, 3).unwrap();
+        assert_eq!(
+            serialized,
+            [b'U', b'N', b'B', b'N', 0, 3, 0, 7, 1, 2, b'h', b'i']
+        );
+        assert_eq!(
+            deserialize_with_header::<(u16, &str)>(&serialized, MAGIC).unwrap(),
+            ((7, "hi"), 3)
+        );
+
+        // peeking at the header leaves an in-memory reader where it was
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader);
+        assert_eq!(decoder.peek_header().unwrap(), (MAGIC, 3));
+        assert_eq!(decoder.peek_header().unwrap(), (MAGIC, 3));
+        assert_eq!(decoder.bytes_read(), 0);
+        assert_eq!(decoder.read_header().unwrap(), (MAGIC, 3));
+        assert_eq!(<(u16, &str)>::deserialize(&mut decoder).unwrap(), (7, "hi"));
+
+        // a stream has it consumed, and kept for reading it afterwards
+        let mut stream = serialized.as_slice();
+        let mut decoder = Decoder::new(&mut stream);
+        assert_eq!(decoder.peek_header().unwrap(), (MAGIC, 3));
+        assert_eq!(decoder.bytes_read(), 6);
+        assert_eq!(decoder.read_header().unwrap(), (MAGIC, 3));
+        assert_eq!(
+            <(u16, String)>::deserialize(&mut decoder).unwrap(),
+            (7, "hi".to_owned())
+        );
+
+        // as does a header straddling chunks
+        let mut reader = ChainedBytesReader::new([&serialized[..4], &serialized[4..]]);
+        let mut decoder = Decoder::new(&mut reader);
+        assert_eq!(decoder.peek_header().unwrap(), (MAGIC, 3));
+        assert_eq!(decoder.read_header().unwrap(), (MAGIC, 3));
+        assert_eq!(<(u16, &str)>::deserialize(&mut decoder).unwrap(), (7, "hi"));
+
+        // the magic number must match
+        assert!(matches!(
+            deserialize_with_header::<(u16, &str)>(&serialized, *b"OTHR"),
+            Err(Error::MagicMismatch {
+                expected: [b'O', b'T', b'H', b'R'],
+                found: MAGIC
+            })
+        ));
+
+        // and the header must be complete
+        let mut reader = BytesReader::new(&serialized[..5]);
+        let mut decoder = Decoder::new(&mut reader);
+        assert!(matches!(decoder.peek_header(), Err(Error::UnexpectedEof)));
+        assert!(matches!(decoder.read_header(), Err(Error::UnexpectedEof)));
+    }
+
+    #[test]
+    fn test_decoder_length_prefixed() {
+        // consecutive frames decode from a stream
+        let mut stream = serialize_length_prefixed(&(7u32, "hi")).unwrap();
+        stream.extend(serialize_length_prefixed(&*VALUE_NO_BORROWS).unwrap());
+        let mut reader = std::io::Cursor::new(stream);
+        let mut decoder = Decoder::new(&mut reader);
+        assert_eq!(
+            decoder
+                .deserialize_length_prefixed::<(u32, String)>()
+                .unwrap(),
+            (7, "hi".to_owned())
+        );
+        assert_eq!(
+            decoder
+                .deserialize_length_prefixed::<MyStructNoBorrows>()
+                .unwrap(),
+            *VALUE_NO_BORROWS
+        );
+
+        // a declared length one byte longer than the value is rejected...
+        let serialized = [1, 5, 0, 0, 0, 7, 0];
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader);
+        assert!(matches!(
+            decoder.deserialize_length_prefixed::<u32>(),
+            Err(Error::FrameLengthMismatch {
+                declared: 5,
+                consumed: 4
+            })
+        ));
+
+        // ...as is one shorter
+        let serialized = [1, 3, 0, 0, 0, 7];
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader);
+        assert!(matches!(
+            decoder.deserialize_length_prefixed::<u32>(),
+            Err(Error::FrameLengthMismatch {
+                declared: 3,
+                consumed: 4
+            })
+        ));
+    }
+
+    #[test]
+    fn test_length_prefixed() {
+        // the body is preceded by its length
+        let serialized = serialize_length_prefixed(&(7u32, "hi")).unwrap();
+        assert_eq!(serialized, [1, 8, 0, 0, 0, 7, 1, 2, b'h', b'i']);
+        assert_eq!(
+            deserialize_length_prefixed::<(u32, &str)>(&serialized).unwrap(),
+            (7, "hi")
+        );
+
+        let serialized = serialize_length_prefixed(&*VALUE).unwrap();
+        assert_eq!(
+            deserialize_length_prefixed::<MyStruct>(&serialized).unwrap(),
+            *VALUE
+        );
+
+        // input following the message is ignored
+        let mut serialized = serialize_length_prefixed(&7u32).unwrap();
+        serialized.push(0xff);
+        assert_eq!(deserialize_length_prefixed::<u32>(&serialized).unwrap(), 7);
+
+        // a declared length longer than the value is rejected...
+        let serialized = [1, 5, 0, 0, 0, 7, 0];
+        assert!(matches!(
+            deserialize_length_prefixed::<u32>(&serialized),
+            Err(Error::TrailingBytes { count: 1 })
+        ));
+
+        // ...as is one shorter than the value, or than the input
+        let serialized = [1, 3, 0, 0, 0, 7];
+        assert!(matches!(
+            deserialize_length_prefixed::<u32>(&serialized),
+            Err(Error::TruncatedValue { ty: ValueType::U32 })
+        ));
+        let serialized = [1, 5, 0, 0, 0, 7];
+        assert!(matches!(
+            deserialize_length_prefixed::<u32>(&serialized),
+            Err(Error::UnexpectedEof)
+        ));
+    }
+
+    #[test]
+    fn test_option_bitmap() {
+        type Options = (
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+            Option<u8>,
+        );
+
+        // the ten presence bits pack into two bytes, followed by the values
+        let options: Options = (
+            Some(1),
+            None,
+            None,
+            Some(4),
+            None,
+            None,
+            None,
+            None,
+            Some(9),
+            None,
+        );
+        let serialized = serialize(&OptionBitmap(options)).unwrap();
+        assert_eq!(serialized, [0b0000_1001, 0b0000_0001, 1, 4, 9]);
+        assert_eq!(serialize(&options).unwrap().len(), 13);
+        assert_eq!(
+            deserialize::<OptionBitmap<Options>>(&serialized).unwrap(),
+            OptionBitmap(options)
+        );
+
+        let serialized = serialize(&OptionBitmap(Options::default())).unwrap();
+        assert_eq!(serialized, [0, 0]);
+        assert_eq!(
+            deserialize::<OptionBitmap<Options>>(&serialized).unwrap(),
+            OptionBitmap(Options::default())
+        );
+
+        // a full last byte has no unused bits
+        let options = (
+            Some(1u8),
+            None::<u8>,
+            None::<u8>,
+            None::<u8>,
+            None::<u8>,
+            None::<u8>,
+            None::<u8>,
+            Some(8u16),
+        );
+        let serialized = serialize(&OptionBitmap(options)).unwrap();
+        assert_eq!(serialized, [0b1000_0001, 1, 0, 8]);
+        assert_eq!(
+            deserialize::<OptionBitmap<_>>(&serialized).unwrap(),
+            OptionBitmap(options)
+        );
+
+        // unused bits must be clear
+        assert!(deserialize::<OptionBitmap<Options>>(&[0, 0b0000_0100]).is_err());
+
+        // a missing value is an error
+        assert!(deserialize::<OptionBitmap<Options>>(&[1, 0]).is_err());
+
+        // the bitmap also works in tagged mode
+        let options: Options = (
+            None,
+            Some(2),
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            None,
+            Some(10),
+        );
+        let mut writer = BytesWriter::new();
+        let mut encoder = Encoder::new(&mut writer).with_tagged(true);
+        OptionBitmap(options).serialize(&mut encoder).unwrap();
+        let serialized = writer.into_inner();
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_tagged(true);
+        assert_eq!(
+            OptionBitmap::<Options>::deserialize(&mut decoder).unwrap(),
+            OptionBitmap(options)
+        );
+    }
+
+    #[test]
+    fn test_variant_index_boundary() {
+        use serde::ser::{SerializeStructVariant, SerializeTupleVariant};
+
+        #[derive(Clone, Copy)]
+        enum Kind {
+            Unit,
+            Newtype,
+            Tuple,
+            Struct,
+        }
+
+        struct Variant(Kind, u32);
+
+        impl Serialize for Variant {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: serde::Serializer,
+            {
+                let Self(kind, index) = *self;
+
+                match kind {
+                    Kind::Unit => serializer.serialize_unit_variant("Wide", index, "V"),
+                    Kind::Newtype => serializer.serialize_newtype_variant("Wide", index, "V", &1u8),
+                    Kind::Tuple => {
+                        let mut variant =
+                            serializer.serialize_tuple_variant("Wide", index, "V", 1)?;
+                        variant.serialize_field(&1u8)?;
+                        variant.end()
+                    }
+                    Kind::Struct => {
+                        let mut variant =
+                            serializer.serialize_struct_variant("Wide", index, "V", 1)?;
+                        variant.serialize_field("a", &1u8)?;
+                        variant.end()
+                    }
+                }
+            }
+        }
+
+        // every site encodes index 255 as a single byte, and rejects 256
+        for (kind, expected) in [
+            (Kind::Unit, &[255][..]),
+            (Kind::Newtype, &[255, 1]),
+            (Kind::Tuple, &[255, 1]),
+            (Kind::Struct, &[255, 1]),
+        ] {
+            assert_eq!(serialize(&Variant(kind, 255)).unwrap(), expected);
+            assert!(matches!(
+                serialize(&Variant(kind, 256)),
+                Err(Error::TooManyVariants("Wide"))
+            ));
+            assert!(matches!(
+                serialize(&Variant(kind, u32::MAX)),
+                Err(Error::TooManyVariants("Wide"))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_single_byte_io() {
+        // overridden single-byte writes match the default
+        let mut writer = BytesWriter::new();
+        let mut vec = Vec::new();
+        for byte in [0, 1, 0x7f, 0xff] {
+            writer.write_u8(byte).unwrap();
+            Write::write_u8(&mut vec, byte).unwrap();
+        }
+        assert_eq!(writer.as_slice(), vec);
+        assert_eq!(serialized_size(&(true, 7u8, Some(()))).unwrap(), 3);
+
+        // overridden single-byte reads match the default, including at the
+        // end of the input
+        let bytes = [0, 1, 0x7f, 0xff];
+        let mut reader = BytesReader::new(&bytes);
+        let mut io_reader = &bytes[..];
+        for _ in 0..bytes.len() {
+            assert_eq!(
+                reader.read_u8().unwrap(),
+                Read::read_u8(&mut io_reader).unwrap()
+            );
+        }
+        assert_eq!(reader.position(), bytes.len());
+        assert!(matches!(reader.read_u8(), Err(Error::UnexpectedEof)));
+        assert!(matches!(
+            Read::read_u8(&mut io_reader),
+            Err(Error::UnexpectedEof)
+        ));
+
+        // values decode the same through either
+        let serialized = serialize(&*VALUE_NO_BORROWS).unwrap();
+        assert_eq!(
+            deserialize_from::<MyStructNoBorrows, _>(&mut &serialized[..]).unwrap(),
+            deserialize::<MyStructNoBorrows>(&serialized).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_any_hint() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(untagged)]
+        enum Id {
+            Num(u32),
+            Name(String),
+        }
+
+        fn hint(position: usize) -> Option<ValueType> {
+            match position % 2 {
+                0 => Some(ValueType::U32),
+                _ => Some(ValueType::String),
+            }
+        }
+
+        fn deserialize_hinted<'de, T: Deserialize<'de>>(
+            bytes: &'de [u8],
+            hint: fn(usize) -> Option<ValueType>,
+        ) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_any_hint(hint);
+            T::deserialize(&mut decoder)
+        }
+
+        // each call to `deserialize_any` is hinted by its position
+        let ids = vec![
+            Id::Num(7),
+            Id::Name("seven".to_owned()),
+            Id::Num(8),
+            Id::Name("eight".to_owned()),
+        ];
+        let serialized = serialize(&ids).unwrap();
+        assert_eq!(
+            deserialize_hinted::<Vec<Id>>(&serialized, hint).unwrap(),
+            ids
+        );
+
+        // without a hint, or with an unhelpful one, decoding fails as usual
+        let serialized = serialize(&Id::Num(7)).unwrap();
+        assert!(matches!(
+            deserialize::<Id>(&serialized),
+            Err(Error::CannotDeserializeAny)
+        ));
+        assert!(matches!(
+            deserialize_hinted::<Id>(&serialized, |_| None),
+            Err(Error::CannotDeserializeAny)
+        ));
+        assert!(matches!(
+            deserialize_hinted::<Id>(&serialized, |_| Some(ValueType::Struct)),
+            Err(Error::CannotDeserializeAny)
+        ));
+    }
+
+    #[test]
+    fn test_btree_map_order() {
+        use std::collections::BTreeMap;
+
+        // keys are written in ascending order, whatever the insertion order
+        let mut map = BTreeMap::new();
+        map.insert(3u8, 'c');
+        map.insert(1u8, 'a');
+        map.insert(2u8, 'b');
+        let serialized = serialize(&map).unwrap();
+        assert_eq!(serialized, [1, 3, 1, 1, b'a', 2, 1, b'b', 3, 1, b'c']);
+        assert_eq!(deserialize::<BTreeMap<u8, char>>(&serialized).unwrap(), map);
+
+        // out-of-order entries are accepted and sorted, even in canonical mode
+        let out_of_order = [1, 3, 3, 1, b'c', 1, 1, b'a', 2, 1, b'b'];
+        assert_eq!(
+            deserialize::<BTreeMap<u8, char>>(&out_of_order).unwrap(),
+            map
+        );
+        let mut reader = BytesReader::new(&out_of_order);
+        let mut decoder = Decoder::new(&mut reader).with_canonical(true);
+        let decoded = BTreeMap::<u8, char>::deserialize(&mut decoder).unwrap();
+        assert_eq!(decoded, map);
+
+        // so re-encoding them produces the sorted encoding
+        assert_eq!(serialize(&decoded).unwrap(), serialized);
+    }
+
+    #[test]
+    fn test_large_lengths() {
+        const USIZE_BYTES: usize = std::mem::size_of::<usize>();
+
+        // every length round trips, up to `usize::MAX`
+        for len in [
+            0,
+            1,
+            255,
+            256,
+            65_535,
+            65_536,
+            u32::MAX as usize,
+            usize::MAX,
+        ] {
+            let encoded = encode_len_large(len);
+            assert_eq!(decode_len_small(encoded[0]), encoded.len() - 1);
+            assert_eq!(decode_len_large(&encoded[1..]), Some(len));
+        }
+
+        let mut expected = vec![USIZE_BYTES as u8];
+        expected.extend_from_slice(&[0xff; USIZE_BYTES]);
+        assert_eq!(encode_len_large(usize::MAX), expected);
+        assert_eq!(encode_len_large(0), [0]);
+
+        // redundant leading zero bytes don't count towards overflow...
+        let mut encoded = vec![0; USIZE_BYTES];
+        encoded.push(1);
+        assert_eq!(decode_len_large(&encoded), Some(1));
+
+        // ...but any length too large for a `usize` is rejected
+        let mut encoded = vec![1];
+        encoded.extend_from_slice(&[0; USIZE_BYTES]);
+        assert_eq!(decode_len_large(&encoded), None);
+
+        let mut serialized = vec![USIZE_BYTES as u8 + 1, 1];
+        serialized.extend_from_slice(&[0; USIZE_BYTES]);
+        assert!(matches!(
+            deserialize::<Vec<u8>>(&serialized),
+            Err(Error::LengthOverflow(bytes)) if bytes == serialized
+        ));
+        assert!(matches!(
+            deserialize_length_prefixed::<u8>(&serialized),
+            Err(Error::LengthOverflow(bytes)) if bytes == serialized
+        ));
+    }
+
+    #[test]
+    fn test_deserialize_from_borrowed() {
+        let serialized = serialize(&*VALUE).unwrap();
+        let mut buffer = Vec::new();
+
+        // borrowed fields can only be decoded by borrowing from the buffer
+        let mut cursor = std::io::Cursor::new(&serialized);
+        let value: MyStruct = deserialize_from_borrowed(&mut cursor, &mut buffer, None).unwrap();
+        assert_eq!(value, *VALUE);
+
+        // the buffer is reused, and the limit applies to the whole stream
+        let len = serialized.len();
+        let mut cursor = std::io::Cursor::new(&serialized);
+        assert!(matches!(
+            deserialize_from_borrowed::<MyStruct, _>(&mut cursor, &mut buffer, Some(len - 1)),
+            Err(Error::InputLimitExceeded { limit }) if limit == len - 1
+        ));
+        let mut cursor = std::io::Cursor::new(&serialized);
+        let value: MyStruct =
+            deserialize_from_borrowed(&mut cursor, &mut buffer, Some(len)).unwrap();
+        assert_eq!(value, *VALUE);
+        assert_eq!(buffer, serialized);
+    }
+
+    #[test]
+    fn test_internally_tagged_enums() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Circle {
+            radius: f32,
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        #[serde(tag = "kind")]
+        enum Shape {
+            Empty,
+            #[serde(rename = "circle")]
+            Circle(Circle),
+            Rect {
+                width: u32,
+                height: u32,
+            },
+        }
+
+        fn serialize_tagged<T: Serialize>(value: &T) -> Vec<u8> {
+            let mut writer = BytesWriter::new();
+            let mut encoder = Encoder::new(&mut writer).with_tagged(true);
+            value.serialize(&mut encoder).unwrap();
+            writer.into_inner()
+        }
+
+        fn deserialize_tagged<'de, T: Deserialize<'de>>(bytes: &'de [u8]) -> Result<T> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_tagged(true);
+            T::deserialize(&mut decoder)
+        }
+
+        let shapes = vec![
+            Shape::Empty,
+            Shape::Circle(Circle { radius: 1.5 }),
+            Shape::Rect {
+                width: 2,
+                height: 3,
+            },
+        ];
+
+        // the variant name is written as the first field, renamed or not
+        let serialized = serialize_tagged(&shapes[1]);
+        assert_eq!(
+            &serialized[..11],
+            [18, 1, 2, 15, 1, 6, b'c', b'i', b'r', b'c', b'l']
+        );
+
+        // in tagged mode, internally tagged enums round trip
+        let serialized = serialize_tagged(&shapes);
+        assert_eq!(
+            deserialize_tagged::<Vec<Shape>>(&serialized).unwrap(),
+            shapes
+        );
+
+        // without it they encode, but can't be decoded
+        let serialized = serialize(&shapes).unwrap();
+        let err = deserialize::<Vec<Shape>>(&serialized).unwrap_err();
+        assert!(matches!(err, Error::CannotDeserializeAny));
+        assert!(err.to_string().contains("tagged mode"));
+    }
+
+    #[test]
+    fn test_serialize_iter() {
+        // items are encoded like a collected sequence
+        let mut writer = BytesWriter::new();
+        serialize_iter(0..1000u32, &mut writer).unwrap();
+        let serialized = writer.into_inner();
+        let expected: Vec<u32> = (0..1000).collect();
+        assert_eq!(serialized, serialize(&expected).unwrap());
+        assert_eq!(deserialize::<Vec<u32>>(&serialized).unwrap(), expected);
+
+        let mut writer = BytesWriter::new();
+        serialize_iter(["a", "b"].iter().map(|s| s.to_uppercase()), &mut writer).unwrap();
+        assert_eq!(
+            deserialize::<Vec<String>>(writer.as_slice()).unwrap(),
+            ["A", "B"]
+        );
+
+        // an iterator misreporting its length is caught
+        struct Liar(u32);
+
+        impl Iterator for Liar {
+            type Item = u32;
+
+            fn next(&mut self) -> Option<u32> {
+                self.0 = self.0.checked_sub(1)?;
+                Some(self.0)
+            }
+        }
+
+        impl ExactSizeIterator for Liar {
+            fn len(&self) -> usize {
+                2
+            }
+        }
+
+        // surplus items stop at the first, before it is written
+        let mut writer = BytesWriter::new();
+        assert!(matches!(
+            serialize_iter(Liar(5), &mut writer),
+            Err(Error::IteratorLengthMismatch {
+                reported: 2,
+                yielded: 3
+            })
+        ));
+        assert_eq!(writer.into_inner(), serialize(&vec![4u32, 3]).unwrap());
+
+        let mut writer = BytesWriter::new();
+        assert!(matches!(
+            serialize_iter(Liar(1), &mut writer),
+            Err(Error::IteratorLengthMismatch {
+                reported: 2,
+                yielded: 1
+            })
+        ));
+    }
+
+    #[test]
+    fn test_invalid_utf8() {
+        // a lone continuation byte, as a string...
+        let invalid_str = [1, 2, b'a', 0x80];
+        assert!(matches!(
+            deserialize::<&str>(&invalid_str),
+            Err(Error::InvalidUtf8 { ty: ValueType::Str, source }) if source.valid_up_to() == 1
+        ));
+        assert!(matches!(
+            deserialize::<String>(&invalid_str),
+            Err(Error::InvalidUtf8 {
+                ty: ValueType::String,
+                ..
+            })
+        ));
+
+        // ...and as a char
+        assert!(matches!(
+            deserialize::<char>(&[1, 0x80]),
+            Err(Error::InvalidUtf8 {
+                ty: ValueType::Char,
+                ..
+            })
+        ));
+
+        let err = deserialize::<char>(&[1, 0x80]).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("invalid UTF-8 while deserializing character"));
+        assert!(std::error::Error::source(&err).is_some());
+    }
+
+    #[test]
+    fn test_nonzero() {
+        use std::num::{
+            NonZeroI128, NonZeroI16, NonZeroI32, NonZeroI64, NonZeroI8, NonZeroU128, NonZeroU16,
+            NonZeroU32, NonZeroU64, NonZeroU8,
+        };
+
+        let value = NonZeroU32::new(0x01020304).unwrap();
+        let serialized = serialize(&value).unwrap();
+        assert_eq!(serialized, [1, 2, 3, 4]);
+        assert_eq!(deserialize::<NonZeroU32>(&serialized).unwrap(), value);
+        assert_eq!(
+            deserialize::<NonZeroI64>(&serialize(&-1i64).unwrap()).unwrap(),
+            NonZeroI64::new(-1).unwrap()
+        );
+
+        assert!(matches!(
+            deserialize::<NonZeroU32>(&[0, 0, 0, 0]),
+            Err(Error::UnexpectedZero { ty: ValueType::U32 })
+        ));
+        assert!(matches!(
+            deserialize::<NonZeroU8>(&[0]),
+            Err(Error::UnexpectedZero { ty: ValueType::U8 })
+        ));
+        assert!(matches!(
+            deserialize::<NonZeroI64>(&[0; 8]),
+            Err(Error::UnexpectedZero { ty: ValueType::I64 })
+        ));
+        assert!(matches!(
+            deserialize::<NonZeroU128>(&[0; 16]),
+            Err(Error::UnexpectedZero {
+                ty: ValueType::U128
+            })
+        ));
+        assert_eq!(
+            deserialize::<NonZeroU32>(&[0; 4]).unwrap_err().to_string(),
+            "expected a nonzero unsigned 32-bit integer, found zero"
+        );
+
+        // every nonzero type reports its own integer type
+        fn zero_type<T: serde::de::DeserializeOwned + std::fmt::Debug>(width: usize) -> ValueType {
+            match deserialize::<T>(&vec![0; width]) {
+                Err(Error::UnexpectedZero { ty }) => ty,
+                other => panic!("expected an unexpected zero, got {other:?}"),
+            }
+        }
+
+        assert_eq!(zero_type::<NonZeroI8>(1), ValueType::I8);
+        assert_eq!(zero_type::<NonZeroI16>(2), ValueType::I16);
+        assert_eq!(zero_type::<NonZeroI32>(4), ValueType::I32);
+        assert_eq!(zero_type::<NonZeroI64>(8), ValueType::I64);
+        assert_eq!(zero_type::<NonZeroI128>(16), ValueType::I128);
+        assert_eq!(zero_type::<NonZeroU8>(1), ValueType::U8);
+        assert_eq!(zero_type::<NonZeroU16>(2), ValueType::U16);
+        assert_eq!(zero_type::<NonZeroU32>(4), ValueType::U32);
+        assert_eq!(zero_type::<NonZeroU64>(8), ValueType::U64);
+        assert_eq!(zero_type::<NonZeroU128>(16), ValueType::U128);
+
+        // other zeros rejected as invalid values aren't mistaken for them
+        struct Positive;
+
+        impl<'de> Deserialize<'de> for Positive {
+            fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+            where
+                D: serde::Deserializer<'de>,
+            {
+                match u8::deserialize(deserializer)? {
+                    0 => Err(serde::de::Error::invalid_value(
+                        serde::de::Unexpected::Unsigned(0),
+                        &"a nonzero u8 or more",
+                    )),
+                    _ => Ok(Positive),
+                }
+            }
+        }
+
+        assert!(matches!(
+            deserialize::<Positive>(&[0]),
+            Err(Error::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_other_sequences() {
+        use std::collections::{LinkedList, VecDeque};
+
+        // a deque whose contents are likely to wrap around its buffer, so
+        // that its order differs from its layout in memory
+        let mut deque = VecDeque::with_capacity(4);
+        deque.extend([2u32, 3, 4]);
+        deque.pop_front();
+        deque.extend([5, 6]);
+        let serialized = serialize(&deque).unwrap();
+        assert_eq!(serialized, serialize(&vec![3u32, 4, 5, 6]).unwrap());
+        let deserialized = deserialize::<VecDeque<u32>>(&serialized).unwrap();
+        assert_eq!(deserialized, deque);
+        assert_eq!(deserialized, [3, 4, 5, 6]);
+
+        let list = ["a", "bc", "", "def"]
+            .into_iter()
+            .map(String::from)
+            .collect::<LinkedList<_>>();
+        let serialized = serialize(&list).unwrap();
+        assert_eq!(serialized, serialize(&vec!["a", "bc", "", "def"]).unwrap());
+        let deserialized = deserialize::<LinkedList<String>>(&serialized).unwrap();
+        assert!(deserialized.iter().eq(&list));
+
+        // the three are interchangeable
+        assert_eq!(
+            deserialize::<Vec<String>>(&serialized).unwrap(),
+            ["a", "bc", "", "def"]
+        );
+        assert!(
+            deserialize::<VecDeque<u32>>(&serialize(&VecDeque::<u32>::new()).unwrap())
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_ranges() {
+        let serialized = serialize(&(5u32..10)).unwrap();
+        assert_eq!(serialized, [0, 0, 0, 5, 0, 0, 0, 10]);
+        assert_eq!(
+            deserialize::<std::ops::Range<u32>>(&serialized).unwrap(),
+            5..10
+        );
+
+        let serialized = serialize(&(5u32..=10)).unwrap();
+        assert_eq!(serialized, [0, 0, 0, 5, 0, 0, 0, 10]);
+        assert_eq!(
+            deserialize::<std::ops::RangeInclusive<u32>>(&serialized).unwrap(),
+            5..=10
+        );
+
+        let mut writer = BytesWriter::new();
+        let mut encoder = Encoder::new(&mut writer).with_int_encoding(IntEncoding::Varint);
+        (5u32..10).serialize(&mut encoder).unwrap();
+        (5u32..=10).serialize(&mut encoder).unwrap();
+        let serialized = writer.into_inner();
+        assert_eq!(serialized, [5, 10, 5, 10]);
+        let mut reader = BytesReader::new(&serialized);
+        let mut decoder = Decoder::new(&mut reader).with_int_encoding(IntEncoding::Varint);
+        assert_eq!(
+            std::ops::Range::<u32>::deserialize(&mut decoder).unwrap(),
+            5..10
+        );
+        assert_eq!(
+            std::ops::RangeInclusive::<u32>::deserialize(&mut decoder).unwrap(),
+            5..=10
+        );
+
+        // an exhausted inclusive range is written as its bounds alone
+        let mut range = 5u8..=5;
+        range.next();
+        assert!(range.is_empty());
+        let serialized = serialize(&range).unwrap();
+        assert_eq!(serialized, [5, 5]);
+        assert!(!deserialize::<std::ops::RangeInclusive<u8>>(&serialized)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_result() {
+        type Reply = std::result::Result<u8, String>;
+
+        let ok: Reply = Ok(5);
+        let err: Reply = Err("x".to_owned());
+        assert_eq!(serialize(&ok).unwrap(), [0, 5]);
+        assert_eq!(serialize(&err).unwrap(), [1, 1, 1, b'x']);
+        assert_eq!(serialize(&Err::<u8, _>("x")).unwrap(), [1, 1, 1, b'x']);
+
+        assert_eq!(deserialize::<Reply>(&[0, 5]).unwrap(), ok);
+        assert_eq!(deserialize::<Reply>(&[1, 1, 1, b'x']).unwrap(), err);
+        assert!(matches!(
+            deserialize::<Reply>(&[2, 5]),
+            Err(Error::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_indexed_encoder() {
+        let mut cursor = std::io::Cursor::new(vec![0xaa, 0xbb]);
+        cursor.seek(std::io::SeekFrom::End(0)).unwrap();
+        let length_fields = IndexedEncoder::new(&mut cursor)
+            .serialize(&(vec![1u16, 2, 3], "hi"))
+            .unwrap();
+        assert_eq!(length_fields, [(2, ValueType::Seq), (10, ValueType::Str)]);
+
+        // each recorded position points at the length bytes
+        let bytes = cursor.into_inner();
+        assert_eq!(bytes[2..4], [1, 3]);
+        assert_eq!(bytes[10..12], [1, 2]);
+        assert_eq!(
+            deserialize::<(Vec<u16>, String)>(&bytes[2..]).unwrap(),
+            (vec![1, 2, 3], "hi".to_owned())
+        );
+
+        // nested lengths are recorded in the order they are written
+        let mut cursor = std::io::Cursor::new(Vec::new());
+        let length_fields = IndexedEncoder::new(&mut cursor)
+            .with_length_encoding(LengthEncoding::Varint)
+            .serialize(&vec![vec![1u8, 2], vec![]])
+            .unwrap();
+        assert_eq!(
+            length_fields,
+            [
+                (0, ValueType::Seq),
+                (1, ValueType::Seq),
+                (4, ValueType::Seq)
+            ]
+        );
+        assert_eq!(cursor.into_inner(), [2, 2, 1, 2, 0]);
+    }
+
+    #[test]
+    fn test_delimiter() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Entry {
+            level: u8,
+            message: String,
+        }
+
+        fn decode_line(bytes: &[u8]) -> Result<Entry> {
+            let mut reader = BytesReader::new(bytes);
+            let mut decoder = Decoder::new(&mut reader).with_delimiter(b"\r\n");
+            Entry::deserialize(&mut decoder)
+        }
+
+        let entry = Entry {
+            level: 3,
+            message: "disk full".to_owned(),
+        };
+        let mut log = serialize(&entry).unwrap();
+        log.extend_from_slice(b"\r\n");
+        let first_len = log.len();
+        log.extend_from_slice(&serialize(&(4u8, "ok")).unwrap());
+        log.extend_from_slice(b"\r\n");
+
+        // a complete value stops short of the delimiter
+        assert_eq!(decode_line(&log).unwrap(), entry);
+        assert_eq!(
+            decode_line(&log[first_len..]).unwrap(),
+            Entry {
+                level: 4,
+                message: "ok".to_owned()
+            }
+        );
+
+        // a value cut short stops at the delimiter instead of reading on into
+        // the next line
+        let mut truncated = vec![3, 1, 9, b'd', b'i', b's', b'k'];
+        truncated.extend_from_slice(&log[first_len - 2..]);
+        assert!(matches!(
+            decode_line(&truncated).as_ref().map_err(Error::innermost),
+            Err(Error::DelimiterReached)
+        ));
+    }
+
+    #[test]
+    fn test_skipped_fields() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Profile {
+            #[serde(skip_serializing_if = "Option::is_none")]
+            nickname: Option<String>,
+            age: u8,
+        }
+
+        #[derive(Debug, Serialize, Deserialize)]
+        enum Event {
+            Renamed {
+                #[serde(skip_serializing_if = "String::is_empty")]
+                name: String,
+            },
+        }
+
+        // decoding this positionally would read the age as the option
+        assert!(matches!(
+            serialize(&Profile {
+                nickname: None,
+                age: 30
+            }),
+            Err(Error::FieldCountMismatch {
+                expected: 2,
+                serialized: 1
+            })
+        ));
+        assert!(matches!(
+            serialize(&Event::Renamed {
+                name: String::new()
+            }),
+            Err(Error::FieldCountMismatch {
+                expected: 1,
+                serialized: 0
+            })
+        ));
+
+        // nothing is skipped while the field is present
+        let profile = Profile {
+            nickname: Some("Al".to_owned()),
+            age: 30,
+        };
+        let serialized = serialize(&profile).unwrap();
+        let deserialized = deserialize::<Profile>(&serialized).unwrap();
+        assert_eq!(deserialized.nickname.as_deref(), Some("Al"));
+        assert_eq!(deserialized.age, 30);
+    }
+
+    #[test]
+    fn test_incomplete_struct() {
+        #[derive(Debug, Serialize, Deserialize)]
+        struct Point {
+            x: u16,
+            y: u16,
+            z: u16,
+        }
+
+        let serialized = serialize(&Point { x: 1, y: 2, z: 3 }).unwrap();
+
+        // cut short after the first field
+        assert!(matches!(
+            deserialize::<Point>(&serialized[..2]),
+            Err(Error::IncompleteStruct {
+                expected: 3,
+                read: 1
+            })
+        ));
+
+        // tuples count their elements in the same way
+        assert!(matches!(
+            deserialize::<(u8, u8, u8)>(&[1, 2]),
+            Err(Error::IncompleteStruct {
+                expected: 3,
+                read: 2
+            })
+        ));
+
+        // the end of input within a field is still attributed to that field
+        assert!(matches!(
+            deserialize::<Point>(&serialized[..3]),
+            Err(Error::InStructField { field: "y", source })
+                if matches!(*source, Error::TruncatedValue { ty: ValueType::U16 })
+        ));
+    }
+
+    #[test]
+    fn test_element_context() {
+        // the third element of the tuple is an invalid bool
+        let err = deserialize::<(u8, bool, bool)>(&[1, 0, 2]).unwrap_err();
+        assert!(matches!(
+            &err,
+            Error::InTupleElement { index: 2, source }
+                if matches!(**source, Error::InvalidBytes { ty: ValueType::Bool, .. })
+        ));
+        assert_eq!(
+            err.to_string(),
+            "invalid byte sequence while deserializing boolean: `[0x02]` at tuple index 2"
+        );
+        assert!(matches!(
+            err.innermost(),
+            Error::InvalidBytes {
+                ty: ValueType::Bool,
+                ..
+            }
+        ));
+
+        // struct fields are named, and nested positions each add context
+        #[derive(Debug, Deserialize)]
+        struct Settings {
+            _enabled: bool,
+            _limits: (u8, bool),
+        }
+
+        let err = deserialize::<Settings>(&[1, 5, 7]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "invalid byte sequence while deserializing boolean: `[0x07]` at tuple index 1 in \
+             field `_limits`"
+        );
+
+        // errors outside of tuples and structs are left alone
+        assert!(matches!(
+            deserialize::<Vec<bool>>(&[1, 1, 2]),
+            Err(Error::InvalidBytes { .. })
+        ));
+    }
+
+    #[test]
+    fn test_discriminant() {
+        use crate::discriminant::Discriminant;
+
+        #[derive(Debug, Clone, Copy, PartialEq)]
+        #[repr(u8)]
+        enum Opcode {
+            Ping = 0x01,
+            Data = 0x10,
+            Ack = 0x11,
+            Close = 0xff,
+        }
+
+        impl Opcode {
+            const ALL: [Self; 4] = [Self::Ping, Self::Data, Self::Ack, Self::Close];
+        }
+
+        impl Discriminant for Opcode {
+            fn discriminant(&self) -> u8 {
+                *self as u8
+            }
+
+            fn from_discriminant(discriminant: u8) -> Option<Self> {
+                Self::ALL
+                    .into_iter()
+                    .find(|opcode| opcode.discriminant() == discriminant)
+            }
+        }
+
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Packet {
+            #[serde(with = "crate::discriminant")]
+            opcode: Opcode,
+            sequence: u8,
+        }
+
+        // each variant is written as its opcode rather than its position
+        for (opcode, expected) in Opcode::ALL.into_iter().zip([0x01, 0x10, 0x11, 0xff]) {
+            let packet = Packet {
+                opcode,
+                sequence: 3,
+            };
+            let serialized = serialize(&packet).unwrap();
+            assert_eq!(serialized, [expected, 3]);
+            assert_eq!(deserialize::<Packet>(&serialized).unwrap(), packet);
+        }
+
+        // unknown opcodes are rejected
+        assert!(matches!(
+            deserialize::<Packet>(&[0x02, 3]),
+            Err(Error::InStructField { field: "opcode", source })
+                if matches!(*source, Error::Custom(_))
+        ));
+    }
+
+    #[test]
+    fn test_empty_input() {
+        // input that ends before a value starts is told apart from input that
+        // ends partway through one
+        assert!(matches!(deserialize::<u32>(&[]), Err(Error::EmptyInput)));
+        assert!(matches!(
+            deserialize::<u32>(&[0]),
+            Err(Error::TruncatedValue { ty: ValueType::U32 })
+        ));
+        assert!(matches!(
+            deserialize::<MyStructNoBorrows>(&[]),
+            Err(Error::EmptyInput)
+        ));
+        assert!(matches!(
+            deserialize::<MyStructNoBorrows>(&[1]),
+            Err(Error::IncompleteStruct { read: 1, .. })
+        ));
+        assert!(matches!(deserialize::<String>(&[]), Err(Error::EmptyInput)));
+        assert!(matches!(
+            deserialize::<String>(&[3, b'a']),
+            Err(Error::UnexpectedEof)
+        ));
+        assert!(matches!(
+            deserialize_length_prefixed::<u8>(&[]),
+            Err(Error::EmptyInput)
+        ));
+
+        // values with nothing to read still decode from empty input
+        deserialize::<()>(&[]).unwrap();
+
+        // a reader that knows its length lets a stream of values stop cleanly
+        let serialized = [serialize(&1u16).unwrap(), serialize(&2u16).unwrap()].concat();
+        let mut reader = BytesReader::new(&serialized);
+        let mut values = Vec::new();
+
+        loop {
+            match deserialize_from::<u16, _>(&mut reader) {
+                Ok(value) => values.push(value),
+                Err(Error::EmptyInput) => break,
+                Err(err) => panic!("unexpected error: {err}"),
+            }
+        }
+
+        assert_eq!(values, [1, 2]);
+        let mut reader = BytesReader::new(&serialized[..3]);
+        deserialize_from::<u16, _>(&mut reader).unwrap();
+        assert!(matches!(
+            deserialize_from::<u16, _>(&mut reader),
+            Err(Error::TruncatedValue { ty: ValueType::U16 })
+        ));
+
+        // streams can't tell, so report a premature end as usual
+        let mut stream = [].as_slice();
+        assert!(matches!(
+            deserialize_from::<u16, _>(&mut stream),
+            Err(Error::TruncatedValue { ty: ValueType::U16 })
+        ));
+    }
+
+    /// Asserts that every prefix of `value` encoded with `options` fails to
+    /// decode, reporting the truncated value as `ty`.
+    fn assert_truncated<T>(value: T, ty: ValueType, options: &Options)
+    where
+        T: Serialize + for<'de> Deserialize<'de>,
+    {
+        let serialized = serialize_with(&value, options).unwrap();
+        assert!(matches!(
+            deserialize_with::<T>(&[], options),
+            Err(Error::EmptyInput)
+        ));
+
+        for len in 1..serialized.len() {
+            let res = deserialize_with::<T>(&serialized[..len], options);
+            assert!(
+                matches!(res, Err(Error::TruncatedValue { ty: actual }) if actual == ty),
+                "{ty:?} truncated to {len} bytes"
+            );
+        }
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&serialized[..serialized.len() - 1]).unwrap();
+        file.rewind().unwrap();
+        let mut decoder = Decoder::new(&mut file).with_options(*options);
+        let res = T::deserialize(&mut decoder);
+        assert!(matches!(res, Err(Error::TruncatedValue { ty: actual }) if actual == ty));
+    }
+
+    #[test]
+    fn test_truncated_varint_integers() {
+        let options = builder().int_encoding(IntEncoding::Varint).build();
+        assert_truncated(i8::MIN, ValueType::I8, &options);
+        assert_truncated(i16::MIN, ValueType::I16, &options);
+        assert_truncated(i32::MIN, ValueType::I32, &options);
+        assert_truncated(i64::MIN, ValueType::I64, &options);
+        assert_truncated(i128::MIN, ValueType::I128, &options);
+        assert_truncated(u8::MAX, ValueType::U8, &options);
+        assert_truncated(u16::MAX, ValueType::U16, &options);
+        assert_truncated(u32::MAX, ValueType::U32, &options);
+        assert_truncated(u64::MAX, ValueType::U64, &options);
+        assert_truncated(u128::MAX, ValueType::U128, &options);
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let options = Options::default();
+
+        assert_truncated(true, ValueType::Bool, &options);
+        assert_truncated(-1i8, ValueType::I8, &options);
+        assert_truncated(-1i16, ValueType::I16, &options);
+        assert_truncated(-1i32, ValueType::I32, &options);
+        assert_truncated(-1i64, ValueType::I64, &options);
+        assert_truncated(-1i128, ValueType::I128, &options);
+        assert_truncated(1u8, ValueType::U8, &options);
+        assert_truncated(1u16, ValueType::U16, &options);
+        assert_truncated(1u32, ValueType::U32, &options);
+        assert_truncated(1u64, ValueType::U64, &options);
+        assert_truncated(1u128, ValueType::U128, &options);
+        assert_truncated(1.5f32, ValueType::F32, &options);
+        assert_truncated(1.5f64, ValueType::F64, &options);
+        assert_truncated('🦀', ValueType::Char, &options);
+        assert_truncated(None::<u8>, ValueType::Option, &options);
+        assert_truncated(MyEnum::UnitVariant, ValueType::Enum, &options);
+
+        // the innermost value cut short is reported
+        assert!(matches!(
+            deserialize::<(u8, u64)>(&[1, 0, 0, 0, 0, 0, 0, 0]),
+            Err(Error::InTupleElement { index: 1, source })
+                if matches!(*source, Error::TruncatedValue { ty: ValueType::U64 })
+        ));
+        assert!(matches!(
+            deserialize::<Vec<u16>>(&[1, 2, 0, 1, 0]),
+            Err(Error::TruncatedValue { ty: ValueType::U16 })
+        ));
+
+        // variable-length values have no fixed width to attribute the end to
+        assert!(matches!(
+            deserialize::<String>(&[1, 5, b'a']),
+            Err(Error::UnexpectedEof)
+        ));
+    }
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_round_trip() {
+        assert_eq!(round_trip(&*VALUE_NO_BORROWS).unwrap(), *VALUE_NO_BORROWS);
+
+        // a serialize impl writing more than its deserialize impl reads
+        #[derive(Debug, PartialEq, Deserialize)]
+        struct Lopsided(u8);
+
+        impl Serialize for Lopsided {
+            fn serialize<S>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                (self.0, 0u8).serialize(serializer)
+            }
+        }
+
+        assert!(matches!(
+            round_trip(&Lopsided(1)),
+            Err(Error::TrailingBytes { count: 1 })
+        ));
+    }
+
+    #[cfg(feature = "uuid")]
+    #[test]
+    fn test_uuid() {
+        #[derive(Debug, PartialEq, Serialize, Deserialize)]
+        struct Record {
+            #[serde(with = "crate::uuid")]
+            id: ::uuid::Uuid,
+        }
+
+        let record = Record {
+            id: ::uuid::Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff),
+        };
+        let serialized = serialize(&record).unwrap();
+        assert_eq!(serialized.len(), 16);
+        assert_eq!(serialized, record.id.as_bytes());
+        assert_eq!(deserialize::<Record>(&serialized).unwrap(), record);
     }
 
     #[test]
@@ -550,5 +5353,6 @@ mod tests {
         assert_sync(&Encoder::new(&mut tempfile::tempfile().unwrap()));
         assert_send(&Decoder::new(&mut tempfile::tempfile().unwrap()));
         assert_sync(&Decoder::new(&mut tempfile::tempfile().unwrap()));
+        assert_sync(&WriterPool::new());
     }
 }
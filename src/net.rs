@@ -0,0 +1,175 @@
+//! Fixed-layout serialization of network addresses.
+//!
+//! serde's own representations of [`IpAddr`] and [`SocketAddr`] go through
+//! its enum and struct machinery, so the bytes they produce are an
+//! implementation detail. The modules here pin a compact layout instead:
+//!
+//! | Type         | Layout                                                      |
+//! | ------------ | ----------------------------------------------------------- |
+//! | `IpAddr`     | tag (`4` or `6`), then 4 or 16 address bytes                |
+//! | `SocketAddr` | tag (`4` or `6`), 4 or 16 address bytes, then a 2-byte port |
+//!
+//! IPv6 flow information and scope IDs are not encoded, and decode as zero.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use std::net::{IpAddr, SocketAddr};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Peer {
+//!     #[serde(with = "unbin::net::ip_addr")]
+//!     ip: IpAddr,
+//!     #[serde(with = "unbin::net::socket_addr")]
+//!     addr: SocketAddr,
+//! }
+//! ```
+
+use serde::de::{self, SeqAccess, Unexpected};
+use serde::ser::SerializeTuple;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// The tag preceding an IPv4 address.
+const IPV4_TAG: u8 = 4;
+
+/// The tag preceding an IPv6 address.
+const IPV6_TAG: u8 = 6;
+
+/// Serializes the tag and address bytes of an IP address as tuple elements.
+fn serialize_ip<S>(tuple: &mut S, ip: &IpAddr) -> Result<(), S::Error>
+where
+    S: SerializeTuple,
+{
+    match ip {
+        IpAddr::V4(ip) => {
+            tuple.serialize_element(&IPV4_TAG)?;
+            tuple.serialize_element(&ip.octets())
+        }
+        IpAddr::V6(ip) => {
+            tuple.serialize_element(&IPV6_TAG)?;
+            tuple.serialize_element(&ip.octets())
+        }
+    }
+}
+
+/// Reads the next required element of an address tuple, where `index` is the
+/// position of the element within the tuple.
+fn next_element<'de, A, T>(seq: &mut A, index: usize) -> Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: de::Deserialize<'de>,
+{
+    seq.next_element()?
+        .ok_or_else(|| de::Error::invalid_length(index, &"a fixed-layout network address"))
+}
+
+/// Deserializes the tag and address bytes of an IP address from the first two
+/// elements of an address tuple.
+fn deserialize_ip<'de, A>(seq: &mut A) -> Result<IpAddr, A::Error>
+where
+    A: SeqAccess<'de>,
+{
+    let tag: u8 = next_element(seq, 0)?;
+
+    match tag {
+        IPV4_TAG => Ok(Ipv4Addr::from(next_element::<_, [u8; 4]>(seq, 1)?).into()),
+        IPV6_TAG => Ok(Ipv6Addr::from(next_element::<_, [u8; 16]>(seq, 1)?).into()),
+        _ => Err(de::Error::invalid_value(
+            Unexpected::Unsigned(tag.into()),
+            &"an IP address tag of 4 or 6",
+        )),
+    }
+}
+
+/// Fixed-layout serialization of [`IpAddr`].
+pub mod ip_addr {
+    use serde::de::{SeqAccess, Visitor};
+    use serde::ser::SerializeTuple;
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+    use std::net::IpAddr;
+
+    /// Serializes an IP address as a tag followed by its address bytes.
+    pub fn serialize<S>(value: &IpAddr, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(2)?;
+        super::serialize_ip(&mut tuple, value)?;
+        tuple.end()
+    }
+
+    /// Deserializes an IP address from a tag followed by its address bytes.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<IpAddr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(2, IpAddrVisitor)
+    }
+
+    /// Visits an IP address.
+    struct IpAddrVisitor;
+
+    impl<'de> Visitor<'de> for IpAddrVisitor {
+        type Value = IpAddr;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a tagged IP address")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            super::deserialize_ip(&mut seq)
+        }
+    }
+}
+
+/// Fixed-layout serialization of [`SocketAddr`](std::net::SocketAddr).
+pub mod socket_addr {
+    use serde::de::{SeqAccess, Visitor};
+    use serde::ser::SerializeTuple;
+    use serde::{Deserializer, Serializer};
+    use std::fmt;
+    use std::net::SocketAddr;
+
+    /// Serializes a socket address as a tag, its address bytes, and its port.
+    pub fn serialize<S>(value: &SocketAddr, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut tuple = serializer.serialize_tuple(3)?;
+        super::serialize_ip(&mut tuple, &value.ip())?;
+        tuple.serialize_element(&value.port())?;
+        tuple.end()
+    }
+
+    /// Deserializes a socket address from a tag, its address bytes, and its
+    /// port.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SocketAddr, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_tuple(3, SocketAddrVisitor)
+    }
+
+    /// Visits a socket address.
+    struct SocketAddrVisitor;
+
+    impl<'de> Visitor<'de> for SocketAddrVisitor {
+        type Value = SocketAddr;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a tagged socket address")
+        }
+
+        fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: SeqAccess<'de>,
+        {
+            let ip = super::deserialize_ip(&mut seq)?;
+            let port = super::next_element(&mut seq, 2)?;
+            Ok(SocketAddr::new(ip, port))
+        }
+    }
+}
@@ -0,0 +1,173 @@
+//! Packed presence bits for groups of options.
+
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A tuple of options, encoded with their presence bits packed together.
+///
+/// Every `Option` normally takes a discriminant byte of its own, which adds up
+/// in wide structs made mostly of optional fields. This writes the presence
+/// bits of a tuple of options together instead, followed by only the values
+/// that are present:
+///
+/// | Part   | Layout                                                           |
+/// | ------ | ---------------------------------------------------------------- |
+/// | Bitmap | one bit per option, eight to a byte, least significant bit first |
+/// | Values | each present value in order, with no discriminant                |
+///
+/// Ten options therefore take two bytes of bitmap rather than ten discriminant
+/// bytes. Unused bits in the last byte of the bitmap must be zero, so that
+/// every group of options has exactly one encoding. Tuples of up to 16 options
+/// are supported.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use unbin::OptionBitmap;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Settings {
+///     name: String,
+///     limits: OptionBitmap<(Option<u32>, Option<u32>, Option<u16>)>,
+/// }
+/// ```
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct OptionBitmap<T>(pub T);
+
+/// Returns the number of bytes in the bitmap for `len` options.
+const fn bitmap_len(len: usize) -> usize {
+    len.div_ceil(8)
+}
+
+/// Visits a bitmap of options, where `T` is the tuple of options.
+struct OptionBitmapVisitor<T>(PhantomData<T>);
+
+/// Reads the next present value, where `index` is its position within the
+/// tuple of bitmap bytes and values.
+fn next_present<'de, A, T, V>(seq: &mut A, index: &mut usize, visitor: &V) -> Result<T, A::Error>
+where
+    A: SeqAccess<'de>,
+    T: Deserialize<'de>,
+    V: Visitor<'de>,
+{
+    let value = seq
+        .next_element()?
+        .ok_or_else(|| de::Error::invalid_length(*index, visitor))?;
+    *index += 1;
+    Ok(value)
+}
+
+/// Implements serialization for a bitmap of `$len` options, with the given
+/// type parameters and tuple indices.
+macro_rules! impl_option_bitmap {
+    ($len:literal => $($ty:ident $index:tt)+) => {
+        impl<$($ty),+> Serialize for OptionBitmap<($(Option<$ty>,)+)>
+        where
+            $($ty: Serialize,)+
+        {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                let mut bitmap = [0u8; bitmap_len($len)];
+                let mut present = 0;
+
+                $(
+                    if self.0.$index.is_some() {
+                        bitmap[$index / 8] |= 1 << ($index % 8);
+                        present += 1;
+                    }
+                )+
+
+                let mut tuple = serializer.serialize_tuple(bitmap.len() + present)?;
+
+                for byte in &bitmap {
+                    tuple.serialize_element(byte)?;
+                }
+
+                $(
+                    if let Some(value) = &self.0.$index {
+                        tuple.serialize_element(value)?;
+                    }
+                )+
+
+                tuple.end()
+            }
+        }
+
+        impl<'de, $($ty),+> Deserialize<'de> for OptionBitmap<($(Option<$ty>,)+)>
+        where
+            $($ty: Deserialize<'de>,)+
+        {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                // the most elements there can be, with every option present
+                let max_len = bitmap_len($len) + $len;
+                let visitor = OptionBitmapVisitor::<($(Option<$ty>,)+)>(PhantomData);
+                deserializer.deserialize_tuple(max_len, visitor)
+            }
+        }
+
+        impl<'de, $($ty),+> Visitor<'de> for OptionBitmapVisitor<($(Option<$ty>,)+)>
+        where
+            $($ty: Deserialize<'de>,)+
+        {
+            type Value = OptionBitmap<($(Option<$ty>,)+)>;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                write!(formatter, "a bitmap of {} options", $len)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let mut bitmap = [0u8; bitmap_len($len)];
+                let mut index = 0;
+
+                for byte in &mut bitmap {
+                    *byte = next_present(&mut seq, &mut index, &self)?;
+                }
+
+                // widened so that a full last byte shifts out entirely
+                let unused = bitmap[bitmap.len() - 1] as u16 >> ($len - (bitmap.len() - 1) * 8);
+
+                if unused != 0 {
+                    return Err(de::Error::invalid_value(
+                        Unexpected::Bytes(&bitmap),
+                        &"a bitmap with its unused bits clear",
+                    ));
+                }
+
+                Ok(OptionBitmap(($(
+                    if bitmap[$index / 8] & (1 << ($index % 8)) != 0 {
+                        Some(next_present::<_, $ty, _>(&mut seq, &mut index, &self)?)
+                    } else {
+                        None
+                    },
+                )+)))
+            }
+        }
+    };
+}
+
+impl_option_bitmap!(1 => T0 0);
+impl_option_bitmap!(2 => T0 0 T1 1);
+impl_option_bitmap!(3 => T0 0 T1 1 T2 2);
+impl_option_bitmap!(4 => T0 0 T1 1 T2 2 T3 3);
+impl_option_bitmap!(5 => T0 0 T1 1 T2 2 T3 3 T4 4);
+impl_option_bitmap!(6 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5);
+impl_option_bitmap!(7 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6);
+impl_option_bitmap!(8 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7);
+impl_option_bitmap!(9 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8);
+impl_option_bitmap!(10 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9);
+impl_option_bitmap!(11 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9 T10 10);
+impl_option_bitmap!(12 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9 T10 10 T11 11);
+impl_option_bitmap!(13 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9 T10 10 T11 11 T12 12);
+impl_option_bitmap!(14 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9 T10 10 T11 11 T12 12 T13 13);
+impl_option_bitmap!(15 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9 T10 10 T11 11 T12 12 T13 13 T14 14);
+impl_option_bitmap!(16 => T0 0 T1 1 T2 2 T3 3 T4 4 T5 5 T6 6 T7 7 T8 8 T9 9 T10 10 T11 11 T12 12 T13 13 T14 14 T15 15);
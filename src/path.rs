@@ -0,0 +1,88 @@
+//! Pinned serialization of filesystem paths.
+//!
+//! A path is encoded exactly as a string: its length, in the configured
+//! [`LengthEncoding`](crate::LengthEncoding), followed by its UTF-8 bytes.
+//! This is the same layout serde gives [`Path`] and [`PathBuf`] on their own
+//! for valid UTF-8 paths, so the two are interchangeable on the wire, but it
+//! is pinned here rather than left to serde. Paths are written as they are,
+//! without normalizing separators or resolving components, so a path written
+//! on Windows decodes with its backslashes intact elsewhere.
+//!
+//! Paths that aren't valid UTF-8, which some platforms allow, have no string
+//! encoding. This module fails to serialize them with a custom error, so that
+//! no path is ever silently altered. The [`lossy`] module writes them with
+//! each invalid sequence replaced by U+FFFD instead, as
+//! [`Path::to_string_lossy`] does, at the cost of the decoded path differing
+//! from the original.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use std::path::PathBuf;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct IndexEntry {
+//!     #[serde(with = "unbin::path")]
+//!     path: PathBuf,
+//!     size: u64,
+//! }
+//!
+//! let entry = IndexEntry {
+//!     path: PathBuf::from("src/lib.rs"),
+//!     size: 1024,
+//! };
+//! let serialized = unbin::serialize(&entry).unwrap();
+//! assert_eq!(serialized[..2], [1, 10]);
+//! assert_eq!(&serialized[2..12], b"src/lib.rs");
+//! ```
+
+use serde::ser::Error;
+use serde::{Deserialize, Deserializer, Serializer};
+use std::path::{Path, PathBuf};
+
+/// Serializes a path as a string, failing if it isn't valid UTF-8.
+pub fn serialize<S>(value: &Path, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    match value.to_str() {
+        Some(path) => serializer.serialize_str(path),
+        None => Err(S::Error::custom(format_args!(
+            "path `{}` is not valid UTF-8",
+            value.display()
+        ))),
+    }
+}
+
+/// Deserializes a path from a string.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    String::deserialize(deserializer).map(PathBuf::from)
+}
+
+/// Serialization of paths that replaces invalid UTF-8 rather than failing.
+///
+/// The layout is the same as the parent module's, and valid UTF-8 paths are
+/// encoded identically, so the two can be mixed freely.
+pub mod lossy {
+    use serde::{Deserializer, Serializer};
+    use std::path::{Path, PathBuf};
+
+    /// Serializes a path as a string, replacing each invalid UTF-8 sequence
+    /// with U+FFFD.
+    pub fn serialize<S>(value: &Path, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_str(&value.to_string_lossy())
+    }
+
+    /// Deserializes a path from a string.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<PathBuf, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::deserialize(deserializer)
+    }
+}
@@ -1,9 +1,10 @@
 //! I/O reading.
 
 use crate::util::*;
-use crate::{Error, Result};
-use serde::de::Visitor;
-use std::io::{self, Write};
+use crate::{Error, Result, ValueType};
+use serde::de::{Expected, Unexpected, Visitor};
+use std::fmt;
+use std::io;
 
 /// Trait to allow reading bytes. Similar to [`std::io::Read`], but also
 /// supports reading from byte arrays.
@@ -11,13 +12,15 @@ pub trait Read<'de> {
     /// Reads the exact number of bytes required to fill buffer.
     fn read_exact(&mut self, buf: &mut [u8]) -> Result<()>;
 
-    /// Reads a string from the reader and passes it to the visitor.
-    fn visit_str<V>(&mut self, visitor: V) -> Result<V::Value>
+    /// Reads a string of `len` bytes from the reader and passes it to the
+    /// visitor.
+    fn visit_str<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>;
 
-    /// Reads a byte slice from the reader and passes it to the visitor.
-    fn visit_bytes<V>(&mut self, visitor: V) -> Result<V::Value>
+    /// Reads a byte slice of `len` bytes from the reader and passes it to the
+    /// visitor.
+    fn visit_bytes<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>;
 
@@ -28,6 +31,12 @@ pub trait Read<'de> {
         Ok(bytes)
     }
 
+    /// Reads a single byte. Readers can override this to avoid going through
+    /// a one-byte buffer.
+    fn read_u8(&mut self) -> crate::Result<u8> {
+        Ok(self.read_n_array::<1>()?[0])
+    }
+
     /// Reads `n` bytes from the underlying reader into a `Vec<u8>`.
     fn read_n_vec(&mut self, n: usize) -> crate::Result<Vec<u8>> {
         let mut bytes = vec![0; n];
@@ -35,23 +44,27 @@ pub trait Read<'de> {
         Ok(bytes)
     }
 
+    /// Returns the number of bytes left to read, if known. This is only used as
+    /// a hint, to avoid trusting encoded lengths that the remaining input
+    /// could not possibly satisfy.
+    fn remaining_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the next `n` bytes without consuming them, or `None` if fewer
+    /// than `n` remain or the reader can't look ahead. Only readers holding
+    /// their input in memory, such as [`BytesReader`], can look ahead.
+    fn peek(&self, _n: usize) -> Option<&[u8]> {
+        None
+    }
+
     /// Reads and returns a dynamically sized collection of bytes, assuming they
     /// are encoded with a small length.
     fn read_bytes_with_small_len(&mut self) -> crate::Result<Vec<u8>> {
-        let len = self.read_n_array::<1>()?;
-        let decoded_len = decode_len_small(len[0]);
+        let len = self.read_u8()?;
+        let decoded_len = decode_len_small(len);
         self.read_n_vec(decoded_len)
     }
-
-    /// Reads and returns a dynamically sized collection of bytes, assuming they
-    /// are encoded with a large length.
-    fn read_bytes_with_large_len(&mut self) -> crate::Result<Vec<u8>> {
-        let len1 = self.read_n_array::<1>()?;
-        let decoded_len1 = decode_len_small(len1[0]);
-        let len2 = self.read_n_vec(decoded_len1)?;
-        let decoded_len2 = decode_len_large(&len2);
-        self.read_n_vec(decoded_len2)
-    }
 }
 
 impl<'de, R> Read<'de> for R
@@ -59,24 +72,97 @@ where
     R: io::Read,
 {
     fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
-        Ok(io::Read::read_exact(self, buf)?)
+        io::Read::read_exact(self, buf).map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => Error::UnexpectedEof,
+            _ => Error::IoError(err),
+        })
+    }
+
+    fn read_n_vec(&mut self, n: usize) -> Result<Vec<u8>> {
+        // reading into spare capacity skips zero-filling the buffer first,
+        // which `read_exact` would need, and `read_to_end` grows it past the
+        // initial capacity only as bytes arrive
+        let mut bytes = Vec::with_capacity(n.min(MAX_READ_PREALLOC));
+        io::Read::read_to_end(&mut io::Read::take(self, n as u64), &mut bytes)?;
+
+        if bytes.len() < n {
+            return Err(Error::UnexpectedEof);
+        }
+
+        Ok(bytes)
     }
 
-    fn visit_str<V>(&mut self, visitor: V) -> Result<V::Value>
+    fn visit_str<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.read_bytes_with_large_len()?;
-        let string = std::str::from_utf8(&bytes)?;
-        visitor.visit_str(string)
+        // the string can't be borrowed from the reader, so hand the visitor
+        // ownership of the buffer in case it can use it without copying
+        let bytes = self.read_n_vec(len)?;
+        let string = String::from_utf8(bytes).map_err(|err| err.utf8_error())?;
+        Ok(visitor.visit_string::<OwnedVisitError>(string)?)
     }
 
-    fn visit_bytes<V>(&mut self, visitor: V) -> Result<V::Value>
+    fn visit_bytes<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let bytes = self.read_bytes_with_large_len()?;
-        visitor.visit_bytes(&bytes)
+        let bytes = self.read_n_vec(len)?;
+        Ok(visitor.visit_byte_buf::<OwnedVisitError>(bytes)?)
+    }
+}
+
+/// The error type given to visitors handed owned data by a reader that can't
+/// lend out borrowed data. A visitor that rejects an owned string or byte array
+/// but describes itself as expecting borrowed data, as serde's visitors for
+/// `&str` and `&[u8]` do, is reported as [`Error::CannotBorrowFromReader`]
+/// rather than a generic type mismatch.
+#[derive(Debug)]
+enum OwnedVisitError {
+    /// The visitor requires borrowed data of the given type.
+    CannotBorrow(ValueType),
+    /// Any other error.
+    Other(Error),
+}
+
+impl fmt::Display for OwnedVisitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::CannotBorrow(ty) => Error::CannotBorrowFromReader { ty: *ty }.fmt(f),
+            Self::Other(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for OwnedVisitError {}
+
+impl serde::de::Error for OwnedVisitError {
+    fn custom<T>(msg: T) -> Self
+    where
+        T: fmt::Display,
+    {
+        Self::Other(Error::custom(msg))
+    }
+
+    fn invalid_type(unexp: Unexpected, exp: &dyn Expected) -> Self {
+        match unexp {
+            Unexpected::Str(_) if expected_starts_with(exp, "a borrowed ") => {
+                Self::CannotBorrow(ValueType::Str)
+            }
+            Unexpected::Bytes(_) if expected_starts_with(exp, "a borrowed ") => {
+                Self::CannotBorrow(ValueType::Bytes)
+            }
+            _ => Self::Other(Error::invalid_type(unexp, exp)),
+        }
+    }
+}
+
+impl From<OwnedVisitError> for Error {
+    fn from(err: OwnedVisitError) -> Self {
+        match err {
+            OwnedVisitError::CannotBorrow(ty) => Self::CannotBorrowFromReader { ty },
+            OwnedVisitError::Other(err) => err,
+        }
     }
 }
 
@@ -85,16 +171,22 @@ where
 pub struct BytesReader<'a> {
     /// The byte buffer.
     bytes: &'a [u8],
+    /// The number of bytes consumed so far.
+    position: usize,
 }
 
 impl<'a> BytesReader<'a> {
     /// Constructs a new reader from a byte array.
     pub fn new(bytes: &'a [u8]) -> Self {
-        Self { bytes }
+        Self { bytes, position: 0 }
     }
 
-    /// Returns the full buffer as a slice.
-    #[allow(dead_code)]
+    /// Returns the number of bytes consumed so far.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the remaining, unconsumed buffer as a slice.
     pub fn as_slice(&self) -> &[u8] {
         self.bytes
     }
@@ -104,6 +196,7 @@ impl<'a> BytesReader<'a> {
         if len <= self.bytes.len() {
             let (first, rest) = self.bytes.split_at(len);
             self.bytes = rest;
+            self.position += len;
             Ok(first)
         } else {
             Err(Error::UnexpectedEof)
@@ -115,34 +208,248 @@ impl<'de, 'a> Read<'de> for BytesReader<'a>
 where
     'a: 'de,
 {
-    fn read_exact(&mut self, mut buf: &mut [u8]) -> Result<()> {
-        let num_bytes = buf.write(self.bytes)?;
-        self.bytes = &self.bytes[num_bytes..];
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let bytes = self.read_bytes(buf.len())?;
+        buf.copy_from_slice(bytes);
         Ok(())
     }
 
-    fn visit_str<V>(&mut self, visitor: V) -> Result<V::Value>
+    fn read_u8(&mut self) -> Result<u8> {
+        let (&byte, rest) = self.bytes.split_first().ok_or(Error::UnexpectedEof)?;
+        self.bytes = rest;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn visit_str<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let len1 = self.read_n_array::<1>()?;
-        let decoded_len1 = decode_len_small(len1[0]);
-        let len2 = self.read_n_vec(decoded_len1)?;
-        let decoded_len2 = decode_len_large(&len2);
-        let bytes = self.read_bytes(decoded_len2)?;
+        let bytes = self.read_bytes(len)?;
         let string = std::str::from_utf8(bytes)?;
         visitor.visit_borrowed_str(string)
     }
 
-    fn visit_bytes<V>(&mut self, visitor: V) -> Result<V::Value>
+    fn visit_bytes<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
     where
         V: Visitor<'de>,
     {
-        let len1 = self.read_n_array::<1>()?;
-        let decoded_len1 = decode_len_small(len1[0]);
-        let len2 = self.read_n_vec(decoded_len1)?;
-        let decoded_len2 = decode_len_large(&len2);
-        let bytes = self.read_bytes(decoded_len2)?;
+        let bytes = self.read_bytes(len)?;
         visitor.visit_borrowed_bytes(bytes)
     }
+
+    fn remaining_len(&self) -> Option<usize> {
+        Some(self.bytes.len())
+    }
+
+    fn peek(&self, n: usize) -> Option<&[u8]> {
+        self.bytes.get(..n)
+    }
+}
+
+/// Reads the given stream to its end, appending its contents to `bytes`, and
+/// failing with [`Error::InputLimitExceeded`] if it is longer than `limit`
+/// bytes, if given.
+pub(crate) fn read_to_end<R>(mut reader: R, bytes: &mut Vec<u8>, limit: Option<usize>) -> Result<()>
+where
+    R: io::Read,
+{
+    let Some(limit) = limit else {
+        io::Read::read_to_end(&mut reader, bytes)?;
+        return Ok(());
+    };
+
+    // one byte past the limit is enough to tell that it was exceeded
+    let max_len = (limit as u64).saturating_add(1);
+    let len = io::Read::read_to_end(&mut io::Read::take(reader, max_len), bytes)?;
+
+    if len > limit {
+        return Err(Error::InputLimitExceeded { limit });
+    }
+
+    Ok(())
+}
+
+/// An owned buffer holding the contents of an [`std::io::Read`]er, read all at
+/// once, so that values can be decoded from it with borrowing.
+///
+/// Decoding straight from an `io::Read`er can't lend out borrowed data, which
+/// forces owned types. Decoding from [`BufferedReader::reader`] instead
+/// borrows from the buffer, for as long as the buffer is alive.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct BufferedReader {
+    /// The contents of the stream.
+    bytes: Vec<u8>,
+}
+
+impl BufferedReader {
+    /// Reads the given stream to its end.
+    pub fn new<R>(reader: R) -> Result<Self>
+    where
+        R: io::Read,
+    {
+        let mut bytes = Vec::new();
+        read_to_end(reader, &mut bytes, None)?;
+        Ok(Self { bytes })
+    }
+
+    /// Reads the given stream to its end, failing with
+    /// [`Error::InputLimitExceeded`] if it is longer than `limit` bytes.
+    pub fn with_limit<R>(reader: R, limit: usize) -> Result<Self>
+    where
+        R: io::Read,
+    {
+        let mut bytes = Vec::new();
+        read_to_end(reader, &mut bytes, Some(limit))?;
+        Ok(Self { bytes })
+    }
+
+    /// Returns the buffered contents of the stream as a slice.
+    pub fn as_slice(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Returns a reader over the buffered contents of the stream, which values
+    /// can be decoded from with borrowing.
+    pub fn reader(&self) -> BytesReader<'_> {
+        BytesReader::new(&self.bytes)
+    }
+
+    /// Unwraps and returns the buffered contents of the stream.
+    pub fn into_inner(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// A [`Read`]-able sequence of byte arrays, read one after another as if they
+/// were a single contiguous array.
+///
+/// Strings and byte arrays are borrowed from the input whenever they lie
+/// entirely within a single chunk. When a value straddles a chunk boundary, it
+/// is copied into an owned buffer instead, so visitors that require borrowed
+/// data (e.g. those of `&str` and `&[u8]`) fail with
+/// [`Error::CannotBorrowFromReader`] for such values, while those accepting
+/// owned data (e.g. those of `Cow<str>` and `String`) still succeed.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ChainedBytesReader<'a> {
+    /// The chunks, the first unconsumed one of which may be partially
+    /// consumed.
+    chunks: Vec<&'a [u8]>,
+    /// The index of the first unconsumed chunk.
+    index: usize,
+}
+
+impl<'a> ChainedBytesReader<'a> {
+    /// Constructs a new reader from a sequence of byte arrays.
+    pub fn new<I>(chunks: I) -> Self
+    where
+        I: IntoIterator<Item = &'a [u8]>,
+    {
+        Self {
+            chunks: chunks.into_iter().collect(),
+            index: 0,
+        }
+    }
+
+    /// Returns the unconsumed part of the current chunk, skipping past any
+    /// exhausted chunks, or `None` if every chunk has been consumed.
+    fn current_chunk(&mut self) -> Option<&'a [u8]> {
+        while let Some(chunk) = self.chunks.get(self.index) {
+            if !chunk.is_empty() {
+                return Some(chunk);
+            }
+
+            self.index += 1;
+        }
+
+        None
+    }
+
+    /// Reads and returns a slice containing the requested number of bytes if
+    /// they lie within a single chunk, or returns `None` without consuming
+    /// anything otherwise.
+    pub fn read_borrowed_bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if len == 0 {
+            return Some(&[]);
+        }
+
+        let chunk = self.current_chunk()?;
+
+        if len <= chunk.len() {
+            let (first, rest) = chunk.split_at(len);
+            self.chunks[self.index] = rest;
+            Some(first)
+        } else {
+            None
+        }
+    }
+}
+
+impl<'de, 'a> Read<'de> for ChainedBytesReader<'a>
+where
+    'a: 'de,
+{
+    fn read_exact(&mut self, buf: &mut [u8]) -> Result<()> {
+        let mut filled = 0;
+
+        while filled < buf.len() {
+            let chunk = self.current_chunk().ok_or(Error::UnexpectedEof)?;
+            let num_bytes = chunk.len().min(buf.len() - filled);
+            buf[filled..filled + num_bytes].copy_from_slice(&chunk[..num_bytes]);
+            self.chunks[self.index] = &chunk[num_bytes..];
+            filled += num_bytes;
+        }
+
+        Ok(())
+    }
+
+    fn visit_str<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_borrowed_bytes(len) {
+            Some(bytes) => {
+                let string = std::str::from_utf8(bytes)?;
+                visitor.visit_borrowed_str(string)
+            }
+            None => {
+                let bytes = self.read_n_vec(len)?;
+                let string = String::from_utf8(bytes).map_err(|err| err.utf8_error())?;
+                Ok(visitor.visit_string::<OwnedVisitError>(string)?)
+            }
+        }
+    }
+
+    fn visit_bytes<V>(&mut self, len: usize, visitor: V) -> Result<V::Value>
+    where
+        V: Visitor<'de>,
+    {
+        match self.read_borrowed_bytes(len) {
+            Some(bytes) => visitor.visit_borrowed_bytes(bytes),
+            None => {
+                let bytes = self.read_n_vec(len)?;
+                Ok(visitor.visit_byte_buf::<OwnedVisitError>(bytes)?)
+            }
+        }
+    }
+
+    fn remaining_len(&self) -> Option<usize> {
+        Some(
+            self.chunks[self.index..]
+                .iter()
+                .map(|chunk| chunk.len())
+                .sum(),
+        )
+    }
+
+    /// Only bytes lying within a single chunk can be peeked at.
+    fn peek(&self, n: usize) -> Option<&[u8]> {
+        match self.chunks[self.index..]
+            .iter()
+            .find(|chunk| !chunk.is_empty())
+        {
+            Some(chunk) => chunk.get(..n),
+            None => (n == 0).then_some(&[]),
+        }
+    }
 }
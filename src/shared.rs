@@ -0,0 +1,215 @@
+//! Deduplicated serialization of shared [`Arc`]s.
+//!
+//! serde serializes an `Arc<T>` as the `T` it points to, so a value reachable
+//! through several clones of the same `Arc` is written out once per clone, and
+//! decodes as that many separate allocations. Fields using this module are
+//! encoded as a tag followed by either the value or a back-reference:
+//!
+//! | Tag | Followed by                                                  |
+//! | --- | ------------------------------------------------------------ |
+//! | `0` | the value, which is assigned the next index in the table     |
+//! | `1` | a `u32` index of a value already written, sharing its `Arc` |
+//!
+//! Back-references are only written within [`serialize_shared`], which tracks
+//! the `Arc`s written so far, and can only be decoded within
+//! [`deserialize_shared`], which rebuilds the sharing between them. Elsewhere,
+//! every `Arc` is written in full with tag `0`.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use std::sync::Arc;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Node {
+//!     #[serde(with = "unbin::shared")]
+//!     name: Arc<String>,
+//! }
+//! ```
+//!
+//! [`serialize_shared`]: crate::serialize_shared
+//! [`deserialize_shared`]: crate::deserialize_shared
+
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::any::Any;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+/// The tag preceding a value written in full.
+const VALUE_TAG: u8 = 0;
+
+/// The tag preceding a back-reference to a value already written.
+const REFERENCE_TAG: u8 = 1;
+
+thread_local! {
+    /// The indices of the `Arc`s written so far, keyed by address, while
+    /// serializing with deduplication.
+    static WRITTEN: RefCell<Option<HashMap<*const (), u32>>> = const { RefCell::new(None) };
+
+    /// The `Arc`s read so far, in index order, while deserializing with
+    /// deduplication.
+    static READ: RefCell<Option<Vec<Box<dyn Any>>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` with deduplication enabled for any `Arc`s it serializes through
+/// this module. Scopes may nest, each with its own table.
+pub(crate) fn with_serialize_table<T>(f: impl FnOnce() -> T) -> T {
+    let outer = WRITTEN.with(|table| table.replace(Some(HashMap::new())));
+    let value = f();
+    WRITTEN.with(|table| table.replace(outer));
+    value
+}
+
+/// Runs `f` with deduplication disabled, so that any `Arc`s it serializes
+/// through this module are written in full and not recorded in the table of
+/// an enclosing scope. This is for encodings made on the side, such as sort
+/// keys, which never reach the output.
+pub(crate) fn without_serialize_table<T>(f: impl FnOnce() -> T) -> T {
+    let outer = WRITTEN.with(|table| table.replace(None));
+    let value = f();
+    WRITTEN.with(|table| table.replace(outer));
+    value
+}
+
+/// Runs `f` with deduplication enabled for any `Arc`s it deserializes through
+/// this module. Scopes may nest, each with its own table.
+pub(crate) fn with_deserialize_table<T>(f: impl FnOnce() -> T) -> T {
+    let outer = READ.with(|table| table.replace(Some(Vec::new())));
+    let value = f();
+    READ.with(|table| table.replace(outer));
+    value
+}
+
+/// Serializes a shared value, writing only a back-reference if the same `Arc`
+/// has already been written within [`serialize_shared`].
+///
+/// [`serialize_shared`]: crate::serialize_shared
+pub fn serialize<S, T>(value: &Arc<T>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+    T: Serialize,
+{
+    let address = Arc::as_ptr(value) as *const ();
+    let index = WRITTEN.with(|table| {
+        table
+            .borrow()
+            .as_ref()
+            .and_then(|table| table.get(&address).copied())
+    });
+
+    let mut tuple = serializer.serialize_tuple(2)?;
+
+    match index {
+        Some(index) => {
+            tuple.serialize_element(&REFERENCE_TAG)?;
+            tuple.serialize_element(&index)?;
+        }
+        None => {
+            tuple.serialize_element(&VALUE_TAG)?;
+            tuple.serialize_element(&**value)?;
+
+            // indices are assigned once the value is fully written, matching
+            // the order in which the decoder finishes reading them
+            WRITTEN.with(|table| {
+                if let Some(table) = table.borrow_mut().as_mut() {
+                    let index = table.len() as u32;
+                    table.insert(address, index);
+                }
+            });
+        }
+    }
+
+    tuple.end()
+}
+
+/// Deserializes a shared value, resolving back-references to `Arc`s already
+/// read within [`deserialize_shared`].
+///
+/// [`deserialize_shared`]: crate::deserialize_shared
+pub fn deserialize<'de, D, T>(deserializer: D) -> Result<Arc<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de> + 'static,
+{
+    deserializer.deserialize_tuple(2, SharedVisitor(PhantomData))
+}
+
+/// Visits a shared value.
+struct SharedVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for SharedVisitor<T>
+where
+    T: Deserialize<'de> + 'static,
+{
+    type Value = Arc<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a tagged shared value")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let tag: u8 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+        match tag {
+            VALUE_TAG => {
+                let value = seq
+                    .next_element::<T>()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                let value = Arc::new(value);
+
+                READ.with(|table| {
+                    if let Some(table) = table.borrow_mut().as_mut() {
+                        table.push(Box::new(Arc::clone(&value)));
+                    }
+                });
+
+                Ok(value)
+            }
+            REFERENCE_TAG => {
+                let index: u32 = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                resolve(index)
+            }
+            _ => Err(de::Error::invalid_value(
+                Unexpected::Unsigned(tag.into()),
+                &"a shared value tag of 0 or 1",
+            )),
+        }
+    }
+}
+
+/// Looks up the `Arc` already read with the given index.
+fn resolve<T, E>(index: u32) -> Result<Arc<T>, E>
+where
+    T: 'static,
+    E: de::Error,
+{
+    READ.with(|table| {
+        let table = table.borrow();
+        let table = table.as_ref().ok_or_else(|| {
+            E::custom("shared value back-reference outside of `deserialize_shared`")
+        })?;
+        let value = table.get(index as usize).ok_or_else(|| {
+            E::invalid_value(
+                Unexpected::Unsigned(index.into()),
+                &"the index of a shared value already read",
+            )
+        })?;
+        value.downcast_ref::<Arc<T>>().cloned().ok_or_else(|| {
+            E::invalid_value(
+                Unexpected::Unsigned(index.into()),
+                &"the index of a shared value of the same type",
+            )
+        })
+    })
+}
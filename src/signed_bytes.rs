@@ -0,0 +1,84 @@
+//! Block serialization of signed byte buffers.
+//!
+//! By default, `Vec<i8>` is serialized as a sequence, one element at a time.
+//! Since `i8` and `u8` are bit-identical, this module serializes the whole
+//! buffer as a single block of bytes instead. In the default configuration,
+//! neither tagged nor framed, the encoded bytes are identical to those of the
+//! sequence encoding, so the two are interchangeable on the wire. Tagged mode
+//! writes a tag per element of a sequence but one for a block, and framed
+//! mode ends sequences with a sentinel, so there the two differ.
+//!
+//! Sequences of `i8` are still accepted when deserializing, for formats that
+//! hand them over one element at a time.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Samples {
+//!     #[serde(with = "unbin::signed_bytes")]
+//!     values: Vec<i8>,
+//! }
+//! ```
+
+use crate::util::MAX_READ_PREALLOC;
+use serde::de::{SeqAccess, Visitor};
+use serde::{Deserializer, Serializer};
+use std::fmt;
+
+/// Serializes a signed byte buffer as a single block of bytes.
+pub fn serialize<S>(value: &[i8], serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let bytes = value.iter().map(|&b| b as u8).collect::<Vec<_>>();
+    serializer.serialize_bytes(&bytes)
+}
+
+/// Deserializes a signed byte buffer from a single block of bytes, or from a
+/// sequence of signed bytes.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Vec<i8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_byte_buf(SignedBytesVisitor)
+}
+
+/// Visits a block of bytes and reinterprets it as signed bytes, or collects a
+/// sequence of signed bytes.
+struct SignedBytesVisitor;
+
+impl<'de> Visitor<'de> for SignedBytesVisitor {
+    type Value = Vec<i8>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a byte buffer or sequence of signed bytes")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(v.iter().map(|&b| b as i8).collect())
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: serde::de::Error,
+    {
+        Ok(v.into_iter().map(|b| b as i8).collect())
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut values = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(MAX_READ_PREALLOC));
+
+        while let Some(value) = seq.next_element()? {
+            values.push(value);
+        }
+
+        Ok(values)
+    }
+}
@@ -0,0 +1,195 @@
+//! Packed presence bits for sequences of options.
+
+use crate::util::MAX_READ_PREALLOC;
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::marker::PhantomData;
+
+/// A vector of options, encoded with their presence bits packed together.
+///
+/// A `Vec<Option<T>>` normally writes a discriminant byte for every element,
+/// which dominates the encoding of sparse data made mostly of `None`s. This
+/// writes the presence bits of all the elements first instead, followed by
+/// only the values that are present:
+///
+/// | Part   | Layout                                                            |
+/// | ------ | ----------------------------------------------------------------- |
+/// | Bitmap | a byte array of one bit per element and an end marker bit         |
+/// | Values | each present value in order, with no discriminant                 |
+///
+/// The bits are packed eight to a byte, least significant bit first, and the
+/// set bit following the last element's bit marks the end, recording the
+/// number of elements without a separate length. The marker must fall in the
+/// last byte of the bitmap, so that every vector has exactly one encoding. A
+/// hundred elements therefore take a thirteen-byte bitmap and its length,
+/// rather than a hundred discriminant bytes.
+///
+/// ```
+/// use serde::{Deserialize, Serialize};
+/// use unbin::SparseVec;
+///
+/// #[derive(Serialize, Deserialize)]
+/// struct Readings {
+///     samples: SparseVec<u16>,
+/// }
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SparseVec<T>(pub Vec<Option<T>>);
+
+/// The presence bitmap of a sparse vector, encoded as a byte array.
+struct Bitmap(Vec<u8>);
+
+impl Serialize for Bitmap {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(&self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Bitmap {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_byte_buf(BitmapVisitor)
+    }
+}
+
+/// Visits the presence bitmap of a sparse vector.
+struct BitmapVisitor;
+
+impl<'de> Visitor<'de> for BitmapVisitor {
+    type Value = Bitmap;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a presence bitmap")
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bitmap(v.to_vec()))
+    }
+
+    fn visit_byte_buf<E>(self, v: Vec<u8>) -> Result<Self::Value, E>
+    where
+        E: de::Error,
+    {
+        Ok(Bitmap(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::new();
+
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+
+        Ok(Bitmap(bytes))
+    }
+}
+
+impl<T> Serialize for SparseVec<T>
+where
+    T: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let len = self.0.len();
+        let mut bitmap = vec![0u8; len / 8 + 1];
+        let mut present = 0;
+
+        for (index, element) in self.0.iter().enumerate() {
+            if element.is_some() {
+                bitmap[index / 8] |= 1 << (index % 8);
+                present += 1;
+            }
+        }
+
+        bitmap[len / 8] |= 1 << (len % 8);
+
+        let mut tuple = serializer.serialize_tuple(1 + present)?;
+        tuple.serialize_element(&Bitmap(bitmap))?;
+
+        for value in self.0.iter().flatten() {
+            tuple.serialize_element(value)?;
+        }
+
+        tuple.end()
+    }
+}
+
+impl<'de, T> Deserialize<'de> for SparseVec<T>
+where
+    T: Deserialize<'de>,
+{
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // the number of values is only known once the bitmap has been read
+        deserializer.deserialize_tuple(usize::MAX, SparseVecVisitor(PhantomData))
+    }
+}
+
+/// Visits a sparse vector of options of `T`.
+struct SparseVecVisitor<T>(PhantomData<T>);
+
+impl<'de, T> Visitor<'de> for SparseVecVisitor<T>
+where
+    T: Deserialize<'de>,
+{
+    type Value = SparseVec<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sparse vector")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let Bitmap(bitmap) = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+
+        // the end marker is the highest set bit, in the last byte
+        let last = match bitmap.last() {
+            Some(&last) if last != 0 => last,
+            _ => {
+                return Err(de::Error::invalid_value(
+                    Unexpected::Bytes(&bitmap),
+                    &"a bitmap ending with its end marker",
+                ))
+            }
+        };
+        let len = (bitmap.len() - 1) * 8 + (7 - last.leading_zeros() as usize);
+        // the bitmap promises eight elements a byte, which may never follow
+        let mut elements = Vec::with_capacity(len.min(MAX_READ_PREALLOC));
+        let mut index = 1;
+
+        for element in 0..len {
+            if bitmap[element / 8] & (1 << (element % 8)) != 0 {
+                let value = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(index, &self))?;
+                elements.push(Some(value));
+                index += 1;
+            } else {
+                elements.push(None);
+            }
+        }
+
+        Ok(SparseVec(elements))
+    }
+}
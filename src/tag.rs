@@ -0,0 +1,112 @@
+//! Type tags for the self-describing mode.
+
+use crate::ValueType;
+
+/// The type tag written before every value in tagged mode, identifying how
+/// the bytes that follow are to be read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[repr(u8)]
+pub(crate) enum Tag {
+    /// A unit, unit struct or unit variant body, followed by nothing.
+    Unit = 0,
+    /// A `bool`.
+    Bool = 1,
+    /// An `i8`.
+    I8 = 2,
+    /// An `i16`.
+    I16 = 3,
+    /// An `i32`.
+    I32 = 4,
+    /// An `i64`.
+    I64 = 5,
+    /// An `i128`.
+    I128 = 6,
+    /// A `u8`.
+    U8 = 7,
+    /// A `u16`.
+    U16 = 8,
+    /// A `u32`.
+    U32 = 9,
+    /// A `u64`.
+    U64 = 10,
+    /// A `u128`.
+    U128 = 11,
+    /// An `f32`.
+    F32 = 12,
+    /// An `f64`.
+    F64 = 13,
+    /// A `char`.
+    Char = 14,
+    /// A string.
+    Str = 15,
+    /// A byte array.
+    Bytes = 16,
+    /// An option, followed by its discriminant and any value.
+    Option = 17,
+    /// A sequence, tuple, struct or tuple or struct variant body, followed by
+    /// its length and its elements.
+    Seq = 18,
+    /// A map, followed by its length and its entries.
+    Map = 19,
+    /// An enum, followed by its variant index and the variant's body.
+    Enum = 20,
+}
+
+impl Tag {
+    /// Every tag, indexed by its byte.
+    const ALL: [Self; 21] = [
+        Self::Unit,
+        Self::Bool,
+        Self::I8,
+        Self::I16,
+        Self::I32,
+        Self::I64,
+        Self::I128,
+        Self::U8,
+        Self::U16,
+        Self::U32,
+        Self::U64,
+        Self::U128,
+        Self::F32,
+        Self::F64,
+        Self::Char,
+        Self::Str,
+        Self::Bytes,
+        Self::Option,
+        Self::Seq,
+        Self::Map,
+        Self::Enum,
+    ];
+
+    /// Returns the tag with the given byte, if any.
+    pub(crate) fn from_byte(byte: u8) -> Option<Self> {
+        Self::ALL.get(byte as usize).copied()
+    }
+
+    /// Returns the type of value the tag introduces, for error reporting.
+    pub(crate) fn value_type(self) -> ValueType {
+        match self {
+            Self::Unit => ValueType::Unit,
+            Self::Bool => ValueType::Bool,
+            Self::I8 => ValueType::I8,
+            Self::I16 => ValueType::I16,
+            Self::I32 => ValueType::I32,
+            Self::I64 => ValueType::I64,
+            Self::I128 => ValueType::I128,
+            Self::U8 => ValueType::U8,
+            Self::U16 => ValueType::U16,
+            Self::U32 => ValueType::U32,
+            Self::U64 => ValueType::U64,
+            Self::U128 => ValueType::U128,
+            Self::F32 => ValueType::F32,
+            Self::F64 => ValueType::F64,
+            Self::Char => ValueType::Char,
+            Self::Str => ValueType::Str,
+            Self::Bytes => ValueType::Bytes,
+            Self::Option => ValueType::Option,
+            Self::Seq => ValueType::Seq,
+            Self::Map => ValueType::Map,
+            Self::Enum => ValueType::Enum,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Compact serialization of durations and points in time.
+//!
+//! serde's own representation of [`Duration`] is a struct of a `u64` number
+//! of seconds and a `u32` number of nanoseconds, taking 12 bytes here however
+//! short the duration. The modules here write both numbers as unsigned LEB128
+//! varints instead, so that typical durations take only a few bytes:
+//!
+//! | Type         | Layout                                                    |
+//! | ------------ | --------------------------------------------------------- |
+//! | `Duration`   | seconds (1 to 10 bytes), then nanoseconds (1 to 5 bytes)  |
+//! | `SystemTime` | the `Duration` since the Unix epoch, as above             |
+//!
+//! The varints are written byte by byte, so the layout is the same whatever
+//! [`IntEncoding`](crate::IntEncoding) the encoder is configured with.
+//! [`SystemTime`](std::time::SystemTime)s before the Unix epoch cannot be
+//! serialized.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use std::time::{Duration, SystemTime};
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Job {
+//!     #[serde(with = "unbin::time::duration")]
+//!     timeout: Duration,
+//!     #[serde(with = "unbin::time::system_time")]
+//!     created: SystemTime,
+//! }
+//! ```
+
+use crate::util::{decode_int_varint, encode_int_varint, MAX_INT_VARINT_BYTES};
+use serde::de::{self, SeqAccess, Unexpected, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserializer, Serializer};
+use std::fmt;
+use std::time::Duration;
+
+/// The maximum number of bytes in a serialized duration.
+const MAX_DURATION_BYTES: usize = (u64::BITS.div_ceil(7) + u32::BITS.div_ceil(7)) as usize;
+
+/// Serializes a duration as varint-encoded seconds and nanoseconds.
+fn serialize_duration<S>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    let mut secs = [0; MAX_INT_VARINT_BYTES];
+    let secs_len = encode_int_varint(value.as_secs().into(), &mut secs);
+    let mut nanos = [0; MAX_INT_VARINT_BYTES];
+    let nanos_len = encode_int_varint(value.subsec_nanos().into(), &mut nanos);
+
+    let mut tuple = serializer.serialize_tuple(secs_len + nanos_len)?;
+
+    for byte in secs[..secs_len].iter().chain(&nanos[..nanos_len]) {
+        tuple.serialize_element(byte)?;
+    }
+
+    tuple.end()
+}
+
+/// Deserializes a duration from varint-encoded seconds and nanoseconds.
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_tuple(MAX_DURATION_BYTES, DurationVisitor)
+}
+
+/// Reads a single varint of at most `bits` bits, one byte per tuple element,
+/// where `index` is the position of its first byte within the tuple.
+fn next_varint<'de, A>(seq: &mut A, index: &mut usize, bits: u32) -> Result<u128, A::Error>
+where
+    A: SeqAccess<'de>,
+{
+    let max_len = bits.div_ceil(7) as usize;
+    let mut encoded = [0; MAX_INT_VARINT_BYTES];
+    let mut len = 0;
+
+    loop {
+        let byte: u8 = seq
+            .next_element()?
+            .ok_or_else(|| de::Error::invalid_length(*index, &"a compact duration"))?;
+        encoded[len] = byte;
+        len += 1;
+        *index += 1;
+
+        if byte & 0x80 == 0 {
+            break;
+        }
+
+        if len == max_len {
+            return Err(de::Error::invalid_value(
+                Unexpected::Bytes(&encoded[..len]),
+                &"a varint that fits in the duration",
+            ));
+        }
+    }
+
+    decode_int_varint(&encoded[..len], bits).ok_or_else(|| {
+        de::Error::invalid_value(
+            Unexpected::Bytes(&encoded[..len]),
+            &"a varint that fits in the duration",
+        )
+    })
+}
+
+/// Visits a compact duration.
+struct DurationVisitor;
+
+impl<'de> Visitor<'de> for DurationVisitor {
+    type Value = Duration;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a compact duration")
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut index = 0;
+        let secs = next_varint(&mut seq, &mut index, u64::BITS)? as u64;
+        let nanos = next_varint(&mut seq, &mut index, u32::BITS)? as u32;
+
+        if nanos >= 1_000_000_000 {
+            return Err(de::Error::invalid_value(
+                Unexpected::Unsigned(nanos.into()),
+                &"fewer than 1,000,000,000 nanoseconds",
+            ));
+        }
+
+        Ok(Duration::new(secs, nanos))
+    }
+}
+
+/// Compact serialization of [`Duration`].
+pub mod duration {
+    use serde::{Deserializer, Serializer};
+    use std::time::Duration;
+
+    /// Serializes a duration as varint-encoded seconds and nanoseconds.
+    pub fn serialize<S>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        super::serialize_duration(value, serializer)
+    }
+
+    /// Deserializes a duration from varint-encoded seconds and nanoseconds.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        super::deserialize_duration(deserializer)
+    }
+}
+
+/// Compact serialization of [`SystemTime`](std::time::SystemTime).
+pub mod system_time {
+    use serde::{de, ser, Deserializer, Serializer};
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    /// Serializes a point in time as the compact duration since the Unix
+    /// epoch. Fails for points in time before the epoch.
+    pub fn serialize<S>(value: &SystemTime, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let since_epoch = value
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| ser::Error::custom("cannot serialize a time before the Unix epoch"))?;
+        super::serialize_duration(&since_epoch, serializer)
+    }
+
+    /// Deserializes a point in time from the compact duration since the Unix
+    /// epoch.
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<SystemTime, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let since_epoch = super::deserialize_duration(deserializer)?;
+        UNIX_EPOCH
+            .checked_add(since_epoch)
+            .ok_or_else(|| de::Error::custom("time is out of range for this platform"))
+    }
+}
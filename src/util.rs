@@ -1,4 +1,14 @@
 //! Library utilities.
+//!
+//! Lengths are encoded in one of two ways. The length of a `char` is always
+//! encoded with [`encode_len_small`], since it never exceeds four bytes. The
+//! lengths of strings, byte arrays, sequences and maps are encoded with
+//! whichever [`LengthEncoding`](crate::LengthEncoding) the encoder is
+//! configured with, through its [`LengthCodec`](crate::LengthCodec), such as
+//! [`encode_len_large`] or [`encode_int_varint`].
+
+use serde::de::Expected;
+use std::fmt::{self, Write};
 
 /// Encodes the size of a small section of bytes. This should only be used for
 /// values known to be less than 256 bytes.
@@ -14,30 +24,260 @@ pub const fn decode_len_small(len_encoded: u8) -> usize {
     len_encoded as usize
 }
 
+// the number of length bytes is written as a single byte
+const _: () = assert!(usize::BITS / 8 <= u8::MAX as u32);
+
+/// The maximum number of bytes in a length encoded with [`encode_len_large`].
+pub const MAX_LEN_LARGE_BYTES: usize = 1 + (usize::BITS / 8) as usize;
+
 /// Encodes the size of a large section of bytes. This can be used for values of
-/// any size.
-pub fn encode_len_large(mut len: usize) -> Vec<u8> {
-    let mut len_encoded = Vec::new();
+/// any size: the length is written as its big-endian bytes with leading zero
+/// bytes omitted, preceded by the number of bytes written.
+pub fn encode_len_large(len: usize) -> Vec<u8> {
+    let mut buf = [0; MAX_LEN_LARGE_BYTES];
+    let n = encode_len_large_into(len, &mut buf);
+    buf[..n].to_vec()
+}
+
+/// Encodes the size of a large section of bytes as [`encode_len_large`] does,
+/// into the given buffer, returning the number of bytes used.
+pub fn encode_len_large_into(len: usize, buf: &mut [u8; MAX_LEN_LARGE_BYTES]) -> usize {
+    let len_bytes = len.to_be_bytes();
+    let skipped = (len.leading_zeros() / 8) as usize;
+    let len_bytes = &len_bytes[skipped..];
+
+    buf[0] = encode_len_small(len_bytes.len());
+    buf[1..=len_bytes.len()].copy_from_slice(len_bytes);
+    1 + len_bytes.len()
+}
+
+/// Decodes the size of a large section of bytes, given the length bytes that
+/// follow the byte count written by [`encode_len_large`]. Redundant leading
+/// zero bytes are allowed. Returns `None` if the length does not fit in a
+/// `usize`.
+pub fn decode_len_large(len_encoded: &[u8]) -> Option<usize> {
+    len_encoded.iter().try_fold(0usize, |len, &byte| {
+        len.checked_mul(256)?.checked_add(byte as usize)
+    })
+}
+
+/// Checks whether the length bytes of a large section of bytes are in their
+/// minimal form, i.e. contain no redundant leading zero bytes. This is the
+/// only form produced by [`encode_len_large`].
+#[inline]
+pub fn is_canonical_len_large(len_encoded: &[u8]) -> bool {
+    len_encoded.first() != Some(&0)
+}
+
+/// The byte written after every struct and sequence in framed mode.
+pub const FRAME_SENTINEL: u8 = 0xa5;
+
+/// The most bytes reserved up front for a string or byte array read from a
+/// stream, and the most elements reserved for a collection whose length
+/// comes from its input. Such a length can't be checked against the input
+/// remaining, so anything beyond this is allocated only as it actually
+/// arrives.
+pub const MAX_READ_PREALLOC: usize = 8192;
+
+/// The number of elements encoded in zero bytes, such as units, that a
+/// sequence or map may hold unless the decoder is configured otherwise. Such
+/// elements don't advance through the input, so without a limit a hostile
+/// length would have the decoder produce them practically forever.
+pub const MAX_EMPTY_ELEMENTS: usize = 1 << 20;
+
+/// Computes the checksum written after a length prefix's bytes with length
+/// checksums enabled: the bytes XORed together.
+pub fn xor_checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |checksum, byte| checksum ^ byte)
+}
 
-    while len > 0 {
-        len_encoded.push(len as u8);
-        len >>= 8;
+/// The number of bytes in a header: a four-byte magic number, then a
+/// big-endian `u16` version.
+pub const HEADER_LEN: usize = 6;
+
+/// Encodes a header from its magic number and version.
+pub fn encode_header(magic: [u8; 4], version: u16) -> [u8; HEADER_LEN] {
+    let [version_high, version_low] = version.to_be_bytes();
+    let [a, b, c, d] = magic;
+    [a, b, c, d, version_high, version_low]
+}
+
+/// Decodes a header into its magic number and version.
+pub fn decode_header(header: [u8; HEADER_LEN]) -> ([u8; 4], u16) {
+    let [a, b, c, d, version_high, version_low] = header;
+    (
+        [a, b, c, d],
+        u16::from_be_bytes([version_high, version_low]),
+    )
+}
+
+/// The maximum number of bytes in a varint-encoded `usize`.
+pub const MAX_LEN_VARINT_BYTES: usize = (usize::BITS as usize).div_ceil(7);
+
+/// Decodes the size of a large section of bytes from an unsigned LEB128
+/// varint. Returns `None` if the length does not fit in a `usize`.
+pub fn decode_len_varint(len_encoded: &[u8]) -> Option<usize> {
+    let mut len = 0usize;
+
+    for (i, byte) in len_encoded.iter().enumerate() {
+        let shift = 7 * i as u32;
+        let group = (byte & 0x7f) as usize;
+
+        if shift >= usize::BITS || (group << shift) >> shift != group {
+            return None;
+        }
+
+        len |= group << shift;
     }
 
-    len_encoded.push(len_encoded.len() as u8);
-    len_encoded.reverse();
-    len_encoded
+    Some(len)
+}
+
+/// Checks whether a varint is in its minimal form, i.e. does not end in a
+/// redundant zero group. This is the only form produced by
+/// [`encode_int_varint`].
+#[inline]
+pub fn is_canonical_varint(encoded: &[u8]) -> bool {
+    encoded.len() <= 1 || encoded.last() != Some(&0)
 }
 
-/// Decodes the size of a large section of bytes. This can be used for values of
-/// any size.
-pub fn decode_len_large(len_encoded: &[u8]) -> usize {
+/// The maximum number of bytes in a varint-encoded integer, reached by
+/// `u128::MAX`.
+pub const MAX_INT_VARINT_BYTES: usize = (u128::BITS as usize).div_ceil(7);
+
+/// Encodes an integer as an unsigned LEB128 varint into the given buffer,
+/// returning the number of bytes used.
+pub fn encode_int_varint(mut value: u128, buf: &mut [u8; MAX_INT_VARINT_BYTES]) -> usize {
     let mut len = 0;
 
-    #[allow(clippy::needless_range_loop)]
-    for i in 0..len_encoded.len() {
-        len = (len << 8) + (len_encoded[i] as usize);
+    loop {
+        let group = (value & 0x7f) as u8;
+        value >>= 7;
+
+        if value == 0 {
+            buf[len] = group;
+            return len + 1;
+        }
+
+        buf[len] = group | 0x80;
+        len += 1;
+    }
+}
+
+/// Decodes an integer from an unsigned LEB128 varint. Returns `None` if the
+/// value does not fit in `bits` bits.
+pub fn decode_int_varint(encoded: &[u8], bits: u32) -> Option<u128> {
+    let mut value = 0u128;
+
+    for (i, byte) in encoded.iter().enumerate() {
+        let shift = 7 * i as u32;
+        let group = (byte & 0x7f) as u128;
+
+        if group != 0 && (shift >= bits || group.checked_shr(bits - shift).unwrap_or(0) != 0) {
+            return None;
+        }
+
+        if shift < bits {
+            value |= group << shift;
+        }
     }
 
-    len
+    Some(value)
+}
+
+/// Maps a signed integer to an unsigned one, interleaving positive and
+/// negative values so that those of small magnitude stay small.
+#[inline]
+pub const fn zigzag_encode(value: i128) -> u128 {
+    ((value << 1) ^ (value >> 127)) as u128
+}
+
+/// Reverses [`zigzag_encode`].
+#[inline]
+pub const fn zigzag_decode(value: u128) -> i128 {
+    ((value >> 1) as i128) ^ -((value & 1) as i128)
+}
+
+/// The width byte of a compactly encoded float holding an integer that fits in
+/// an `i8`.
+pub const COMPACT_FLOAT_I8: u8 = 0;
+
+/// The width byte of a compactly encoded float holding an `f32`.
+pub const COMPACT_FLOAT_F32: u8 = 1;
+
+/// The width byte of a compactly encoded float holding an `f64`.
+pub const COMPACT_FLOAT_F64: u8 = 2;
+
+/// Returns the value as an `i8` if converting it back gives the same bits.
+/// This excludes negative zero, which would come back positive.
+fn float_as_i8(value: f64) -> Option<i8> {
+    let int = value as i8;
+    (f64::from(int).to_bits() == value.to_bits()).then_some(int)
+}
+
+/// Returns the width byte of the narrowest compact form that holds the given
+/// `f32` exactly.
+pub fn compact_f32_width(value: f32) -> u8 {
+    match float_as_i8(value.into()) {
+        Some(_) => COMPACT_FLOAT_I8,
+        None => COMPACT_FLOAT_F32,
+    }
+}
+
+/// Returns the width byte of the narrowest compact form that holds the given
+/// `f64` exactly. NaNs are always kept at full width, since narrowing one
+/// isn't guaranteed to preserve its payload.
+pub fn compact_f64_width(value: f64) -> u8 {
+    if float_as_i8(value).is_some() {
+        COMPACT_FLOAT_I8
+    } else if !value.is_nan() && f64::from(value as f32).to_bits() == value.to_bits() {
+        COMPACT_FLOAT_F32
+    } else {
+        COMPACT_FLOAT_F64
+    }
+}
+
+/// Returns whether the description a visitor gives of itself starts with
+/// `prefix`, without allocating it.
+pub fn expected_starts_with(exp: &dyn Expected, prefix: &str) -> bool {
+    let mut matcher = ExpectedMatcher {
+        remaining: prefix,
+        exact: false,
+    };
+    write!(matcher, "{exp}").is_ok() && matcher.remaining.is_empty()
+}
+
+/// Returns whether the description a visitor gives of itself is exactly
+/// `text`, without allocating it.
+pub fn expected_is(exp: &dyn Expected, text: &str) -> bool {
+    let mut matcher = ExpectedMatcher {
+        remaining: text,
+        exact: true,
+    };
+    write!(matcher, "{exp}").is_ok() && matcher.remaining.is_empty()
+}
+
+/// Compares a visitor's description against some text as it is written,
+/// failing at the first difference.
+struct ExpectedMatcher<'a> {
+    /// The text not yet matched.
+    remaining: &'a str,
+    /// Whether the description must end with the text, rather than only
+    /// start with it.
+    exact: bool,
+}
+
+impl Write for ExpectedMatcher<'_> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let n = s.len().min(self.remaining.len());
+
+        if s.as_bytes()[..n] != self.remaining.as_bytes()[..n] || (self.exact && n < s.len()) {
+            return Err(fmt::Error);
+        }
+
+        // `n` is a character boundary of `remaining`, since its first `n`
+        // bytes are either all of `s` or all of `remaining`
+        self.remaining = &self.remaining[n..];
+        Ok(())
+    }
 }
@@ -0,0 +1,35 @@
+//! Fixed-layout serialization of [`Uuid`].
+//!
+//! A `Uuid` is encoded as exactly its 16 raw bytes, in the order returned by
+//! [`Uuid::as_bytes`], with no length prefix. This module is only available
+//! with the `uuid` feature enabled.
+//!
+//! ```
+//! use serde::{Deserialize, Serialize};
+//! use uuid::Uuid;
+//!
+//! #[derive(Serialize, Deserialize)]
+//! struct Record {
+//!     #[serde(with = "unbin::uuid")]
+//!     id: Uuid,
+//! }
+//! ```
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+/// Serializes a UUID as its 16 raw bytes.
+pub fn serialize<S>(value: &Uuid, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    value.as_bytes().serialize(serializer)
+}
+
+/// Deserializes a UUID from its 16 raw bytes.
+pub fn deserialize<'de, D>(deserializer: D) -> Result<Uuid, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    <[u8; 16]>::deserialize(deserializer).map(Uuid::from_bytes)
+}
@@ -1,17 +1,37 @@
 //! I/O writing.
 
 use crate::Result;
+use std::hash::Hasher;
 use std::io;
+use std::ops::{Deref, DerefMut};
+use std::sync::{Mutex, MutexGuard, PoisonError};
 
 /// Trait to allow writing bytes. Similar to [`std::io::Write`], but also
 /// supports writing to byte arrays.
+///
+/// Every [`std::io::Write`] type implements this trait, which covers
+/// `Vec<u8>`, `&mut [u8]`, and mutable references to any other `io::Write`
+/// type, however deeply nested. Writing to a `Vec<u8>` always appends the
+/// entire buffer.
 pub trait Write {
     /// Writes the entire buffer to the writer.
     fn write_all(&mut self, buf: &[u8]) -> Result<()>;
 
+    /// Writes a single byte. Writers can override this to avoid going through
+    /// a one-byte slice.
+    fn write_u8(&mut self, byte: u8) -> Result<()> {
+        self.write_all(&[byte])
+    }
+
     /// Flushes this output stream, ensuring that all intermediately buffered
     /// contents reach their destination.
     fn flush(&mut self) -> Result<()>;
+
+    /// Returns the number of bytes written so far, if the writer keeps track.
+    /// Defaults to `None`, as for every [`std::io::Write`] type.
+    fn bytes_written(&self) -> Option<usize> {
+        None
+    }
 }
 
 impl<W> Write for W
@@ -28,7 +48,7 @@ where
 }
 
 /// A wrapper around a [`Write`]-able byte array.
-#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
 pub struct BytesWriter {
     /// The byte buffer.
     bytes: Vec<u8>,
@@ -40,6 +60,14 @@ impl BytesWriter {
         Self { bytes: Vec::new() }
     }
 
+    /// Constructs a new writer with an empty byte array that can hold at least
+    /// `capacity` bytes without reallocating.
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            bytes: Vec::with_capacity(capacity),
+        }
+    }
+
     /// Returns the full buffer as a slice.
     #[allow(dead_code)]
     pub fn as_slice(&self) -> &[u8] {
@@ -56,16 +84,378 @@ impl BytesWriter {
     pub fn into_inner(self) -> Vec<u8> {
         self.bytes
     }
+
+    /// Returns the number of bytes the writer can hold without reallocating.
+    pub fn capacity(&self) -> usize {
+        self.bytes.capacity()
+    }
+
+    /// Empties the writer, keeping its allocated capacity so that it can be
+    /// reused.
+    pub fn reset(&mut self) {
+        self.bytes.clear();
+    }
 }
 
 impl Write for BytesWriter {
     fn write_all(&mut self, buf: &[u8]) -> Result<()> {
-        io::Write::write(&mut self.bytes, buf)?;
+        self.bytes.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn write_u8(&mut self, byte: u8) -> Result<()> {
+        self.bytes.push(byte);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        Some(self.bytes.len())
+    }
+}
+
+/// Allows passing `&mut BytesWriter` to anything expecting an
+/// [`std::io::Write`]. The impl is on the mutable reference rather than on
+/// `BytesWriter` itself, since every `io::Write` type already implements this
+/// crate's [`Write`] trait, and `BytesWriter` has its own impl of that.
+impl io::Write for &mut BytesWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.bytes.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn write_all(&mut self, buf: &[u8]) -> io::Result<()> {
+        self.bytes.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A pool of [`BytesWriter`]s, so that their allocations can be recycled
+/// rather than made afresh for every value serialized. The pool can be shared
+/// between threads.
+#[derive(Debug, Default)]
+pub struct WriterPool {
+    /// The writers not currently checked out.
+    writers: Mutex<Vec<BytesWriter>>,
+}
+
+impl WriterPool {
+    /// Constructs a new, empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Checks a writer out of the pool, or creates one if the pool is empty.
+    /// The writer is empty, and returns to the pool when the guard is
+    /// dropped.
+    pub fn checkout(&self) -> PooledWriter<'_> {
+        let mut writer = self.lock().pop().unwrap_or_default();
+        writer.reset();
+
+        PooledWriter {
+            writer: Some(writer),
+            pool: self,
+        }
+    }
+
+    /// Returns the number of writers waiting in the pool.
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    /// Locks the list of writers. A panic while the lock was held can't
+    /// have left the list inconsistent, so poisoning is ignored.
+    fn lock(&self) -> MutexGuard<'_, Vec<BytesWriter>> {
+        self.writers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// A [`BytesWriter`] checked out of a [`WriterPool`], which returns to the pool
+/// when dropped.
+#[derive(Debug)]
+pub struct PooledWriter<'p> {
+    /// The writer, only taken when returning it to the pool.
+    writer: Option<BytesWriter>,
+    /// The pool the writer came from.
+    pool: &'p WriterPool,
+}
+
+impl Deref for PooledWriter<'_> {
+    type Target = BytesWriter;
+
+    fn deref(&self) -> &Self::Target {
+        self.writer.as_ref().expect("writer is only taken on drop")
+    }
+}
+
+impl DerefMut for PooledWriter<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.writer.as_mut().expect("writer is only taken on drop")
+    }
+}
+
+impl Drop for PooledWriter<'_> {
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            self.pool.lock().push(writer);
+        }
+    }
+}
+
+/// A [`Write`] wrapper that buffers all writes in memory and only forwards
+/// them to the underlying writer once committed. Dropping the writer without
+/// committing discards everything written to it.
+#[derive(Debug, PartialEq, Eq, Hash)]
+pub struct TransactionalWriter<'w, W>
+where
+    W: Write,
+{
+    /// The underlying writer.
+    writer: &'w mut W,
+    /// The uncommitted bytes.
+    bytes: Vec<u8>,
+}
+
+impl<'w, W> TransactionalWriter<'w, W>
+where
+    W: Write,
+{
+    /// Constructs a new transactional writer around the given writer.
+    pub fn new(writer: &'w mut W) -> Self {
+        Self {
+            writer,
+            bytes: Vec::new(),
+        }
+    }
+
+    /// Returns the uncommitted bytes as a slice.
+    pub fn pending(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Writes all buffered bytes to the underlying writer in a single call.
+    pub fn commit(self) -> Result<()> {
+        self.writer.write_all(&self.bytes)
+    }
+}
+
+impl<'w, W> Write for TransactionalWriter<'w, W>
+where
+    W: Write,
+{
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.bytes.extend_from_slice(buf);
+        Ok(())
+    }
+
+    fn write_u8(&mut self, byte: u8) -> Result<()> {
+        self.bytes.push(byte);
         Ok(())
     }
 
     fn flush(&mut self) -> Result<()> {
-        io::Write::flush(&mut self.bytes)?;
+        // nothing reaches the underlying writer until the transaction is
+        // committed
         Ok(())
     }
 }
+
+/// A [`Write`] wrapper that feeds every byte written to it into a [`Hasher`]
+/// before forwarding it to the underlying writer, so a value can be serialized
+/// and hashed in a single pass.
+///
+/// Bytes are passed to [`Hasher::write`] in the chunks the encoder writes
+/// them, so the resulting hash matches hashing the serialized bytes all at
+/// once for any hasher whose output doesn't depend on how its input is split,
+/// such as [`DefaultHasher`](std::hash::DefaultHasher).
+#[derive(Debug, Clone, Default)]
+pub struct HashingWriter<H, W>
+where
+    H: Hasher,
+    W: Write,
+{
+    /// The hasher.
+    hasher: H,
+    /// The underlying writer.
+    writer: W,
+}
+
+impl<H, W> HashingWriter<H, W>
+where
+    H: Hasher,
+    W: Write,
+{
+    /// Constructs a new hashing writer around the given hasher and writer.
+    pub fn new(hasher: H, writer: W) -> Self {
+        Self { hasher, writer }
+    }
+
+    /// Returns the hash of all bytes written so far.
+    pub fn finish(&self) -> u64 {
+        self.hasher.finish()
+    }
+
+    /// Returns a reference to the underlying writer.
+    pub fn get_ref(&self) -> &W {
+        &self.writer
+    }
+
+    /// Unwraps and returns the hasher and the underlying writer.
+    pub fn into_inner(self) -> (H, W) {
+        (self.hasher, self.writer)
+    }
+}
+
+impl<H, W> Write for HashingWriter<H, W>
+where
+    H: Hasher,
+    W: Write,
+{
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.hasher.write(buf);
+        self.writer.write_all(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A [`Write`] wrapper that forwards every byte written to it to two
+/// writers, so a value can be serialized once and sent to both, such as a file
+/// and an in-memory buffer.
+///
+/// Each write goes to the first writer before the second. If the first fails,
+/// the second is not written to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct TeeWriter<A, B>
+where
+    A: Write,
+    B: Write,
+{
+    /// The first underlying writer.
+    first: A,
+    /// The second underlying writer.
+    second: B,
+}
+
+impl<A, B> TeeWriter<A, B>
+where
+    A: Write,
+    B: Write,
+{
+    /// Constructs a new tee writer around the given writers.
+    pub fn new(first: A, second: B) -> Self {
+        Self { first, second }
+    }
+
+    /// Returns references to the underlying writers.
+    pub fn get_ref(&self) -> (&A, &B) {
+        (&self.first, &self.second)
+    }
+
+    /// Unwraps and returns the underlying writers.
+    pub fn into_inner(self) -> (A, B) {
+        (self.first, self.second)
+    }
+}
+
+impl<A, B> Write for TeeWriter<A, B>
+where
+    A: Write,
+    B: Write,
+{
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.first.write_all(buf)?;
+        self.second.write_all(buf)
+    }
+
+    fn write_u8(&mut self, byte: u8) -> Result<()> {
+        self.first.write_u8(byte)?;
+        self.second.write_u8(byte)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.first.flush()?;
+        self.second.flush()
+    }
+}
+
+/// A [`Write`]r that passes every chunk of bytes written to it to a callback.
+pub struct CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    /// The callback receiving each chunk.
+    callback: F,
+}
+
+impl<F> CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    /// Constructs a new writer around the given callback.
+    pub fn new(callback: F) -> Self {
+        Self { callback }
+    }
+}
+
+impl<F> Write for CallbackWriter<F>
+where
+    F: FnMut(&[u8]) -> Result<()>,
+{
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        (self.callback)(buf)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// A [`Write`]r that discards everything written to it, only counting the
+/// number of bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SizeCounter {
+    /// The number of bytes written so far.
+    size: usize,
+}
+
+impl SizeCounter {
+    /// Constructs a new counter at zero.
+    pub fn new() -> Self {
+        Self { size: 0 }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+}
+
+impl Write for SizeCounter {
+    fn write_all(&mut self, buf: &[u8]) -> Result<()> {
+        self.size += buf.len();
+        Ok(())
+    }
+
+    fn write_u8(&mut self, _byte: u8) -> Result<()> {
+        self.size += 1;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn bytes_written(&self) -> Option<usize> {
+        Some(self.size)
+    }
+}
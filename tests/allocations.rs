@@ -0,0 +1,265 @@
+//! Allocation-counting tests. These live in their own test binary so that the
+//! counting global allocator doesn't affect the rest of the test suite.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+
+/// A global allocator that counts allocations made by the current thread, and
+/// tracks the largest of them.
+struct CountingAllocator;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+    static LARGEST_ALLOCATION: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Records a single allocation of `size` bytes on the current thread.
+fn record_allocation(size: usize) {
+    let _ = ALLOCATIONS.try_with(|allocations| allocations.set(allocations.get() + 1));
+    let _ = LARGEST_ALLOCATION.try_with(|largest| largest.set(largest.get().max(size)));
+}
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        record_allocation(layout.size());
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        record_allocation(new_size);
+        unsafe { System.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+/// Runs `f`, returning its result and the number of allocations it made.
+fn count_allocations<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = ALLOCATIONS.with(Cell::get);
+    let value = f();
+    let after = ALLOCATIONS.with(Cell::get);
+    (value, after - before)
+}
+
+/// Runs `f`, returning its result and the size in bytes of the largest single
+/// allocation it made.
+fn largest_allocation<T>(f: impl FnOnce() -> T) -> (T, usize) {
+    let before = LARGEST_ALLOCATION.with(|largest| largest.replace(0));
+    let value = f();
+    let largest = LARGEST_ALLOCATION.with(|largest| largest.replace(before));
+    (value, largest)
+}
+
+#[test]
+fn test_small_collection_lengths_do_not_allocate() {
+    let values = (0..1000u32)
+        .map(|i| vec![i as u8, (i >> 8) as u8, 3])
+        .collect::<Vec<_>>();
+    let mut writer = Vec::with_capacity(values.len() * 5);
+
+    let ((), allocations) = count_allocations(|| {
+        for value in &values {
+            unbin::serialize_into(value, &mut writer).unwrap();
+        }
+    });
+
+    assert_eq!(allocations, 0);
+    assert_eq!(writer.len(), values.len() * 5);
+    assert_eq!(writer[..5], [1, 3, 0, 0, 3]);
+}
+
+#[test]
+fn test_static_custom_errors_do_not_allocate() {
+    let (err, allocations) = count_allocations(|| unbin::Error::Custom("static message".into()));
+
+    assert_eq!(allocations, 0);
+    assert_eq!(
+        err.to_string().len(),
+        "serialization error: static message".len()
+    );
+}
+
+#[test]
+fn test_hostile_collection_lengths_do_not_preallocate() {
+    use std::collections::HashMap;
+
+    // a length prefix claiming `u64::MAX` entries, followed by a single byte
+    let bytes = [8, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0];
+
+    let (result, largest) = largest_allocation(|| unbin::deserialize::<HashMap<u64, u64>>(&bytes));
+    assert!(matches!(
+        result,
+        Err(unbin::Error::TruncatedValue {
+            ty: unbin::ValueType::U64
+        })
+    ));
+    assert!(largest < 1024, "allocated {largest} bytes");
+
+    let (result, largest) = largest_allocation(|| unbin::deserialize::<Vec<u64>>(&bytes));
+    assert!(matches!(
+        result,
+        Err(unbin::Error::TruncatedValue {
+            ty: unbin::ValueType::U64
+        })
+    ));
+    assert!(largest < 1024, "allocated {largest} bytes");
+}
+
+#[test]
+fn test_file_decoding_reuses_length_buffer() {
+    use serde::{Deserialize, Serialize};
+    use std::io::{Seek, SeekFrom, Write};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Contact {
+        first_name: String,
+        last_name: String,
+        email: String,
+        phone: String,
+        street: String,
+        city: String,
+        country: String,
+        notes: String,
+    }
+
+    let contact = Contact {
+        first_name: "Ada".to_owned(),
+        last_name: "Lovelace".to_owned(),
+        email: "ada@example.com".to_owned(),
+        phone: "555-0100".to_owned(),
+        street: "12 St James's Square".to_owned(),
+        city: "London".to_owned(),
+        country: "England".to_owned(),
+        notes: "first programmer".to_owned(),
+    };
+    let mut file = tempfile::tempfile().unwrap();
+    file.write_all(&unbin::serialize(&contact).unwrap())
+        .unwrap();
+    file.seek(SeekFrom::Start(0)).unwrap();
+
+    let (decoded, allocations) = count_allocations(|| {
+        let mut decoder = unbin::Decoder::new(&mut file);
+        Contact::deserialize(&mut decoder).unwrap()
+    });
+
+    // one allocation for each string, plus one for the length buffer, which
+    // is then reused for every later length prefix
+    assert_eq!(decoded, contact);
+    assert_eq!(allocations, 8 + 1);
+}
+
+#[test]
+fn test_fixed_size_decoding_does_not_allocate() {
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Mode {
+        Idle,
+        Running(u16),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Reading {
+        id: u32,
+        flags: [bool; 4],
+        position: (f32, f32, f64),
+        delta: i128,
+        unit: (),
+        symbol: char,
+        calibration: Option<u8>,
+        mode: Mode,
+    }
+
+    let reading = Reading {
+        id: 7,
+        flags: [true, false, true, true],
+        position: (1.5, -2.25, 1e100),
+        delta: -1,
+        unit: (),
+        symbol: 'µ',
+        calibration: Some(3),
+        mode: Mode::Running(1200),
+    };
+    let serialized = unbin::serialize(&reading).unwrap();
+
+    let (decoded, allocations) = count_allocations(|| {
+        let mut reader = unbin::BytesReader::new(&serialized);
+        let mut decoder = unbin::Decoder::new(&mut reader);
+        Reading::deserialize(&mut decoder).unwrap()
+    });
+
+    assert_eq!(allocations, 0);
+    assert_eq!(decoded, reading);
+
+    let (decoded, allocations) = count_allocations(|| unbin::deserialize::<Reading>(&serialized));
+    assert_eq!(allocations, 0);
+    assert_eq!(decoded.unwrap(), reading);
+}
+
+#[test]
+fn test_length_checksums_catch_corruption_before_allocation() {
+    let options = unbin::builder()
+        .length_checksums(true)
+        .trust_length_hints(true)
+        .build();
+    let mut bytes = unbin::serialize_with(&vec![0u8; 65_536], &options).unwrap();
+    assert_eq!(bytes[..5], [3, 0x01, 0x00, 0x00, 3 ^ 0x01]);
+
+    // flipping the top bit of the length claims over eight million elements,
+    // which a trusted length hint would allocate up front
+    bytes[1] ^= 0x80;
+
+    let (result, largest) =
+        largest_allocation(|| unbin::deserialize_with::<Vec<u8>>(&bytes, &options));
+    assert!(matches!(
+        result,
+        Err(unbin::Error::LengthChecksumMismatch {
+            expected: 0x82,
+            found: 0x02
+        })
+    ));
+    assert!(largest < 1024, "allocated {largest} bytes");
+}
+
+#[test]
+fn test_hostile_string_lengths_do_not_preallocate_from_streams() {
+    use serde::Deserialize;
+
+    // a length prefix claiming a terabyte-long string, followed by three bytes
+    let bytes = [6, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, b'a', b'b', b'c'];
+
+    let (result, largest) = largest_allocation(|| {
+        let mut reader = std::io::Cursor::new(&bytes);
+        let mut decoder = unbin::Decoder::new(&mut reader);
+        String::deserialize(&mut decoder)
+    });
+    assert!(matches!(result, Err(unbin::Error::UnexpectedEof)));
+    assert!(largest <= 16384, "allocated {largest} bytes");
+
+    // lengths that are honest are still read in full
+    let value = "7".repeat(100_000);
+    let serialized = unbin::serialize(&value).unwrap();
+    let mut reader = std::io::Cursor::new(&serialized);
+    let mut decoder = unbin::Decoder::new(&mut reader);
+    assert_eq!(String::deserialize(&mut decoder).unwrap(), value);
+}
+
+#[test]
+fn test_sparse_vec_bitmaps_do_not_preallocate() {
+    // a bitmap promising 800,000 elements, the first of them present, but
+    // none of the values that should follow it
+    let mut elements = vec![None; 800_000];
+    elements[0] = Some(1u64);
+    let serialized = unbin::serialize(&unbin::SparseVec(elements)).unwrap();
+    let bytes = &serialized[..serialized.len() - 8];
+
+    let (result, largest) =
+        largest_allocation(|| unbin::deserialize::<unbin::SparseVec<u64>>(bytes));
+    assert!(result.is_err());
+    assert!(largest < 1_000_000, "allocated {largest} bytes");
+}